@@ -13,6 +13,8 @@ pub struct Model {
     pub resp_time: Option<i32>,
     pub healthy: bool,
     pub response_code: Option<i32>,
+    /// Check was blocked by the host, not counted as downtime
+    pub blocked: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -1,7 +1,7 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
 use sea_orm::entity::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Default, Debug, DeriveEntity)]
 pub struct Entity;
@@ -27,9 +27,27 @@ pub struct Model {
     pub connectivity: Option<Connectivity>,
     /// Last time the url and enabled were updated, *not* the rss
     pub updated: i64,
+    /// Whether the host served a captcha/JS challenge during the last list update
+    pub captcha: bool,
+    /// Version URL pinned by the operator, not touched by list updates
+    pub expected_version_url: Option<String>,
+    /// Hidden from the public list by an admin, independent of the wiki
+    pub force_disabled: bool,
+    /// First time this host was found in the instance list
+    pub first_seen: i64,
+    /// Operator opted in to show a category of the last error publicly
+    pub public_last_error: bool,
+    /// Accepted SHA-256 certificate fingerprints pinned by the operator, comma separated lowercase hex
+    pub cert_sha256: Option<String>,
+    /// Whether the RSS feed is a well-formed feed, only checked in strict RSS mode
+    pub rss_valid: Option<bool>,
+    /// Recommended by an admin, listed before all other hosts
+    pub featured: bool,
+    /// Set by an admin for hosts without an about page, a missing version doesn't cost points
+    pub version_neutral: bool,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "i32", db_type = "Integer")]
 pub enum Connectivity {
     #[sea_orm(num_value = 0)]
@@ -52,6 +70,15 @@ pub enum Column {
     Connectivity,
     Rss,
     Updated,
+    Captcha,
+    ExpectedVersionUrl,
+    ForceDisabled,
+    FirstSeen,
+    PublicLastError,
+    CertSha256,
+    RssValid,
+    Featured,
+    VersionNeutral,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -86,6 +113,15 @@ impl ColumnTrait for Column {
             Self::Rss => ColumnType::Integer.def(),
             Self::Updated => ColumnType::Integer.def(),
             Self::Connectivity => ColumnType::Integer.def().null(),
+            Self::Captcha => ColumnType::Integer.def(),
+            Self::ExpectedVersionUrl => ColumnType::String(None).def().null(),
+            Self::ForceDisabled => ColumnType::Integer.def(),
+            Self::FirstSeen => ColumnType::Integer.def(),
+            Self::PublicLastError => ColumnType::Integer.def(),
+            Self::CertSha256 => ColumnType::String(None).def().null(),
+            Self::RssValid => ColumnType::Integer.def().null(),
+            Self::Featured => ColumnType::Integer.def(),
+            Self::VersionNeutral => ColumnType::Integer.def(),
         }
     }
 
@@ -3,8 +3,13 @@
 pub mod prelude;
 
 pub mod check_errors;
+pub mod connectivity_check;
 pub mod health_check;
 pub mod host;
+pub mod host_meta;
+pub mod points_history;
+pub mod protocol_probe;
+pub mod rss_check;
 
 // has to be re-added on entity regeneration
 pub mod state;
@@ -1,5 +1,10 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
 pub use super::check_errors::Entity as CheckErrors;
+pub use super::connectivity_check::Entity as ConnectivityCheck;
 pub use super::health_check::Entity as HealthCheck;
 pub use super::host::Entity as Host;
+pub use super::host_meta::Entity as HostMeta;
+pub use super::points_history::Entity as PointsHistory;
+pub use super::protocol_probe::Entity as ProtocolProbe;
+pub use super::rss_check::Entity as RssCheck;
@@ -2,11 +2,12 @@
 //! Global state and structures.
 //! For build process decoupling
 
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 use chrono::Utc;
 use sea_orm::prelude::DateTimeUtc;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::host::Connectivity;
 
@@ -20,6 +21,82 @@ pub type AppState = Arc<InnerState>;
 
 pub struct InnerState {
     pub cache: RwLock<CacheData>,
+    /// Result of the last instance list parsing
+    pub list_debug: RwLock<ListParseDebug>,
+    /// Latest health check results of remote probe peers, by domain
+    pub probe_reports: RwLock<HashMap<String, Vec<PeerProbe>>>,
+    /// Connection usage of the scanner instance checks, by host
+    pub connection_stats: RwLock<HashMap<String, ConnectionStats>>,
+    /// Last runs of the scanner tasks, for debugging the scheduling
+    pub schedule: RwLock<ScannerSchedule>,
+}
+
+/// Last runs of the scanner tasks, published before every sleep of the scanner
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ScannerSchedule {
+    pub last_uptime_check: Option<DateTimeUtc>,
+    pub last_list_fetch: Option<DateTimeUtc>,
+    /// Last version-only re-check or list update
+    pub last_version_check: Option<DateTimeUtc>,
+    /// Time the scanner sleeps until, `None` if it didn't sleep yet
+    pub next_wakeup: Option<DateTimeUtc>,
+}
+
+/// Requests and newly opened connections of a host, to measure the connection reuse
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ConnectionStats {
+    pub requests: u64,
+    /// New connections, counted by their DNS resolution
+    pub connections: u64,
+}
+
+/// Health check result reported by a remote probe peer
+#[derive(Debug, Clone)]
+pub struct PeerProbe {
+    /// Name of the reporting peer
+    pub peer: String,
+    pub healthy: bool,
+    /// Creation time of the report, at most the time it was received
+    pub time: DateTimeUtc,
+}
+
+impl InnerState {
+    /// Store a peer result for *domain*, replacing an older result of the same peer
+    ///
+    /// Results not newer than the stored one are ignored, so replayed reports can't extend it.
+    pub fn insert_probe(&self, domain: String, probe: PeerProbe) {
+        let mut reports = self.probe_reports.write().unwrap();
+        let probes = reports.entry(domain).or_default();
+        if probes
+            .iter()
+            .any(|v| v.peer == probe.peer && v.time >= probe.time)
+        {
+            return;
+        }
+        probes.retain(|v| v.peer != probe.peer);
+        probes.push(probe);
+    }
+
+    /// Count a request to *host* for the connection stats
+    pub fn count_request(&self, host: &str) {
+        let mut stats = self.connection_stats.write().unwrap();
+        stats.entry(host.to_owned()).or_default().requests += 1;
+    }
+
+    /// Count a newly opened connection to *host* for the connection stats
+    pub fn count_connection(&self, host: &str) {
+        let mut stats = self.connection_stats.write().unwrap();
+        stats.entry(host.to_owned()).or_default().connections += 1;
+    }
+
+    /// Whether any peer reported *domain* as healthy since *since*
+    pub fn is_healthy_from_peer(&self, domain: &str, since: DateTimeUtc) -> bool {
+        self.probe_reports
+            .read()
+            .unwrap()
+            .get(domain)
+            .is_some_and(|probes| probes.iter().any(|v| v.healthy && v.time >= since))
+    }
 }
 
 pub fn new() -> AppState {
@@ -29,17 +106,52 @@ pub fn new() -> AppState {
             last_update: Utc::now(),
             latest_commit: String::new(),
         }),
+        list_debug: RwLock::new(ListParseDebug::default()),
+        probe_reports: RwLock::new(HashMap::new()),
+        connection_stats: RwLock::new(HashMap::new()),
+        schedule: RwLock::new(ScannerSchedule::default()),
     })
 }
 
+/// Raw result of the last instance list parsing, for debugging layout changes
+#[derive(Debug, Default, Serialize)]
+pub struct ListParseDebug {
+    /// Time of the parsing, None if the list wasn't parsed yet
+    pub time: Option<DateTimeUtc>,
+    /// Error that aborted the parsing
+    pub error: Option<String>,
+    /// Successfully parsed instances
+    pub instances: Vec<ParsedInstance>,
+    /// HTML of all table rows that failed parsing
+    pub failed_rows: Vec<String>,
+}
+
+/// Instance as parsed from the list
 #[derive(Debug, Serialize)]
+pub struct ParsedInstance {
+    pub domain: String,
+    pub url: String,
+    pub online: bool,
+    pub ssl_provider: String,
+    pub country: String,
+}
+
+/// Public metadata of an instance, provided by its operator
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InstanceMeta {
+    pub contact_email: Option<String>,
+    pub tor_mirror: Option<String>,
+    pub donation_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CacheData {
     pub hosts: Vec<CacheHost>,
     pub last_update: DateTimeUtc,
     pub latest_commit: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CacheHost {
     pub url: String,
     pub domain: String,
@@ -49,23 +161,74 @@ pub struct CacheHost {
     pub ping_max: Option<i32>,
     pub ping_min: Option<i32>,
     pub ping_avg: Option<i32>,
+    /// Median response time over the ping range
+    pub ping_p50: Option<i32>,
+    /// 95th percentile response time over the ping range
+    pub ping_p95: Option<i32>,
     pub version: Option<String>,
     pub version_url: Option<String>,
     pub healthy: bool,
     pub last_healthy: Option<DateTimeUtc>,
-    /// Whether the source is from the normal upstream repo
-    pub is_upstream: bool,
-    /// Whether the source is from the latest upstream commit
-    pub is_latest_version: bool,
+    /// Time of the latest health check, healthy or not
+    pub last_checked: Option<DateTimeUtc>,
+    /// State of the reported version compared to upstream
+    pub version_state: VersionState,
     /// Whether this host is known to be bad (ip blocking)
     pub is_bad_host: bool,
+    /// Reason for the bad host flag
+    pub bad_host_reason: Option<String>,
+    /// Expiry of the bad host flag
+    pub bad_host_until: Option<DateTimeUtc>,
+    /// Whether this host serves a captcha/JS challenge, making it unusable without JS
+    pub is_captcha_gated: bool,
+    /// Whether this host is only healthy according to remote probe peers
+    #[serde(default)]
+    pub is_healthy_remote: bool,
+    /// Whether the latest check was blocked by the host, it may still be up for other users
+    #[serde(default)]
+    pub is_blocked: bool,
+    /// Category of the last error, if the operator opted in to show it publicly
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Whether all accounts of this host have been rate limited for multiple checks
+    pub is_account_pool_exhausted: bool,
+    /// Whether the host is up, but served an error page like "User not found" in its latest check
+    #[serde(default)]
+    pub is_content_unavailable: bool,
     /// Country from the wiki
     pub country: String,
     /// Last health checks time formatted, healthy
     pub recent_checks: Vec<(String, bool)>,
     /// Percentage of healthy checks since first seen
     pub healthy_percentage_overall: u8,
+    /// Too few health checks for ranking, listed after all ranked hosts
+    #[serde(default)]
+    pub insufficient_data: bool,
+    /// Recommended by an admin, listed before all other hosts
+    #[serde(default)]
+    pub is_featured: bool,
+    /// Whether the RSS feed is a well-formed feed, only checked in strict RSS mode
+    #[serde(default)]
+    pub rss_valid: Option<bool>,
+    /// Public metadata provided by the operator
+    #[serde(default)]
+    pub meta: InstanceMeta,
     pub connectivity: Option<Connectivity>,
     /// Internal: show last-seen information
+    #[serde(skip)]
     pub __show_last_seen: bool,
 }
+
+/// Version of a host compared to the upstream repository
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionState {
+    /// Latest upstream commit
+    Latest,
+    /// Upstream repo, but not the latest commit
+    Outdated,
+    /// Source is not the upstream repo
+    Foreign,
+    /// No version reported
+    Missing,
+}
@@ -1,7 +1,9 @@
 // SPDX-License-Identifier: AGPL-3.0-only
-use std::{sync::Arc, time::Duration};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, NaiveDate, Utc};
 pub type ScannerConfig = Arc<Config>;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     /// time until next instance list fetch
     pub list_fetch_interval: Duration,
@@ -9,6 +11,8 @@ pub struct Config {
     pub instance_check_interval: Duration,
     /// instances list URL
     pub instance_list_url: String,
+    /// Bearer token for fetching the instance list, never sent to instances
+    pub instance_list_auth: Option<String>,
     /// profile path for health check
     pub profile_path: String,
     /// rss path for health check
@@ -19,8 +23,18 @@ pub struct Config {
     pub profile_name: String,
     /// Expected minimum of timeline posts for a valid profile health check
     pub profile_posts_min: usize,
+    /// Consecutive checks with fewer than *profile_posts_min* posts still counted as healthy
+    pub profile_posts_tolerance: usize,
+    /// Additional profiles, checked round-robin together with *profile_path*
+    pub profile_rotation: Vec<ProfileProbe>,
+    /// User-Agents rotated per health check request, uses the default one if empty
+    pub probe_user_agents: Vec<String>,
+    /// Body markers of error pages, in addition to nitter's own error panel
+    pub content_unavailable_markers: Vec<String>,
     /// Expected string for a valid RSS health check
     pub rss_content: String,
+    /// Also parse the RSS feed as XML, stored separately from the regex check
+    pub rss_strict: bool,
     /// List of additional hosts to include during health checks
     pub additional_hosts: Vec<String>,
     /// Country to use for additional hosts
@@ -29,20 +43,74 @@ pub struct Config {
     pub website_url: String,
     /// Duration to average the ping/response times over
     pub ping_range: chrono::Duration,
+    /// Percentage of the slowest responses to drop before averaging, 0 to keep all
+    pub ping_avg_trim_percent: u8,
+    /// Drop responses above this many standard deviations from the mean before averaging
+    pub ping_avg_max_stddev: Option<f64>,
     /// don't emit errors for hosts which are already listed as down
     pub auto_mute: bool,
     /// Git URL for source fetching
     pub source_git_url: String,
     /// Git branch to fetch the current commit from
     pub source_git_branch: String,
-    /// Hosts known to be bad (ip block), domain per entry
-    pub bad_hosts: Vec<String>,
+    /// Hosts known to be bad (ip block)
+    pub bad_hosts: Vec<BadHost>,
     /// Interval to run cleanup operations in, to remove old data
     pub cleanup_interval: Duration,
     /// Amount of latest errors to keep per instance/host
     pub error_retention_per_host: usize,
+    /// Age after which points history entries are removed
+    pub points_retention: Duration,
     /// Path for connectivity checks
     pub connectivity_path: String,
+    /// Send a HEAD request before the profile check, skipping the full fetch for unreachable hosts
+    pub health_precheck: bool,
+    /// Verify the Content-Type of profile and RSS responses before parsing
+    pub check_content_type: bool,
+    /// Perform all fetching and parsing, but don't write anything to the database
+    pub dry_run: bool,
+    /// Skip all instances not reachable via https
+    pub require_https: bool,
+    /// Use conditional requests for the instance list, skipping updates if unchanged.
+    ///
+    /// Performs a full update at least once per this duration.
+    pub list_conditional_max_age: Option<Duration>,
+    /// Re-check only the versions of all hosts in this interval, between full list updates
+    pub version_check_interval: Option<Duration>,
+    /// Consecutive rate limited checks after which a host's account pool counts as exhausted
+    pub rate_limit_threshold: usize,
+    /// Minimum amount of counted health checks before a host is ranked by its points
+    pub ranking_min_checks: u64,
+    /// Consecutive failed checks before a host is shown unhealthy, points always use every check
+    pub unhealthy_after_failures: usize,
+    /// Domains to exclude from the list, also excluding all subdomains
+    pub exclude_hosts: Vec<String>,
+    /// Regex matched against domains to exclude from the list
+    pub exclude_hosts_regex: Option<String>,
+    /// File to persist the cache in, served on startup until the first update
+    pub cache_file: Option<PathBuf>,
+    /// Signals required for a host to count as healthy, the profile is always required
+    pub healthy_requires: Vec<HealthSignal>,
+    /// Regex the reported version has to match for a host to count as healthy
+    pub version_pattern: Option<String>,
+    /// HTTP(S) proxy for instance probes, connectivity checks always connect directly
+    pub http_proxy: Option<String>,
+    /// Also fetch the instance list via the [Config::http_proxy]
+    pub http_proxy_instance_list: bool,
+    /// FlareSolverr compatible service, used if the instance list serves a JS challenge
+    pub instance_list_challenge_solver: Option<String>,
+    /// URL receiving a POST for every host changing between healthy and unhealthy
+    pub state_webhook_url: Option<String>,
+    /// Header texts all required for the instance table, matched case insensitive
+    pub instance_table_headers: Vec<String>,
+    /// Index of the instance table in the wiki, used if no table matches the headers
+    pub instance_table_index: Option<usize>,
+    /// List instances with a `www.` subdomain under their apex domain
+    pub strip_www: bool,
+    /// Idle connections kept per host, unlimited if unset
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Timeout for idle connections, reqwest default if unset
+    pub pool_idle_timeout: Option<Duration>,
 }
 
 impl Config {
@@ -51,23 +119,188 @@ impl Config {
             list_fetch_interval: Duration::from_secs(60 * 5),
             instance_check_interval: Duration::from_secs(60 * 5),
             instance_list_url: String::from("https://github.com/zedeus/nitter/wiki/Instances"),
+            instance_list_auth: None,
             profile_path: String::from("/jack"),
             rss_path: String::from("/jack/rss"),
             about_path: String::from("/about"),
             profile_name: String::from("@jack"),
             profile_posts_min: 5,
+            profile_posts_tolerance: 0,
+            profile_rotation: vec![],
+            probe_user_agents: vec![],
+            content_unavailable_markers: vec![],
             rss_content: String::from(r#"<rss xmlns\:atom"#),
+            rss_strict: false,
             additional_hosts: vec![String::from("https://nitter.net")],
             additional_host_country: String::from("🇳🇱"),
             website_url: String::from(""),
             ping_range: chrono::Duration::hours(3),
+            ping_avg_trim_percent: 0,
+            ping_avg_max_stddev: None,
             auto_mute: true,
             source_git_branch: String::from("master"),
             source_git_url: String::from("https://github.com/zedeus/nitter.git"),
-            bad_hosts: vec![String::from("tweet.whateveritworks.org")],
+            bad_hosts: vec![BadHost {
+                domain: String::from("tweet.whateveritworks.org"),
+                reason: None,
+                until: None,
+            }],
             cleanup_interval: Duration::from_secs(24 * 60 * 60),
             error_retention_per_host: 100,
+            points_retention: Duration::from_secs(120 * 24 * 60 * 60),
             connectivity_path: String::from("/"),
+            health_precheck: false,
+            check_content_type: false,
+            dry_run: false,
+            require_https: false,
+            list_conditional_max_age: None,
+            version_check_interval: None,
+            rate_limit_threshold: 3,
+            ranking_min_checks: 0,
+            unhealthy_after_failures: 1,
+            exclude_hosts: vec![],
+            exclude_hosts_regex: None,
+            cache_file: None,
+            healthy_requires: vec![HealthSignal::Profile],
+            version_pattern: None,
+            http_proxy: None,
+            http_proxy_instance_list: false,
+            instance_list_challenge_solver: None,
+            state_webhook_url: None,
+            instance_table_headers: vec![String::from("Online")],
+            instance_table_index: None,
+            strip_www: false,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+        })
+    }
+
+    /// Profile path and expected name for the health check of *round*, rotating over all profiles
+    pub fn profile_probe(&self, round: usize) -> (&str, &str) {
+        match round % (self.profile_rotation.len() + 1) {
+            0 => (&self.profile_path, &self.profile_name),
+            i => {
+                let probe = &self.profile_rotation[i - 1];
+                (&probe.path, &probe.name)
+            }
+        }
+    }
+
+    /// All profile paths used for health checks
+    pub fn profile_paths(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.profile_path.as_str())
+            .chain(self.profile_rotation.iter().map(|v| v.path.as_str()))
+    }
+}
+
+/// Profile for the health check, parsed from `path|name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileProbe {
+    pub path: String,
+    /// Expected profile name
+    pub name: String,
+}
+
+impl FromStr for ProfileProbe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((path, name)) = s.split_once('|').map(|(p, n)| (p.trim(), n.trim())) else {
+            return Err(format!("missing `|name` in profile entry `{s}`"));
+        };
+        if !path.starts_with('/') {
+            return Err(format!("profile path in `{s}` has to start with `/`"));
+        }
+        if name.is_empty() {
+            return Err(format!("missing name in profile entry `{s}`"));
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            name: name.to_owned(),
         })
     }
 }
+
+/// Signal that can be required for a host to count as healthy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthSignal {
+    /// Profile check passes
+    Profile,
+    /// RSS feeds are available
+    Rss,
+    /// IP connectivity could be detected
+    Connectivity,
+}
+
+impl FromStr for HealthSignal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "profile" => Ok(Self::Profile),
+            "rss" => Ok(Self::Rss),
+            "connectivity" => Ok(Self::Connectivity),
+            "stats" => Err(String::from(
+                "health signal `stats` is not supported, instance stats aren't fetched",
+            )),
+            v => Err(format!(
+                "unknown health signal `{v}`, expected one of profile, rss, connectivity"
+            )),
+        }
+    }
+}
+
+/// Host known to be bad (ip block)
+///
+/// Parsed from `domain[|until[|reason]]`, where `until` is an RFC 3339 timestamp or a `YYYY-MM-DD` date.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadHost {
+    pub domain: String,
+    /// Until when this host is flagged, forever if unset
+    pub until: Option<DateTime<Utc>>,
+    /// Reason to display for the flag
+    pub reason: Option<String>,
+}
+
+impl BadHost {
+    /// Whether the flag hasn't expired yet at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+impl FromStr for BadHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '|').map(str::trim);
+        let domain = parts.next().unwrap_or_default();
+        if domain.is_empty() {
+            return Err(format!("missing domain in bad host entry `{s}`"));
+        }
+        let until = match parts.next().filter(|v| !v.is_empty()) {
+            None => None,
+            Some(v) => Some(parse_until(v).ok_or_else(|| {
+                format!(
+                    "invalid expiry `{v}` in bad host entry `{s}`, expected RFC 3339 or YYYY-MM-DD"
+                )
+            })?),
+        };
+        let reason = parts.next().filter(|v| !v.is_empty()).map(str::to_owned);
+        Ok(Self {
+            domain: domain.to_owned(),
+            until,
+            reason,
+        })
+    }
+}
+
+fn parse_until(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+}
@@ -7,6 +7,22 @@ mod m20230803_154714_version_url;
 mod m20230829_201916_country;
 mod m20230914_231514_connectivity;
 mod m20231011_231223_errors;
+mod m20261014_120000_host_captcha;
+mod m20261014_120100_expected_version;
+mod m20261014_120200_health_check_index;
+mod m20261014_120300_host_force_disabled;
+mod m20261014_120400_host_first_seen;
+mod m20261014_120500_connectivity_history;
+mod m20261014_120600_health_check_blocked;
+mod m20261014_120700_host_public_last_error;
+mod m20261014_120800_points_history;
+mod m20261014_120900_protocol_probe;
+mod m20261014_121000_host_cert_pin;
+mod m20261014_121100_host_rss_valid;
+mod m20261014_121200_host_meta;
+mod m20261014_121300_rss_check;
+mod m20261014_121400_host_featured;
+mod m20261014_121500_host_version_neutral;
 
 pub struct Migrator;
 
@@ -21,6 +37,22 @@ impl MigratorTrait for Migrator {
             Box::new(m20230829_201916_country::Migration),
             Box::new(m20230914_231514_connectivity::Migration),
             Box::new(m20231011_231223_errors::Migration),
+            Box::new(m20261014_120000_host_captcha::Migration),
+            Box::new(m20261014_120100_expected_version::Migration),
+            Box::new(m20261014_120200_health_check_index::Migration),
+            Box::new(m20261014_120300_host_force_disabled::Migration),
+            Box::new(m20261014_120400_host_first_seen::Migration),
+            Box::new(m20261014_120500_connectivity_history::Migration),
+            Box::new(m20261014_120600_health_check_blocked::Migration),
+            Box::new(m20261014_120700_host_public_last_error::Migration),
+            Box::new(m20261014_120800_points_history::Migration),
+            Box::new(m20261014_120900_protocol_probe::Migration),
+            Box::new(m20261014_121000_host_cert_pin::Migration),
+            Box::new(m20261014_121100_host_rss_valid::Migration),
+            Box::new(m20261014_121200_host_meta::Migration),
+            Box::new(m20261014_121300_rss_check::Migration),
+            Box::new(m20261014_121400_host_featured::Migration),
+            Box::new(m20261014_121500_host_version_neutral::Migration),
         ]
     }
 }
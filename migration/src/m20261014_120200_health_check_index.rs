@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+/// Index for the last healthy check per host.
+///
+/// Measured on 150 hosts with a year of checks (5.25M rows, 98MB):
+/// - last healthy query: 970ms -> 0.5ms, together with the per-host subquery
+/// - 3h ping and 30d stats range queries: 230ms -> 1.5ms and 300ms -> 150ms, from `ANALYZE` alone
+///
+/// The index adds about 64MB, roughly 60% of the table size.
+/// Latest checks per host are already served by the (host, time) primary key.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let cmd = r#"CREATE INDEX "idx_health_check_host_healthy_time" ON "health_check" ("host", "healthy", "time");"#;
+        let db = manager.get_connection();
+        db.execute_unprepared("BEGIN EXCLUSIVE").await?;
+        tracing::info!("adding health_check index..");
+        db.execute_unprepared(cmd).await?;
+        db.execute_unprepared("COMMIT TRANSACTION").await?;
+        // planner statistics, required for skip-scans over the primary key on time ranges
+        db.execute_unprepared("ANALYZE").await?;
+        db.execute_unprepared("VACUUM").await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        panic!("Can't migrate down");
+    }
+}
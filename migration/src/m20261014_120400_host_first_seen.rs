@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let cmd = r#"ALTER TABLE "host" ADD COLUMN "first_seen" INTEGER NOT NULL DEFAULT 0;"#;
+        // backfill from the first health check, hosts without any checks use their last update
+        let backfill = r#"UPDATE "host" SET "first_seen" = COALESCE(
+            (SELECT MIN("time") FROM "health_check" WHERE "health_check"."host" = "host"."id"),
+            "updated"
+        );"#;
+        let db = manager.get_connection();
+        db.execute_unprepared("BEGIN EXCLUSIVE").await?;
+        tracing::info!("adding first_seen column..");
+        db.execute_unprepared(cmd).await?;
+        tracing::info!("backfilling first_seen..");
+        db.execute_unprepared(backfill).await?;
+        db.execute_unprepared("COMMIT TRANSACTION").await?;
+        db.execute_unprepared("VACUUM").await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        panic!("Can't migrate down");
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let cmd = r#"CREATE TABLE "connectivity_check" (
+            "time" integer NOT NULL,
+            "host" integer NOT NULL,
+            "connectivity" integer,
+            CONSTRAINT "pk_connectivity_check" PRIMARY KEY ("host", "time"),
+            FOREIGN KEY ("host") REFERENCES "host" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+        ) WITHOUT ROWID, STRICT;"#;
+        // start the history with the current state
+        let backfill = r#"INSERT INTO "connectivity_check" ("time", "host", "connectivity")
+            SELECT "updated", "id", "connectivity" FROM "host";"#;
+        let db = manager.get_connection();
+        db.execute_unprepared("BEGIN EXCLUSIVE").await?;
+        tracing::info!("adding connectivity_check table..");
+        db.execute_unprepared(cmd).await?;
+        db.execute_unprepared(backfill).await?;
+        db.execute_unprepared("COMMIT TRANSACTION").await?;
+        db.execute_unprepared("VACUUM").await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        panic!("Can't migrate down");
+    }
+}
@@ -25,6 +25,7 @@ pub(crate) struct AboutParser {
     regex: Regex,
 }
 
+#[derive(Debug)]
 pub struct AboutParsed {
     pub version_name: String,
     pub url: String,
@@ -1,22 +1,81 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 use std::cmp;
 use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::time::Instant;
 
 use chrono::{Days, Utc};
 use chrono::{Duration, TimeZone};
 use entities::host;
+use entities::host_meta;
+use entities::points_history;
 use entities::prelude::*;
 use entities::state::CacheData;
 use entities::state::CacheHost;
+use entities::state::InstanceMeta;
+use entities::state::VersionState;
+use reqwest::header::CONTENT_TYPE;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue;
 use sea_orm::ColumnTrait;
+use sea_orm::ConnectionTrait;
 use sea_orm::EntityTrait;
 use sea_orm::QueryFilter;
 use sea_orm::QueryOrder;
+use sea_orm::TransactionTrait;
 use sea_orm::{prelude::DateTimeUtc, DbBackend, FromQueryResult, Statement};
 
 use crate::version_check::fetch_git_state;
 use crate::LatestCheck;
-use crate::{Result, Scanner};
+use crate::{Result, Scanner, ScannerError};
+
+/// Health checks shown per host in the recent checks graph
+const RECENT_CHECKS: usize = 22;
+
+/// Write *data* to *path*, replacing it atomically
+async fn store_cache_file(path: &Path, data: &CacheData) -> Result<()> {
+    let json = serde_json::to_vec(data)?;
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, json).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Read a cache stored via [store_cache_file]
+async fn load_cache_file(path: &Path) -> Result<CacheData> {
+    let json = tokio::fs::read(path).await?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Coalesces overlapping runs of a job.
+///
+/// A run waiting for another one to finish is skipped if a newer run started after it was requested,
+/// as that run already covers everything the waiting one would.
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    /// Start of the last successful run
+    last_start: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl SingleFlight {
+    /// Run *job* unless coalesced, returns whether it ran
+    pub(crate) async fn run<F, Fut>(&self, job: F) -> Result<bool>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let requested = Instant::now();
+        let mut last_start = self.last_start.lock().await;
+        if last_start.is_some_and(|start| start >= requested) {
+            return Ok(false);
+        }
+        let start = Instant::now();
+        job().await?;
+        *last_start = Some(start);
+        Ok(true)
+    }
+}
 
 #[derive(Debug, FromQueryResult)]
 pub struct HostStats {
@@ -30,6 +89,8 @@ pub struct HostHealthyPercentage {
     host: i32,
     /// Percentage
     healthy: u8,
+    /// Amount of counted checks
+    checks: i64,
 }
 
 #[derive(Debug, Default)]
@@ -37,31 +98,215 @@ struct LastPings {
     avg: Option<i32>,
     min: Option<i32>,
     max: Option<i32>,
+    p50: Option<i32>,
+    p95: Option<i32>,
     pings: Vec<Option<i32>>,
 }
 
+#[derive(Debug, FromQueryResult, Default)]
+struct PingEntry {
+    host: i32,
+    ping: Option<i32>,
+}
+
+/// Nearest-rank percentile of *sorted*, None if empty
+pub(crate) fn percentile<T: Copy>(sorted: &[T], percentile: usize) -> Option<T> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * percentile).div_ceil(100);
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+/// Mean of *sorted* after dropping the slowest *trim_percent* and values above *max_stddev*
+fn trimmed_avg(sorted: &[i32], trim_percent: u8, max_stddev: Option<f64>) -> Option<i32> {
+    if sorted.is_empty() {
+        return None;
+    }
+    // always keep at least one value
+    let drop = (sorted.len() * trim_percent as usize / 100).min(sorted.len() - 1);
+    let mut samples = &sorted[..sorted.len() - drop];
+    let mean = |v: &[i32]| v.iter().map(|v| *v as f64).sum::<f64>() / v.len() as f64;
+    if let Some(max_stddev) = max_stddev {
+        let avg = mean(samples);
+        let variance = samples
+            .iter()
+            .map(|v| (*v as f64 - avg).powi(2))
+            .sum::<f64>()
+            / samples.len() as f64;
+        let limit = avg + max_stddev * variance.sqrt();
+        // sorted, the smallest value is never above the limit
+        let keep = samples.partition_point(|v| *v as f64 <= limit);
+        samples = &samples[..keep.max(1)];
+    }
+    Some(mean(samples) as i32)
+}
+
+/// Calculate min/max/avg/p50/p95 and the list of all pings per host.
+///
+/// Expects entries to be ordered by host, the stats only take non-null pings into account.
+fn aggregate_pings(
+    last_pings: &[PingEntry],
+    trim_percent: u8,
+    max_stddev: Option<f64>,
+) -> HashMap<i32, LastPings> {
+    let mut map = HashMap::with_capacity(100);
+    let mut iter = last_pings.iter().peekable();
+    while let Some(first) = iter.peek() {
+        let host = first.host;
+        let mut entry = LastPings::default();
+        let mut samples = Vec::new();
+        while let Some(ping) = iter.next_if(|v| v.host == host) {
+            if let Some(value) = ping.ping {
+                samples.push(value);
+                entry.min = Some(entry.min.map_or(value, |v| v.min(value)));
+                entry.max = Some(entry.max.map_or(value, |v| v.max(value)));
+            }
+            entry.pings.push(ping.ping);
+        }
+        samples.sort_unstable();
+        entry.avg = trimmed_avg(&samples, trim_percent, max_stddev);
+        entry.p50 = percentile(&samples, 50);
+        entry.p95 = percentile(&samples, 95);
+        assert!(
+            map.insert(host, entry).is_none(),
+            "entries not ordered by host"
+        );
+    }
+    map
+}
+
 #[derive(Debug, FromQueryResult)]
 pub struct Version {
     version: String,
 }
 
 impl Scanner {
+    /// Regenerate and swap the cache, coalescing overlapping calls into one regeneration
     pub(crate) async fn update_cache(&self) -> Result<()> {
+        let ran = self
+            .inner
+            .cache_update
+            .run(|| self.update_cache_inner())
+            .await?;
+        if !ran {
+            tracing::debug!("cache update coalesced with a concurrent one");
+        }
+        Ok(())
+    }
+
+    async fn update_cache_inner(&self) -> Result<()> {
         let new_data = self.generate_cache_data().await?;
-        {
+        if !self.inner.config.dry_run {
+            if let Err(e) = self.store_points(&new_data).await {
+                tracing::warn!(error=?e, "Failed storing points history");
+            }
+        }
+        if let Some(path) = self.inner.config.cache_file.as_deref() {
+            if let Err(e) = store_cache_file(path, &new_data).await {
+                tracing::warn!(error=?e, path=?path, "Failed storing cache file");
+            }
+        }
+        let transitions = {
             let mut guard = self.inner.app_state.cache.write().unwrap();
+            let transitions = state_transitions(&guard, &new_data);
             *guard = new_data;
+            transitions
+        };
+        if let Some(url) = self.inner.config.state_webhook_url.clone() {
+            if !transitions.is_empty() && !self.inner.config.dry_run {
+                let scanner = self.clone();
+                tokio::spawn(async move { scanner.send_state_webhook(&url, &transitions).await });
+            }
+        }
+        Ok(())
+    }
+
+    /// POST every transition to the webhook *url*, failures are only logged
+    pub(crate) async fn send_state_webhook(&self, url: &str, transitions: &[StateTransition]) {
+        for transition in transitions {
+            let res = self
+                .inner
+                .webhook_client
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(transition).expect("serializable transition"))
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+            if let Err(e) = res {
+                tracing::warn!(error=?e, domain = transition.domain, "Failed sending state webhook");
+            }
+        }
+    }
+
+    /// Store the points of all hosts in *data*, replacing earlier values of the same hour
+    pub(crate) async fn store_points(&self, data: &CacheData) -> Result<()> {
+        if data.hosts.is_empty() {
+            return Ok(());
+        }
+        let hour = data.last_update.timestamp() - data.last_update.timestamp().rem_euclid(3600);
+        let ids: HashMap<String, i32> = Host::find()
+            .filter(host::Column::Domain.is_in(data.hosts.iter().map(|h| h.domain.as_str())))
+            .all(&self.inner.db)
+            .await?
+            .into_iter()
+            .map(|host| (host.domain, host.id))
+            .collect();
+        let entries: Vec<_> = data
+            .hosts
+            .iter()
+            .filter_map(|h| {
+                ids.get(&h.domain).map(|id| points_history::ActiveModel {
+                    time: ActiveValue::Set(hour),
+                    host: ActiveValue::Set(*id),
+                    points: ActiveValue::Set(h.points),
+                })
+            })
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
         }
+        PointsHistory::insert_many(entries)
+            .on_conflict(
+                OnConflict::columns([points_history::Column::Host, points_history::Column::Time])
+                    .update_column(points_history::Column::Points)
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.inner.db)
+            .await?;
         Ok(())
     }
 
+    /// Load the cache from the configured cache file, returns false if none was loaded
+    pub(crate) async fn load_cache_file(&self) -> bool {
+        let Some(path) = self.inner.config.cache_file.as_deref() else {
+            return false;
+        };
+        match load_cache_file(path).await {
+            Ok(data) => {
+                tracing::info!(path=?path, last_update=?data.last_update, "Loaded cache file");
+                *self.inner.app_state.cache.write().unwrap() = data;
+                true
+            }
+            Err(ScannerError::CacheFile(e)) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => {
+                tracing::warn!(error=?e, path=?path, "Failed loading cache file");
+                false
+            }
+        }
+    }
+
     /// Generate host stats and returns a new CacheData
+    ///
+    /// All queries run inside one transaction, so the cache is built from a consistent snapshot.
     pub(crate) async fn generate_cache_data(&self) -> Result<CacheData> {
-        let hosts = self.query_hosts_enabled().await?;
         let config_c = self.inner.config.clone();
         let current_version = tokio::task::spawn_blocking(move || fetch_git_state(config_c))
             .await
             .unwrap()?;
+        let txn = self.inner.db.begin().await?;
+        let hosts = self.query_hosts_enabled(&txn).await?;
         if hosts.is_empty() {
             return Ok(CacheData {
                 hosts: Vec::new(),
@@ -75,49 +320,70 @@ impl Scanner {
             .checked_sub_signed(chrono::Duration::hours(3))
             .unwrap();
         let time_30d = time_now.checked_sub_days(Days::new(30)).unwrap();
+        // peer reports have to be as recent as our own checks
+        let time_peer_probes = time_now
+            - Duration::from_std(self.inner.config.instance_check_interval * 2)
+                .unwrap_or(Duration::hours(1));
         let time_120d = time_now.checked_sub_days(Days::new(120)).unwrap();
 
-        let stats_3h = self.query_stats_range(time_3h, time_now).await?;
-        let stats_30d = self.query_stats_range(time_30d, time_now).await?;
-        let stats_120d = self.query_stats_range(time_120d, time_30d).await?;
+        let stats_3h = self.query_stats_range(&txn, time_3h, time_now).await?;
+        let stats_30d = self.query_stats_range(&txn, time_30d, time_now).await?;
+        let stats_120d = self.query_stats_range(&txn, time_120d, time_30d).await?;
 
-        let mut last_healthy_check = self.query_last_healthy().await?;
+        let mut last_healthy_check = self.query_last_healthy(&txn).await?;
 
-        let version_points = self.query_versions(time_30d).await?;
-        let latest_check = self.query_latest_check(&self.inner.db).await?;
+        let version_points = self.query_versions(&txn, time_30d).await?;
+        let latest_check = self.query_latest_check(&txn).await?;
         let latest_check: HashMap<i32, LatestCheck> =
             latest_check.into_iter().map(|v| (v.host, v)).collect();
 
         let mut ping_data = self
-            .query_pings(time_now - self.inner.config.ping_range)
+            .query_pings(&txn, time_now - self.inner.config.ping_range)
+            .await?;
+
+        let mut healthy_percentage_total = self.query_healthy_percentage(&txn).await?;
+
+        // also covers the checks for the debounce
+        let unhealthy_after = self.inner.config.unhealthy_after_failures.max(1);
+        let mut recent_checks = self
+            .query_latest_health_checks(&txn, RECENT_CHECKS.max(unhealthy_after) as i32)
             .await?;
 
-        let mut healthy_percentage_total = self.query_healthy_percentage().await?;
+        let mut public_last_errors = self.query_public_last_errors(&txn).await?;
+
+        let mut host_meta: HashMap<i32, host_meta::Model> = HostMeta::find()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|meta| (meta.host, meta))
+            .collect();
 
         let mut host_statistics = Vec::with_capacity(hosts.len());
         let default_health_check = LatestCheck::default();
         for host in hosts {
-            let stats_3h_host = stats_3h
+            let last_check = latest_check.get(&host.id);
+            let last_checked = last_check.map(|check| Utc.timestamp_opt(check.time, 0).unwrap());
+            let last_check = last_check.unwrap_or(&default_health_check);
+
+            let stats_30d_host = stats_30d
                 .get(&host.id)
                 .map_or(0.0, |stats| stats.good as f64 / stats.total as f64);
+            // blocked checks aren't counted, fall back to the 30d uptime if we're only blocked
+            let stats_3h_host = match stats_3h.get(&host.id) {
+                Some(stats) => stats.good as f64 / stats.total as f64,
+                None if last_check.blocked => stats_30d_host,
+                None => 0.0,
+            };
             let points_3h: f64 = 0.3 * stats_3h_host;
-            let points_30d: f64 = 0.2
-                * stats_30d
-                    .get(&host.id)
-                    .map_or(0.0, |stats| stats.good as f64 / stats.total as f64);
+            let points_30d: f64 = 0.2 * stats_30d_host;
             let points_120d: f64 = 0.2
                 * stats_120d
                     .get(&host.id)
                     .map_or(0.0, |stats| stats.good as f64 / stats.total as f64);
-            let points_version = 0.1
-                * host
-                    .version
-                    .as_ref()
-                    .map_or(0.0, |version| *version_points.get(version).unwrap_or(&0.0));
+            let points_version = 0.1 * host_version_points(&host, &version_points);
             let points = points_30d + points_120d + points_version + points_3h;
             let points = stats_3h_host * points;
 
-            let last_check = latest_check.get(&host.id).unwrap_or(&default_health_check);
             // // don't rank currently down instances highly
             // let points = match last_check.healthy {
             //     true => (points * 100.0) as i32,
@@ -125,17 +391,46 @@ impl Scanner {
             // };
             let points = (points * 100.0) as i32;
 
-            let latest_version = host
-                .version_url
-                .as_ref()
-                .map_or(false, |url| current_version.is_same_version(&url));
-            let is_upstream = host
-                .version_url
-                .as_ref()
-                .map_or(false, |url| current_version.is_same_repo(&url));
+            let version_state = match host.version_url.as_ref() {
+                None => VersionState::Missing,
+                Some(url) if current_version.is_same_version(url) => VersionState::Latest,
+                Some(url) if current_version.is_same_repo(url) => VersionState::Outdated,
+                Some(_) => VersionState::Foreign,
+            };
+
+            let bad_host = match last_check.healthy {
+                true => None,
+                false => self
+                    .inner
+                    .config
+                    .bad_hosts
+                    .iter()
+                    .find(|bad| bad.domain == host.domain && bad.is_active(time_now)),
+            };
+
+            // blocked from here, but reachable for a remote probe
+            let healthy_remote = !last_check.healthy
+                && self
+                    .inner
+                    .app_state
+                    .is_healthy_from_peer(&host.domain, time_peer_probes);
+
+            let mut host_recent_checks = recent_checks.remove(&host.id).unwrap_or_default();
+            let debounced =
+                last_check.healthy || has_recent_healthy(&host_recent_checks, unhealthy_after);
+            if host_recent_checks.len() > RECENT_CHECKS {
+                host_recent_checks.drain(..host_recent_checks.len() - RECENT_CHECKS);
+            }
+
+            let healthy_percentage = healthy_percentage_total.remove(&host.id);
+            let insufficient_data = healthy_percentage.as_ref().map_or(0, |v| v.checks)
+                < self.inner.config.ranking_min_checks as i64;
 
-            let is_bad_host =
-                (!last_check.healthy) && self.inner.config.bad_hosts.contains(&host.domain);
+            let meta = host_meta.remove(&host.id);
+            let country = match meta.as_ref().and_then(|meta| meta.country.clone()) {
+                Some(country) => country,
+                None => host.country,
+            };
 
             let host_ping_data = ping_data.remove(&host.id);
             let last_healthy = last_healthy_check.remove(&host.id);
@@ -143,6 +438,7 @@ impl Scanner {
                 last_healthy.map_or(true, |e| (time_now - e) > Duration::hours(12));
             host_statistics.push(CacheHost {
                 last_healthy: last_healthy,
+                last_checked,
                 __show_last_seen,
                 url: host.url,
                 domain: host.domain,
@@ -150,43 +446,42 @@ impl Scanner {
                 connectivity: host.connectivity,
                 rss: host.rss,
                 version: host.version,
-                healthy: last_check.healthy,
+                healthy: debounced || healthy_remote,
+                is_healthy_remote: healthy_remote,
+                is_blocked: last_check.blocked && !healthy_remote,
+                last_error: match last_check.healthy {
+                    true => None,
+                    false => public_last_errors.remove(&host.id),
+                },
                 ping_max: host_ping_data.as_ref().and_then(|v| v.max),
                 ping_min: host_ping_data.as_ref().and_then(|v| v.min),
                 ping_avg: host_ping_data.as_ref().and_then(|v| v.avg),
+                ping_p50: host_ping_data.as_ref().and_then(|v| v.p50),
+                ping_p95: host_ping_data.as_ref().and_then(|v| v.p95),
                 recent_pings: host_ping_data.map(|v| v.pings).unwrap_or_default(),
-                is_latest_version: latest_version,
-                is_upstream,
+                version_state,
                 version_url: host.version_url,
-                is_bad_host,
-                country: host.country,
-                healthy_percentage_overall: healthy_percentage_total.remove(&host.id).unwrap_or(0),
-                recent_checks: self.query_latest_health_checks(22, host.id).await?,
+                is_bad_host: bad_host.is_some(),
+                bad_host_reason: bad_host.and_then(|bad| bad.reason.clone()),
+                bad_host_until: bad_host.and_then(|bad| bad.until),
+                is_captcha_gated: host.captcha,
+                is_account_pool_exhausted: self.is_account_pool_exhausted(host.id),
+                is_content_unavailable: self.is_content_unavailable(host.id),
+                country,
+                meta: meta.map_or_else(Default::default, |meta| InstanceMeta {
+                    contact_email: meta.contact_email,
+                    tor_mirror: meta.tor_mirror,
+                    donation_url: meta.donation_url,
+                }),
+                healthy_percentage_overall: healthy_percentage.map_or(0, |v| v.healthy),
+                insufficient_data,
+                is_featured: host.featured,
+                rss_valid: host.rss_valid,
+                recent_checks: host_recent_checks,
             })
         }
-        host_statistics.sort_unstable_by(|a, b| {
-            if a.points > 0 {
-                match a.points.cmp(&b.points) {
-                    cmp::Ordering::Equal => a
-                        .healthy_percentage_overall
-                        .cmp(&b.healthy_percentage_overall),
-                    v => v,
-                }
-            } else {
-                let cmp_v: cmp::Ordering = b.__show_last_seen.cmp(&a.__show_last_seen);
-                if cmp_v.is_ne() {
-                    cmp_v
-                } else {
-                    match (a.last_healthy, b.last_healthy) {
-                        (Some(a), Some(b)) => a.cmp(&b),
-                        (Some(_), None) => cmp::Ordering::Greater,
-                        (None, Some(_)) => cmp::Ordering::Less,
-                        (None, None) => cmp::Ordering::Equal,
-                    }
-                }
-            }
-        });
-        host_statistics.reverse();
+        rank_hosts(&mut host_statistics);
+        txn.commit().await?;
         Ok(CacheData {
             hosts: host_statistics,
             last_update: time_now,
@@ -194,20 +489,24 @@ impl Scanner {
         })
     }
 
-    async fn query_hosts_enabled(&self) -> Result<Vec<host::Model>> {
+    /// Hosts for the public list, excluding ones force disabled by an admin
+    async fn query_hosts_enabled<T: ConnectionTrait>(
+        &self,
+        connection: &T,
+    ) -> Result<Vec<host::Model>> {
         Ok(Host::find()
             .filter(host::Column::Enabled.eq(true))
+            .filter(host::Column::ForceDisabled.eq(false))
             .order_by_asc(host::Column::Id)
-            .all(&self.inner.db)
+            .all(connection)
             .await?)
     }
 
-    async fn query_pings(&self, age: DateTimeUtc) -> Result<HashMap<i32, LastPings>> {
-        #[derive(Debug, FromQueryResult, Default)]
-        struct PingEntry {
-            host: i32,
-            ping: Option<i32>,
-        }
+    async fn query_pings<T: ConnectionTrait>(
+        &self,
+        connection: &T,
+        age: DateTimeUtc,
+    ) -> Result<HashMap<i32, LastPings>> {
         let last_pings = PingEntry::find_by_statement(Statement::from_sql_and_values(
             DbBackend::Sqlite,
             r#"
@@ -218,62 +517,20 @@ impl Scanner {
             "#,
             [age.timestamp().into()],
         ))
-        .all(&self.inner.db)
+        .all(connection)
         .await?;
-        // this is pretty verbose, but allows to
-        // - calculate the avg
-        // - map by host
-        // - get a list of all past entries
-        // in one go and with one DB request
-        let mut map = HashMap::with_capacity(100);
-        let mut iter = last_pings.iter();
-        let first = match iter.next() {
-            None => {
-                return Ok(HashMap::new());
-            }
-            Some(v) => v,
-        };
-        let mut current_entry = LastPings::default();
-        let mut last_host = first.host;
-        let mut non_null_entries = first.ping.as_ref().map_or(0, |_| 1);
-        if let Some(ping) = first.ping.as_ref() {
-            current_entry.avg = Some(*ping);
-            non_null_entries += 1;
-            current_entry.min = Some(*ping);
-            current_entry.max = Some(*ping);
-        }
-        current_entry.pings.push(first.ping);
-        for ping in iter {
-            if last_host != ping.host {
-                let mut new_entry = LastPings::default();
-                // will overflow only if we hit > 1500 days of backlog
-                // when having 5 minutes interval and only 5000ms response times
-                if let Some(sum) = current_entry.avg {
-                    current_entry.avg = Some(sum / non_null_entries);
-                }
-                non_null_entries = 0;
-                std::mem::swap(&mut new_entry, &mut current_entry);
-                // insert back the old (swapped) entry
-                assert_eq!(map.insert(last_host, new_entry).is_some(), false);
-                last_host = ping.host;
-            }
-            if let Some(ping) = ping.ping.as_ref() {
-                current_entry.avg = Some(current_entry.avg.unwrap_or(0) + ping);
-                non_null_entries += 1;
-                current_entry.min = Some(current_entry.min.map_or(*ping, |v| v.min(*ping)));
-                current_entry.max = Some(current_entry.max.map_or(*ping, |v| v.max(*ping)));
-            }
-            current_entry.pings.push(ping.ping);
-        }
-        // Insert last entry
-        if let Some(sum) = current_entry.avg {
-            current_entry.avg = Some(sum / non_null_entries);
-        }
-        assert_eq!(map.insert(last_host, current_entry).is_some(), false);
-        Ok(map)
+        Ok(aggregate_pings(
+            &last_pings,
+            self.inner.config.ping_avg_trim_percent,
+            self.inner.config.ping_avg_max_stddev,
+        ))
     }
 
-    async fn query_versions(&self, age: DateTimeUtc) -> Result<HashMap<String, f64>> {
+    async fn query_versions<T: ConnectionTrait>(
+        &self,
+        connection: &T,
+        age: DateTimeUtc,
+    ) -> Result<HashMap<String, f64>> {
         let stats = Version::find_by_statement(Statement::from_sql_and_values(
             DbBackend::Sqlite,
             r#"SELECT version FROM host h
@@ -283,7 +540,7 @@ impl Scanner {
             ORDER BY version ASC"#,
             [age.timestamp().into()],
         ))
-        .all(&self.inner.db)
+        .all(connection)
         .await?;
 
         let amount = stats.len();
@@ -297,7 +554,12 @@ impl Scanner {
     }
 
     /// Timestamp of last healthy host check
-    async fn query_last_healthy(&self) -> Result<HashMap<i32, DateTimeUtc>> {
+    ///
+    /// Subquery per host, so sqlite can use the (host, healthy, time) index instead of a full scan.
+    async fn query_last_healthy<T: ConnectionTrait>(
+        &self,
+        connection: &T,
+    ) -> Result<HashMap<i32, DateTimeUtc>> {
         #[derive(Debug, FromQueryResult)]
         struct LastHealthyEntry {
             host: i32,
@@ -307,14 +569,17 @@ impl Scanner {
             LastHealthyEntry::find_by_statement(Statement::from_sql_and_values(
                 DbBackend::Sqlite,
                 r#"
-            SELECT u.host,MAX(u.time) as time FROM health_check u
-            JOIN host h ON h.id = u.host
-            WHERE h.enabled = true AND u.healthy = true
-            GROUP BY u.host
+            SELECT * FROM (
+                SELECT h.id as host,(
+                    SELECT MAX(u.time) FROM health_check u
+                    WHERE u.host = h.id AND u.healthy = true
+                ) as time FROM host h
+                WHERE h.enabled = true
+            ) WHERE time IS NOT NULL
             "#,
                 [],
             ))
-            .all(&self.inner.db)
+            .all(connection)
             .await?;
         let last_healthy_times: HashMap<_, _> = last_healthy_times
             .into_iter()
@@ -323,9 +588,10 @@ impl Scanner {
         Ok(last_healthy_times)
     }
 
-    /// Query uptime statistics per host
-    async fn query_stats_range(
+    /// Query uptime statistics per host, blocked checks are not counted
+    async fn query_stats_range<T: ConnectionTrait>(
         &self,
+        connection: &T,
         from: DateTimeUtc,
         to: DateTimeUtc,
     ) -> Result<HashMap<i32, HostStats>> {
@@ -333,66 +599,924 @@ impl Scanner {
             DbBackend::Sqlite,
             r#"SELECT u.host, COUNT(CASE WHEN healthy = true THEN 1 END) as good,COUNT(*) as total FROM health_check u
             JOIN host h ON h.id = u.host
-            WHERE h.enabled = true AND u.time BETWEEN $1 AND $2
+            WHERE h.enabled = true AND u.blocked = false AND u.time BETWEEN $1 AND $2
             GROUP BY u.host "#,
             [from.timestamp().into(), to.timestamp().into()],
         ))
-        .all(&self.inner.db)
+        .all(connection)
         .await?;
         let stats: HashMap<_, _> = stats.into_iter().map(|v| (v.host, v)).collect();
         Ok(stats)
     }
 
-    /// Query total up percentage for all hosts
-    async fn query_healthy_percentage(&self) -> Result<HashMap<i32, u8>> {
+    /// Query total up percentage for all hosts, blocked checks are not counted
+    async fn query_healthy_percentage<T: ConnectionTrait>(
+        &self,
+        connection: &T,
+    ) -> Result<HashMap<i32, HostHealthyPercentage>> {
         let stats: Vec<HostHealthyPercentage> =
             HostHealthyPercentage::find_by_statement(Statement::from_sql_and_values(
                 DbBackend::Sqlite,
-                r#"SELECT u.host, CAST(AVG(healthy) * 100 as INT) as healthy FROM health_check u
+                r#"SELECT u.host, CAST(AVG(healthy) * 100 as INT) as healthy, COUNT(*) as checks FROM health_check u
             JOIN host h ON h.id = u.host
-            WHERE h.enabled = true
+            WHERE h.enabled = true AND u.blocked = false
             GROUP BY u.host"#,
                 [],
             ))
-            .all(&self.inner.db)
+            .all(connection)
             .await?;
-        let stats: HashMap<_, _> = stats.into_iter().map(|v| (v.host, v.healthy)).collect();
+        let stats: HashMap<_, _> = stats.into_iter().map(|v| (v.host, v)).collect();
         Ok(stats)
     }
 
-    /// Query latest health checks for the red/green only graph. Returns latest $amount in ascending order and formatted time.
-    async fn query_latest_health_checks(
+    /// Category of the latest error per host, only for hosts whose operator opted in
+    async fn query_public_last_errors<T: ConnectionTrait>(
+        &self,
+        connection: &T,
+    ) -> Result<HashMap<i32, String>> {
+        #[derive(Debug, FromQueryResult)]
+        struct LastError {
+            host: i32,
+            message: String,
+            http_status: Option<i32>,
+        }
+        let errors = LastError::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"SELECT e.host, e.message, e.http_status FROM check_errors e
+            JOIN host h ON h.id = e.host
+            WHERE h.enabled = true AND h.public_last_error = true
+            AND e.time = (SELECT MAX(time) FROM check_errors WHERE host = e.host)"#,
+            [],
+        ))
+        .all(connection)
+        .await?;
+        Ok(errors
+            .into_iter()
+            .map(|e| (e.host, error_category(&e.message, e.http_status)))
+            .collect())
+    }
+
+    /// Query latest health checks for the red/green only graph.
+    ///
+    /// Returns the latest $amount per host in ascending order and formatted time.
+    /// Ranked in a single query, instead of one query per host.
+    async fn query_latest_health_checks<T: ConnectionTrait>(
         &self,
-        // How many to retrieve
+        connection: &T,
+        // How many to retrieve per host
         amount: i32,
-        host: i32,
-    ) -> Result<Vec<(String, bool)>> {
+    ) -> Result<HashMap<i32, Vec<(String, bool)>>> {
         #[derive(Debug, FromQueryResult)]
         pub struct HostHealthCheck {
+            host: i32,
             healthy: bool,
             time: i64,
         }
         let health_checks: Vec<HostHealthCheck> =
             HostHealthCheck::find_by_statement(Statement::from_sql_and_values(
                 DbBackend::Sqlite,
-                r#"SELECT healthy, time FROM health_check u
+                r#"SELECT host, healthy, time FROM (
+                SELECT u.host, u.healthy, u.time,
+                ROW_NUMBER() OVER (PARTITION BY u.host ORDER BY u.time DESC) as rank
+                FROM health_check u
+                JOIN host h ON h.id = u.host
+                WHERE h.enabled = true
+            ) WHERE rank <= $1
+            ORDER BY host, time ASC"#,
+                [amount.into()],
+            ))
+            .all(connection)
+            .await?;
+        // group per host and transform to correct time format
+        let mut grouped: HashMap<i32, Vec<(String, bool)>> = HashMap::new();
+        for entry in health_checks {
+            grouped
+                .entry(entry.host)
+                .or_default()
+                .push((format_check_time(entry.time), entry.healthy));
+        }
+        Ok(grouped)
+    }
+}
+
+/// Sanitized category of a check error, without bodies or addresses
+fn error_category(message: &str, http_status: Option<i32>) -> String {
+    let message = message.to_lowercase();
+    let category = if message.contains("captcha") {
+        "captcha"
+    } else if message.contains("rate limited") {
+        "rate limited"
+    } else if message.contains("content unavailable") {
+        "content unavailable"
+    } else if message.contains("profile content mismatch")
+        || message.contains("profile-card")
+        || message.contains("timeline div")
+    {
+        "profile mismatch"
+    } else if message.contains("post count") {
+        "too few profile posts"
+    } else if message.contains("required signal") {
+        "missing required signal"
+    } else if message.contains("content type") {
+        "unexpected content type"
+    } else if message.contains("timed out") || message.contains("timeout") {
+        "timeout"
+    } else if let Some(code) = http_status {
+        return format!("HTTP {code}");
+    } else {
+        "connection failed"
+    };
+    String::from(category)
+}
+
+/// Health state change of a host between two cache updates
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub(crate) struct StateTransition {
+    domain: String,
+    old_state: &'static str,
+    new_state: &'static str,
+    time: DateTimeUtc,
+}
+
+fn health_state(healthy: bool) -> &'static str {
+    match healthy {
+        true => "healthy",
+        false => "unhealthy",
+    }
+}
+
+/// Hosts changing between healthy and unhealthy from *old* to *new*, added and removed hosts are ignored
+fn state_transitions(old: &CacheData, new: &CacheData) -> Vec<StateTransition> {
+    let old_states: HashMap<&str, bool> = old
+        .hosts
+        .iter()
+        .map(|host| (host.domain.as_str(), host.healthy))
+        .collect();
+    new.hosts
+        .iter()
+        .filter_map(|host| {
+            let old_healthy = *old_states.get(host.domain.as_str())?;
+            (old_healthy != host.healthy).then(|| StateTransition {
+                domain: host.domain.clone(),
+                old_state: health_state(old_healthy),
+                new_state: health_state(host.healthy),
+                time: new.last_update,
+            })
+        })
+        .collect()
+}
+
+/// Version points of hosts without a version, which are flagged as neutral
+const NEUTRAL_VERSION_POINTS: f64 = 0.5;
+
+/// Rating of the version of *host* between 0 and 1, by the *version_points* of all versions
+fn host_version_points(host: &host::Model, version_points: &HashMap<String, f64>) -> f64 {
+    match &host.version {
+        Some(version) => *version_points.get(version).unwrap_or(&0.0),
+        // forks disabling the about page
+        None if host.version_neutral => NEUTRAL_VERSION_POINTS,
+        None => 0.0,
+    }
+}
+
+/// Whether any of the latest *amount* checks is healthy, *checks* are in ascending order
+fn has_recent_healthy(checks: &[(String, bool)], amount: usize) -> bool {
+    checks
+        .iter()
+        .rev()
+        .take(amount)
+        .any(|(_, healthy)| *healthy)
+}
+
+/// Sort *hosts* by their ranking, best first
+///
+/// Featured hosts come first, hosts with insufficient data follow all ranked ones of their section.
+fn rank_hosts(hosts: &mut [CacheHost]) {
+    hosts.sort_unstable_by(|a, b| {
+        if a.points > 0 {
+            match a.points.cmp(&b.points) {
+                cmp::Ordering::Equal => a
+                    .healthy_percentage_overall
+                    .cmp(&b.healthy_percentage_overall),
+                v => v,
+            }
+        } else {
+            let cmp_v: cmp::Ordering = b.__show_last_seen.cmp(&a.__show_last_seen);
+            if cmp_v.is_ne() {
+                cmp_v
+            } else {
+                match (a.last_healthy, b.last_healthy) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => cmp::Ordering::Greater,
+                    (None, Some(_)) => cmp::Ordering::Less,
+                    (None, None) => cmp::Ordering::Equal,
+                }
+            }
+        }
+    });
+    hosts.reverse();
+    // stable, keeps the order within all groups
+    hosts.sort_by_key(|host| (!host.is_featured, host.insufficient_data));
+}
+
+/// Format of the health check time in the recent checks graph
+fn format_check_time(time: i64) -> String {
+    let time = Utc.timestamp_opt(time, 0).unwrap();
+    time.format("%Y.%m.%d %H:%M").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use entities::health_check;
+    use entities::state::scanner::Config;
+    use sea_orm::{ActiveModelTrait, ActiveValue};
+
+    use crate::test::{
+        db_memory, insert_host, mock_instance, new_host, test_host, test_scanner, test_scanner_with,
+    };
+    use axum::Router;
+
+    fn cache_host(domain: &str, points: i32) -> CacheHost {
+        CacheHost {
+            url: format!("https://{domain}"),
+            domain: domain.to_owned(),
+            points,
+            rss: true,
+            recent_pings: vec![Some(100), None],
+            ping_max: Some(100),
+            ping_min: Some(100),
+            ping_avg: Some(100),
+            ping_p50: Some(100),
+            ping_p95: Some(100),
+            version: Some(String::from("2023.10.01")),
+            version_url: None,
+            healthy: true,
+            last_healthy: Some(Utc.timestamp_opt(1_700_000_000, 0).unwrap()),
+            last_checked: None,
+            version_state: VersionState::Outdated,
+            is_bad_host: false,
+            bad_host_reason: None,
+            bad_host_until: None,
+            is_captcha_gated: false,
+            is_healthy_remote: false,
+            is_blocked: false,
+            last_error: None,
+            is_account_pool_exhausted: false,
+            is_content_unavailable: false,
+            country: String::from("DE"),
+            recent_checks: vec![(String::from("12:00"), true)],
+            healthy_percentage_overall: 99,
+            insufficient_data: false,
+            is_featured: false,
+            rss_valid: None,
+            meta: Default::default(),
+            connectivity: Some(host::Connectivity::All),
+            __show_last_seen: false,
+        }
+    }
+
+    #[test]
+    fn recent_healthy_debounce() {
+        let checks = |states: &[bool]| -> Vec<(String, bool)> {
+            states.iter().map(|v| (String::new(), *v)).collect()
+        };
+        let flapping = checks(&[true, true, false, false]);
+        assert!(!has_recent_healthy(&flapping, 1));
+        assert!(!has_recent_healthy(&flapping, 2));
+        assert!(has_recent_healthy(&flapping, 3));
+        // recovers with the first healthy check
+        assert!(has_recent_healthy(&checks(&[false, false, true]), 1));
+        // new hosts without any healthy check
+        assert!(!has_recent_healthy(&checks(&[false]), 3));
+        assert!(!has_recent_healthy(&[], 3));
+    }
+
+    #[test]
+    fn ranking_insufficient_data() {
+        let mut hosts = vec![
+            cache_host("low", 10),
+            cache_host("new-high", 90),
+            cache_host("high", 50),
+            cache_host("new-low", 20),
+        ];
+        hosts[1].insufficient_data = true;
+        hosts[3].insufficient_data = true;
+        rank_hosts(&mut hosts);
+        let order: Vec<_> = hosts.iter().map(|h| h.domain.as_str()).collect();
+        assert_eq!(order, ["high", "low", "new-high", "new-low"]);
+    }
+
+    #[test]
+    fn version_points_neutral() {
+        let mut host = test_host("https://localhost");
+        let version_points = HashMap::from([(String::from("2023.10.01"), 1.0)]);
+        assert_eq!(host_version_points(&host, &version_points), 0.0);
+        host.version_neutral = true;
+        assert_eq!(
+            host_version_points(&host, &version_points),
+            NEUTRAL_VERSION_POINTS
+        );
+        // reported versions are always rated
+        host.version = Some(String::from("2023.10.01"));
+        assert_eq!(host_version_points(&host, &version_points), 1.0);
+        host.version = Some(String::from("unknown"));
+        assert_eq!(host_version_points(&host, &version_points), 0.0);
+    }
+
+    #[test]
+    fn rank_featured_first() {
+        let mut hosts = vec![
+            cache_host("low", 10),
+            cache_host("featured-low", 5),
+            cache_host("high", 50),
+            cache_host("featured-high", 20),
+            cache_host("featured-new", 30),
+        ];
+        hosts[1].is_featured = true;
+        hosts[3].is_featured = true;
+        hosts[4].is_featured = true;
+        hosts[4].insufficient_data = true;
+        rank_hosts(&mut hosts);
+        let order: Vec<_> = hosts.iter().map(|h| h.domain.as_str()).collect();
+        assert_eq!(
+            order,
+            [
+                "featured-high",
+                "featured-low",
+                "featured-new",
+                "high",
+                "low"
+            ]
+        );
+    }
+
+    #[test]
+    fn state_transitions_changed_only() {
+        let time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let data = |hosts: &[(&str, bool)]| CacheData {
+            hosts: hosts
+                .iter()
+                .map(|(domain, healthy)| CacheHost {
+                    healthy: *healthy,
+                    ..cache_host(domain, 0)
+                })
+                .collect(),
+            last_update: time,
+            latest_commit: String::new(),
+        };
+        let old = data(&[
+            ("down", true),
+            ("up", false),
+            ("same", true),
+            ("gone", true),
+        ]);
+        let new = data(&[
+            ("down", false),
+            ("up", true),
+            ("same", true),
+            ("added", false),
+        ]);
+        assert_eq!(
+            state_transitions(&old, &new),
+            vec![
+                StateTransition {
+                    domain: String::from("down"),
+                    old_state: "healthy",
+                    new_state: "unhealthy",
+                    time,
+                },
+                StateTransition {
+                    domain: String::from("up"),
+                    old_state: "unhealthy",
+                    new_state: "healthy",
+                    time,
+                },
+            ]
+        );
+        // startup without a cache file
+        assert!(state_transitions(&data(&[]), &new).is_empty());
+    }
+
+    #[tokio::test]
+    async fn state_webhook_payload() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let router = Router::new().route(
+            "/hook",
+            axum::routing::post(|body: String| async move {
+                sender.send(body).unwrap();
+            }),
+        );
+        let url = format!("{}/hook", mock_instance(router).await);
+        let transition = StateTransition {
+            domain: String::from("nitter.example.com"),
+            old_state: "healthy",
+            new_state: "unhealthy",
+            time: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        };
+        let scanner = test_scanner(sea_orm::DatabaseConnection::Disconnected);
+        let transitions = [transition];
+        scanner.send_state_webhook(&url, &transitions).await;
+        let payload: serde_json::Value =
+            serde_json::from_str(&receiver.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "domain": "nitter.example.com",
+                "old_state": "healthy",
+                "new_state": "unhealthy",
+                "time": "2023-11-14T22:13:20Z",
+            })
+        );
+        // unreachable webhooks are only logged
+        scanner
+            .send_state_webhook("http://127.0.0.1:1/hook", &transitions)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn state_webhook_unproxied() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let router = Router::new().route(
+            "/hook",
+            axum::routing::post(|body: String| async move {
+                sender.send(body).unwrap();
+            }),
+        );
+        let url = mock_instance(router)
+            .await
+            .replace("127.0.0.1", "localhost");
+        let mut config = (*Config::test_defaults()).clone();
+        // probes would fail through this proxy
+        config.http_proxy = Some(String::from("http://127.0.0.1:1"));
+        let scanner = test_scanner_with(sea_orm::DatabaseConnection::Disconnected, config);
+        let transition = StateTransition {
+            domain: String::from("nitter.example.com"),
+            old_state: "healthy",
+            new_state: "unhealthy",
+            time: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        };
+        scanner
+            .send_state_webhook(&format!("{url}/hook"), &[transition])
+            .await;
+        assert!(receiver.try_recv().is_ok());
+        // not listed as host connection
+        assert!(scanner
+            .inner
+            .app_state
+            .connection_stats
+            .read()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn cache_file_roundtrip() {
+        let path = std::env::temp_dir().join(format!("nitter-cache-{}.json", std::process::id()));
+        let data = CacheData {
+            hosts: vec![cache_host("nitter.example.com", 42)],
+            last_update: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            latest_commit: String::from("abc"),
+        };
+        store_cache_file(&path, &data).await.unwrap();
+        let loaded = load_cache_file(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.last_update, data.last_update);
+        assert_eq!(loaded.latest_commit, data.latest_commit);
+        assert_eq!(
+            serde_json::to_value(&loaded.hosts).unwrap(),
+            serde_json::to_value(&data.hosts).unwrap()
+        );
+        // missing file
+        assert!(matches!(
+            load_cache_file(&path).await,
+            Err(ScannerError::CacheFile(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn points_history() {
+        let db = db_memory().await;
+        let host = insert_host(&db, new_host("a", "https://a")).await;
+        let scanner = test_scanner(db);
+        let history = || async {
+            PointsHistory::find()
+                .order_by_asc(points_history::Column::Time)
+                .all(&scanner.inner.db)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|v| (v.host, v.time, v.points))
+                .collect::<Vec<_>>()
+        };
+        let data = |time: i64, points: i32| CacheData {
+            // unknown hosts are skipped
+            hosts: vec![cache_host("a", points), cache_host("unknown", 1)],
+            last_update: Utc.timestamp_opt(time, 0).unwrap(),
+            latest_commit: String::new(),
+        };
+        let hour = 1_699_999_200;
+        scanner.store_points(&data(hour + 800, 10)).await.unwrap();
+        // latest value of the hour is kept
+        scanner.store_points(&data(hour + 900, 20)).await.unwrap();
+        scanner.store_points(&data(hour + 3600, 30)).await.unwrap();
+        assert_eq!(
+            history().await,
+            vec![(host.id, hour, 20), (host.id, hour + 3600, 30)]
+        );
+
+        let now = Utc::now().timestamp();
+        scanner.store_points(&data(now, 40)).await.unwrap();
+        scanner.cleanup_points().await.unwrap();
+        assert_eq!(
+            history().await,
+            vec![(host.id, now - now.rem_euclid(3600), 40)]
+        );
+    }
+
+    #[tokio::test]
+    async fn hosts_enabled_excludes_force_disabled() {
+        let db = db_memory().await;
+        for (domain, enabled, force_disabled) in [
+            ("public", true, false),
+            ("hidden", true, true),
+            ("disabled", false, false),
+        ] {
+            insert_host(
+                &db,
+                host::ActiveModel {
+                    enabled: ActiveValue::Set(enabled),
+                    force_disabled: ActiveValue::Set(force_disabled),
+                    ..new_host(domain, &format!("https://{domain}"))
+                },
+            )
+            .await;
+        }
+        let scanner = test_scanner(db);
+        let hosts = scanner
+            .query_hosts_enabled(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].domain, "public");
+    }
+
+    #[tokio::test]
+    async fn last_healthy() {
+        let db = db_memory().await;
+        let mut hosts = Vec::new();
+        for (domain, enabled) in [("up", true), ("down", true), ("disabled", false)] {
+            let host = insert_host(
+                &db,
+                host::ActiveModel {
+                    enabled: ActiveValue::Set(enabled),
+                    ..new_host(domain, &format!("https://{domain}"))
+                },
+            )
+            .await;
+            hosts.push(host.id);
+        }
+        for (host, time, healthy) in [
+            (hosts[0], 10, true),
+            (hosts[0], 20, true),
+            (hosts[0], 30, false),
+            (hosts[1], 10, false),
+            (hosts[2], 10, true),
+        ] {
+            health_check::ActiveModel {
+                time: ActiveValue::Set(time),
+                host: ActiveValue::Set(host),
+                resp_time: ActiveValue::Set(None),
+                healthy: ActiveValue::Set(healthy),
+                response_code: ActiveValue::Set(None),
+                blocked: ActiveValue::Set(false),
+            }
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+        let scanner = test_scanner(db);
+        let res = scanner.query_last_healthy(&scanner.inner.db).await.unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[&hosts[0]].timestamp(), 20);
+    }
+
+    #[tokio::test]
+    async fn blocked_not_counted() {
+        let db = db_memory().await;
+        let host = insert_host(&db, new_host("blocking", "https://blocking")).await;
+        for (time, healthy, blocked) in [
+            (10, true, false),
+            (20, false, true),
+            (30, false, true),
+            (40, false, false),
+        ] {
+            health_check::ActiveModel {
+                time: ActiveValue::Set(time),
+                host: ActiveValue::Set(host.id),
+                resp_time: ActiveValue::Set(None),
+                healthy: ActiveValue::Set(healthy),
+                response_code: ActiveValue::Set(None),
+                blocked: ActiveValue::Set(blocked),
+            }
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+        let scanner = test_scanner(db);
+        let from = Utc.timestamp_opt(0, 0).unwrap();
+        let to = Utc.timestamp_opt(100, 0).unwrap();
+        let stats = scanner
+            .query_stats_range(&scanner.inner.db, from, to)
+            .await
+            .unwrap();
+        assert_eq!(stats[&host.id].good, 1);
+        assert_eq!(stats[&host.id].total, 2);
+        let percentage = scanner
+            .query_healthy_percentage(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(percentage[&host.id].healthy, 50);
+        assert_eq!(percentage[&host.id].checks, 2);
+        // only blocked checks in the range
+        let from = Utc.timestamp_opt(15, 0).unwrap();
+        let to = Utc.timestamp_opt(35, 0).unwrap();
+        let stats = scanner
+            .query_stats_range(&scanner.inner.db, from, to)
+            .await
+            .unwrap();
+        assert!(!stats.contains_key(&host.id));
+    }
+
+    /// Previous per host query, as reference for the grouped one
+    async fn latest_health_checks_single(
+        db: &sea_orm::DatabaseConnection,
+        amount: i32,
+        host: i32,
+    ) -> Vec<(String, bool)> {
+        #[derive(Debug, FromQueryResult)]
+        pub struct HostHealthCheck {
+            healthy: bool,
+            time: i64,
+        }
+        let health_checks = HostHealthCheck::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"SELECT healthy, time FROM health_check u
             JOIN host h ON h.id = u.host
             WHERE h.enabled = true AND host = $1
             ORDER BY time DESC
             LIMIT $2"#,
-                [host.into(), amount.into()],
-            ))
-            .all(&self.inner.db)
-            .await?;
-        // transform to correct time format
-        let health_checks: Vec<_> = health_checks
+            [host.into(), amount.into()],
+        ))
+        .all(db)
+        .await
+        .unwrap();
+        health_checks
             .into_iter()
             .rev()
-            .map(|entry| {
-                let time = Utc.timestamp_opt(entry.time, 0).unwrap();
-                (time.format("%Y.%m.%d %H:%M").to_string(), entry.healthy)
-            })
+            .map(|entry| (format_check_time(entry.time), entry.healthy))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn latest_health_checks_grouped() {
+        let db = db_memory().await;
+        let mut hosts = Vec::new();
+        for (domain, enabled, checks) in [
+            ("many", true, 30),
+            ("few", true, 3),
+            ("none", true, 0),
+            ("disabled", false, 5),
+        ] {
+            let host = insert_host(
+                &db,
+                host::ActiveModel {
+                    enabled: ActiveValue::Set(enabled),
+                    ..new_host(domain, &format!("https://{domain}"))
+                },
+            )
+            .await;
+            for i in 0..checks {
+                health_check::ActiveModel {
+                    time: ActiveValue::Set(1_700_000_000 + i * 300),
+                    host: ActiveValue::Set(host.id),
+                    resp_time: ActiveValue::Set(None),
+                    healthy: ActiveValue::Set(i % 3 != 0),
+                    response_code: ActiveValue::Set(None),
+                    blocked: ActiveValue::Set(false),
+                }
+                .insert(&db)
+                .await
+                .unwrap();
+            }
+            hosts.push(host.id);
+        }
+        let scanner = test_scanner(db);
+        let grouped = scanner
+            .query_latest_health_checks(&scanner.inner.db, 22)
+            .await
+            .unwrap();
+        for host in &hosts {
+            let single = latest_health_checks_single(&scanner.inner.db, 22, *host).await;
+            assert_eq!(
+                grouped.get(host).cloned().unwrap_or_default(),
+                single,
+                "host {host}"
+            );
+        }
+        assert_eq!(grouped[&hosts[0]].len(), 22);
+        assert_eq!(grouped[&hosts[1]].len(), 3);
+        assert!(!grouped.contains_key(&hosts[2]));
+        assert!(!grouped.contains_key(&hosts[3]));
+    }
+
+    #[tokio::test]
+    async fn public_last_errors() {
+        let db = db_memory().await;
+        let mut hosts = Vec::new();
+        for (domain, public) in [("public", true), ("private", false)] {
+            let host = insert_host(
+                &db,
+                host::ActiveModel {
+                    public_last_error: ActiveValue::Set(public),
+                    ..new_host(domain, &format!("https://{domain}"))
+                },
+            )
+            .await;
+            for (time, message) in [(10, "Captcha detected"), (20, "failed to fetch")] {
+                entities::check_errors::ActiveModel {
+                    time: ActiveValue::Set(time),
+                    host: ActiveValue::Set(host.id),
+                    message: ActiveValue::Set(message.to_owned()),
+                    http_body: ActiveValue::Set(Some(String::from("<html>secret</html>"))),
+                    http_status: ActiveValue::Set(Some(502)),
+                }
+                .insert(&db)
+                .await
+                .unwrap();
+            }
+            hosts.push(host.id);
+        }
+        let scanner = test_scanner(db);
+        let errors = scanner
+            .query_public_last_errors(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[&hosts[0]], "HTTP 502");
+    }
+
+    #[tokio::test]
+    async fn single_flight() {
+        let flight = SingleFlight::default();
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let job = || async {
+            runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Ok(())
+        };
+        // second waits for the running first one, third is covered by the second
+        let (a, b, c) = tokio::join!(flight.run(job), flight.run(job), flight.run(job));
+        assert!(a.unwrap());
+        assert!(b.unwrap());
+        assert!(!c.unwrap());
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // sequential runs are never coalesced
+        assert!(flight.run(job).await.unwrap());
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        // failed runs don't count
+        let failing = || async { Err(ScannerError::GitBranch) };
+        assert!(flight.run(failing).await.is_err());
+    }
+
+    #[test]
+    fn error_categories() {
+        for (message, status, expected) in [
+            ("Captcha detected", Some(403), "captcha"),
+            ("profile content mismatch", Some(200), "profile mismatch"),
+            ("No timeline div found!", Some(200), "profile mismatch"),
+            (
+                r#"Instance up, but content unavailable: User "jack" not found"#,
+                Some(200),
+                "content unavailable",
+            ),
+            (
+                "profile post count too low",
+                Some(200),
+                "too few profile posts",
+            ),
+            (
+                "error sending request for url (https://example.com/jack): operation timed out",
+                None,
+                "timeout",
+            ),
+            ("failed to fetch", Some(500), "HTTP 500"),
+            (
+                "error sending request for url (https://example.com/jack): dns error",
+                None,
+                "connection failed",
+            ),
+        ] {
+            assert_eq!(error_category(message, status), expected, "{message}");
+        }
+    }
+
+    fn entry(host: i32, ping: Option<i32>) -> PingEntry {
+        PingEntry { host, ping }
+    }
+
+    #[test]
+    fn aggregate_pings_avg() {
+        let entries = [
+            entry(1, Some(10)),
+            entry(1, Some(20)),
+            entry(1, None),
+            entry(1, Some(30)),
+            entry(2, None),
+            entry(2, None),
+            entry(3, Some(7)),
+            entry(4, None),
+            entry(4, Some(5)),
+            entry(4, Some(15)),
+        ];
+        let res = aggregate_pings(&entries, 0, None);
+        assert_eq!(res.len(), 4);
+
+        let host = &res[&1];
+        assert_eq!(host.avg, Some(20));
+        assert_eq!(host.min, Some(10));
+        assert_eq!(host.max, Some(30));
+        assert_eq!(host.p50, Some(20));
+        assert_eq!(host.p95, Some(30));
+        assert_eq!(host.pings, vec![Some(10), Some(20), None, Some(30)]);
+
+        // all null
+        let host = &res[&2];
+        assert_eq!(host.avg, None);
+        assert_eq!(host.min, None);
+        assert_eq!(host.max, None);
+        assert_eq!(host.p50, None);
+        assert_eq!(host.p95, None);
+        assert_eq!(host.pings, vec![None, None]);
+
+        // single sample
+        let host = &res[&3];
+        assert_eq!(host.avg, Some(7));
+        assert_eq!(host.min, Some(7));
+        assert_eq!(host.max, Some(7));
+        assert_eq!(host.p50, Some(7));
+        assert_eq!(host.p95, Some(7));
+
+        // leading null
+        let host = &res[&4];
+        assert_eq!(host.avg, Some(10));
+        assert_eq!(host.min, Some(5));
+        assert_eq!(host.max, Some(15));
+    }
+
+    #[test]
+    fn ping_percentiles() {
+        let sorted: Vec<i32> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 50), Some(50));
+        assert_eq!(percentile(&sorted, 95), Some(95));
+        assert_eq!(percentile(&[3, 9], 50), Some(3));
+        assert_eq!(percentile(&[3, 9], 95), Some(9));
+        assert_eq!(percentile::<i32>(&[], 95), None);
+    }
+
+    #[test]
+    fn aggregate_pings_empty() {
+        assert!(aggregate_pings(&[], 10, Some(2.0)).is_empty());
+    }
+
+    #[test]
+    fn aggregate_pings_outliers() {
+        let entries: Vec<_> = [120, 100, 9800, 110, 90, 105, 95, 115, 100, 105]
+            .into_iter()
+            .map(|v| entry(1, Some(v)))
             .collect();
-        Ok(health_checks)
+
+        let host = &aggregate_pings(&entries, 0, None)[&1];
+        assert_eq!(host.avg, Some(1074));
+
+        let host = &aggregate_pings(&entries, 10, None)[&1];
+        assert_eq!(host.avg, Some(104));
+        // min/max and percentiles stay raw
+        assert_eq!(host.min, Some(90));
+        assert_eq!(host.max, Some(9800));
+        assert_eq!(host.p95, Some(9800));
+
+        let host = &aggregate_pings(&entries, 0, Some(2.0))[&1];
+        assert_eq!(host.avg, Some(104));
+        assert_eq!(host.max, Some(9800));
+
+        // never drops everything
+        assert_eq!(trimmed_avg(&[50], 90, Some(0.1)), Some(50));
+        assert_eq!(trimmed_avg(&[10, 20], 99, None), Some(10));
+        assert_eq!(trimmed_avg(&[], 10, None), None);
+        // no outliers, nothing dropped
+        assert_eq!(trimmed_avg(&[10, 10, 10], 0, Some(1.0)), Some(10));
     }
 }
@@ -1,5 +1,6 @@
 use entities::check_errors;
 use entities::host;
+use entities::points_history;
 use sea_orm::ColumnTrait;
 use sea_orm::EntityTrait;
 use sea_orm::Order;
@@ -28,6 +29,23 @@ impl Scanner {
     /// Perform cleanup of outdated data
     async fn cleanup(&self) -> Result<()> {
         self.cleanup_errors().await?;
+        self.cleanup_points().await?;
+        Ok(())
+    }
+
+    /// Remove points history entries older than the retention
+    pub(crate) async fn cleanup_points(&self) -> Result<()> {
+        let retention = chrono::Duration::from_std(self.inner.config.points_retention)
+            .unwrap_or(chrono::Duration::max_value());
+        let cutoff = self
+            .now()
+            .checked_sub_signed(retention)
+            .map_or(i64::MIN, |v| v.timestamp());
+        let res = points_history::Entity::delete_many()
+            .filter(points_history::Column::Time.lt(cutoff))
+            .exec(&self.inner.db)
+            .await?;
+        tracing::debug!(deleted_points = res.rows_affected);
         Ok(())
     }
 
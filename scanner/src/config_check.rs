@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+//! Startup validation of the scanner config, before anything is fetched or written
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use entities::state::scanner::Config;
+use regex::{Regex, RegexBuilder};
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+
+/// Validate all regexes, URLs and paths of *config*, returns every problem found
+///
+/// Messages name the environment variable of the invalid value.
+/// Empty values are skipped, missing required values are reported while reading the config.
+pub fn validate_config(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if let Err(e) = RegexBuilder::new(&config.rss_content)
+        .case_insensitive(true)
+        .build()
+    {
+        errors.push(format!("`RSS_CONTENT` is not a valid regex: {e}"));
+    }
+    for (name, pattern) in [
+        ("EXCLUDE_HOSTS_REGEX", &config.exclude_hosts_regex),
+        ("REQUIRED_VERSION_REGEX", &config.version_pattern),
+    ] {
+        if let Some(Err(e)) = pattern.as_deref().map(Regex::new) {
+            errors.push(format!("`{name}` is not a valid regex: {e}"));
+        }
+    }
+
+    for (name, url) in [
+        ("NITTER_INSTANCELIST", Some(&config.instance_list_url)),
+        ("SITE_URL", Some(&config.website_url)),
+        (
+            "INSTANCE_LIST_CHALLENGE_SOLVER_URL",
+            config.instance_list_challenge_solver.as_ref(),
+        ),
+        ("STATE_WEBHOOK_URL", config.state_webhook_url.as_ref()),
+    ] {
+        if let Some(url) = url.filter(|v| !v.is_empty()) {
+            if let Err(e) = check_http_url(url) {
+                errors.push(format!("`{name}` is not a valid http(s) URL: {e}"));
+            }
+        }
+    }
+    for url in &config.additional_hosts {
+        if let Err(e) = check_http_url(url) {
+            errors.push(format!("invalid `ADDITIONAL_HOSTS` entry {url:?}: {e}"));
+        }
+    }
+    // git also accepts local repositories
+    let git_url = &config.source_git_url;
+    if !git_url.is_empty() && Url::parse(git_url).is_err() && !Path::new(git_url).exists() {
+        errors.push(String::from(
+            "`ORIGIN_SOURCE_GIT_URL` is neither a valid URL nor an existing path",
+        ));
+    }
+    if let Some(Err(e)) = config.http_proxy.as_deref().map(reqwest::Proxy::all) {
+        errors.push(format!("`HTTP_PROXY_URL` is not a valid proxy URL: {e}"));
+    }
+
+    for (name, path) in [
+        ("PROFILE_PATH", &config.profile_path),
+        ("RSS_PATH", &config.rss_path),
+        ("ABOUT_PATH", &config.about_path),
+    ] {
+        if !path.is_empty() && !path.starts_with('/') {
+            errors.push(format!("`{name}` has to start with a `/`"));
+        }
+    }
+    for probe in &config.profile_rotation {
+        if !probe.path.starts_with('/') {
+            errors.push(format!(
+                "invalid `PROFILE_ROTATION` path {:?}, has to start with a `/`",
+                probe.path
+            ));
+        }
+    }
+    for agent in &config.probe_user_agents {
+        if HeaderValue::from_str(agent).is_err() {
+            errors.push(format!("invalid `PROBE_USER_AGENTS` entry {agent:?}"));
+        }
+    }
+
+    if let Some(path) = &config.cache_file {
+        if let Err(e) = check_writable_dir(path) {
+            errors.push(format!(
+                "`CACHE_FILE` {} can't be written: {e}",
+                path.display()
+            ));
+        }
+    }
+    errors
+}
+
+fn check_http_url(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| e.to_string())?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!("unsupported scheme {scheme:?}")),
+    }
+}
+
+/// Whether the folder of *file* exists and a file can be created in it
+fn check_writable_dir(file: &Path) -> std::io::Result<()> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let probe = dir.join(".nitter-status-write-check");
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)?;
+    std::fs::remove_file(probe)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn defaults_valid() {
+        assert_eq!(
+            validate_config(&Config::test_defaults()),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let mut config = (*Config::test_defaults()).clone();
+        config.rss_content = String::from("<rss(");
+        config.version_pattern = Some(String::from("["));
+        config.instance_list_url = String::from("ftp://example.com/list");
+        config.rss_path = String::from("jack/rss");
+        config.cache_file = Some("/nonexistent-dir/cache.json".into());
+        let errors = validate_config(&config);
+        assert_eq!(errors.len(), 5, "{errors:?}");
+        assert!(errors[0].starts_with("`RSS_CONTENT`"));
+        assert!(errors[1].starts_with("`REQUIRED_VERSION_REGEX`"));
+        assert!(errors[2].starts_with("`NITTER_INSTANCELIST`"));
+        assert!(errors[3].starts_with("`RSS_PATH`"));
+        assert!(errors[4].starts_with("`CACHE_FILE`"));
+    }
+}
@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+//! Minimal XML well-formedness check for RSS and Atom feeds
+
+/// Whether *content* is a well-formed XML feed with at least one entry
+///
+/// Requires a single `<rss>` or `<feed>` root with an `<item>` or `<entry>` element inside,
+/// namespace prefixes like `<atom:feed>` are accepted.
+/// Checks balanced tags and quoting, but not entities or the full XML grammar.
+pub(crate) fn is_valid_feed(content: &str) -> bool {
+    let mut stack: Vec<&str> = Vec::new();
+    let mut root = None;
+    let mut has_entry = false;
+    let mut rest = content.trim_start_matches('\u{feff}');
+    loop {
+        let (text, tag_start) = match rest.find('<') {
+            Some(start) => (&rest[..start], Some(start)),
+            None => (rest, None),
+        };
+        // no text outside of the root element
+        if stack.is_empty() && !text.trim().is_empty() {
+            return false;
+        }
+        let Some(start) = tag_start else {
+            break;
+        };
+        rest = &rest[start..];
+        let end = if rest.starts_with("<?") {
+            rest.find("?>").map(|v| v + 2)
+        } else if rest.starts_with("<!--") {
+            rest.find("-->").map(|v| v + 3)
+        } else if rest.starts_with("<![CDATA[") {
+            if stack.is_empty() {
+                return false;
+            }
+            rest.find("]]>").map(|v| v + 3)
+        } else if rest.starts_with("<!") {
+            // doctype, only before the root
+            if root.is_some() {
+                return false;
+            }
+            rest.find('>').map(|v| v + 1)
+        } else if let Some(tag) = rest.strip_prefix("</") {
+            let Some(end) = tag.find('>') else {
+                return false;
+            };
+            if stack.pop() != Some(tag[..end].trim_end()) {
+                return false;
+            }
+            Some(end + 3)
+        } else {
+            let Some(end) = tag_end(rest) else {
+                return false;
+            };
+            let tag = &rest[1..end - 1];
+            let self_closing = tag.ends_with('/');
+            let name = tag
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default();
+            if name.is_empty() {
+                return false;
+            }
+            match root {
+                None => root = Some(name),
+                // second root element
+                Some(_) if stack.is_empty() => return false,
+                Some(_) => {}
+            }
+            has_entry |= matches!(local_name(name), "item" | "entry");
+            if !self_closing {
+                stack.push(name);
+            }
+            Some(end)
+        };
+        let Some(end) = end else {
+            return false;
+        };
+        rest = &rest[end..];
+    }
+    stack.is_empty() && matches!(root.map(local_name), Some("rss" | "feed")) && has_entry
+}
+
+/// Element *name* without its namespace prefix
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+/// End of the start tag at the beginning of *tag*, after its `>`, skipping quoted attribute values
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_feeds() {
+        let rss = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss xmlns:atom="http://www.w3.org/2005/Atom" version="2.0">
+  <channel>
+    <atom:link href="https://nitter.example.com/jack/rss" rel="self" type="application/rss+xml" />
+    <title>jack / @jack</title>
+    <!-- comment with <tags> -->
+    <item>
+      <title><![CDATA[just setting up my <twttr>]]></title>
+      <link>https://nitter.example.com/jack/status/20#m</link>
+    </item>
+  </channel>
+</rss>
+"#;
+        assert!(is_valid_feed(rss));
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title a='>'>x</title><entry><id>1</id></entry></feed>"#;
+        assert!(is_valid_feed(atom));
+        let prefixed = r#"<atom:feed xmlns:atom="http://www.w3.org/2005/Atom"><atom:entry><atom:id>1</atom:id></atom:entry></atom:feed>"#;
+        assert!(is_valid_feed(prefixed));
+    }
+
+    #[test]
+    fn invalid_feeds() {
+        for content in [
+            // regex match inside of a html page
+            r#"<html><body><p>&lt;rss xmlns:atom</p><item></item></body></html>"#,
+            r#"<x:html xmlns:x="http://www.w3.org/1999/xhtml"><item/></x:html>"#,
+            // no entries
+            r#"<rss xmlns:atom="http://www.w3.org/2005/Atom" version="2.0"></rss>"#,
+            r#"<atom:feed xmlns:atom="http://www.w3.org/2005/Atom"><atom:title>x</atom:title></atom:feed>"#,
+            // unbalanced
+            r#"<rss><channel><item></channel></rss>"#,
+            r#"<rss><channel><item></item></channel>"#,
+            r#"<atom:feed><atom:entry></entry></atom:feed>"#,
+            // text or second element after the root
+            r#"<rss><item/></rss>trailing"#,
+            r#"<rss><item/></rss><rss></rss>"#,
+            // unterminated
+            r#"<rss><item attr="></item></rss>"#,
+            "<rss><item/><!-- </rss>",
+            "",
+        ] {
+            assert!(!is_valid_feed(content), "{content}");
+        }
+    }
+}
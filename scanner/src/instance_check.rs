@@ -1,11 +1,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //! Instance health/uptime checking code
-use std::time::Instant;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
 use entities::state::error_cache::HostError;
+use entities::state::scanner::HealthSignal;
 use entities::{check_errors, health_check};
 use entities::{host, prelude::*};
+use reqwest::header::{HeaderValue, USER_AGENT};
 use reqwest::Url;
 use sea_orm::prelude::DateTimeUtc;
 use sea_orm::ColumnTrait;
@@ -16,9 +19,107 @@ use tokio::task::JoinSet;
 use tracing::instrument;
 
 use crate::about_parser::AboutParsed;
+use crate::cache_update::percentile;
+use crate::feed_parser::is_valid_feed;
+use crate::parse_instance_url;
+use crate::profile_parser::ProfileParseError;
+use crate::FetchError;
 use crate::Result;
 use crate::Scanner;
 
+/// Successful profile check
+#[derive(Debug)]
+pub(crate) struct HealthyCheck {
+    /// Response time in ms
+    pub resp_time: i32,
+    pub http_code: u16,
+}
+
+/// Failed profile check
+#[derive(Debug)]
+pub(crate) struct FailedCheck {
+    pub error: HostError,
+    /// Response time in ms, if the host responded at all
+    pub resp_time: Option<i32>,
+    /// Host responded that all of its accounts are rate limited
+    pub rate_limited: bool,
+    /// Profile was found, but with fewer posts than required
+    pub low_posts: bool,
+    /// Host blocked the check, it may still be up for other users
+    pub blocked: bool,
+    /// Host is up, but served an error page like "User not found" instead of the profile
+    pub content_unavailable: bool,
+}
+
+/// Result of an RSS feed check
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct RssCheck {
+    pub available: bool,
+    /// Whether the feed parsed, only set in strict mode
+    pub valid: Option<bool>,
+    /// Response time in ms, if the host responded at all
+    pub resp_time: Option<i32>,
+    pub http_status: Option<u16>,
+}
+
+/// Failure of the about page version check
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum VersionCheckError {
+    /// Host responded with a captcha/JS challenge
+    Captcha,
+    /// Fetching or parsing failed
+    Failed,
+}
+
+/// Result of all checks against a single instance
+pub struct InstanceReport {
+    url: String,
+    profile: std::result::Result<HealthyCheck, FailedCheck>,
+    rss: bool,
+    version: Option<AboutParsed>,
+    connectivity: Option<host::Connectivity>,
+}
+
+impl std::fmt::Display for InstanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Instance: {}", self.url)?;
+        match &self.profile {
+            Ok(check) => writeln!(
+                f,
+                "Profile: healthy, status {} in {}ms",
+                check.http_code, check.resp_time
+            )?,
+            Err(failed) => {
+                write!(f, "Profile: unhealthy, {}", failed.error.message)?;
+                if let Some(status) = failed.error.http_status {
+                    write!(f, ", status {status}")?;
+                }
+                if let Some(resp_time) = failed.resp_time {
+                    write!(f, " in {resp_time}ms")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        writeln!(f, "RSS: {}", self.rss)?;
+        match &self.version {
+            Some(version) => writeln!(f, "Version: {} ({})", version.version_name, version.url)?,
+            None => writeln!(f, "Version: not found")?,
+        }
+        match &self.connectivity {
+            Some(connectivity) => writeln!(f, "Connectivity: {connectivity:?}"),
+            None => writeln!(f, "Connectivity: none"),
+        }
+    }
+}
+
+/// Accepted Content-Types for profile pages
+const PROFILE_CONTENT_TYPES: &[&str] = &["text/html"];
+/// Accepted Content-Types for RSS feeds
+const RSS_CONTENT_TYPES: &[&str] = &["application/rss+xml", "application/xml", "text/xml"];
+
+/// Timeout for the HEAD pre-check, should fail faster than the full profile fetch
+const PRECHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
 impl Scanner {
     /// Check uptime for host and create a new uptime entry in the database
     pub(crate) async fn check_uptime(&mut self) -> Result<()> {
@@ -39,187 +140,538 @@ impl Scanner {
                 .find(|v| v.host == model.id)
                 .map_or(false, |check| !check.healthy);
             join_set.spawn(async move {
+                let start = Instant::now();
                 scanner.health_check_host(model, muted_host).await;
+                start.elapsed().as_millis()
             });
         }
         // wait till all of them are finished, preventing DoS
         let tasks = join_set.len();
-        while let Some(_) = join_set.join_next().await {}
+        let mut durations = Vec::with_capacity(tasks);
+        while let Some(res) = join_set.join_next().await {
+            if let Ok(took_ms) = res {
+                durations.push(took_ms);
+            }
+        }
         let end = Instant::now();
         let took_ms = end.saturating_duration_since(start).as_millis();
-        *self.inner.last_uptime_check.lock().unwrap() = Utc::now();
-        tracing::debug!(hosts = tasks, took_ms = took_ms, "checked uptime");
+        *self.inner.last_uptime_check.lock().unwrap() = self.now();
+        // per host probe durations, to spot slow hosts dominating the check time
+        durations.sort_unstable();
+        tracing::debug!(
+            hosts = tasks,
+            took_ms = took_ms,
+            host_p50_ms = percentile(&durations, 50),
+            host_p95_ms = percentile(&durations, 95),
+            host_max_ms = durations.last(),
+            "checked uptime"
+        );
         Ok(())
     }
 
     #[instrument]
     async fn health_check_host(&self, host: host::Model, muted: bool) {
         let now = Utc::now();
-        let mut url = match Url::parse(&host.url) {
-            Err(e) => {
-                if !muted {
-                    tracing::error!(error=?e, url=host.url,"failed to parse instance URL");
-                }
+        let result = self.check_profile(&host.url, muted).await;
+        self.track_rate_limited(&host, result.as_ref().is_err_and(|e| e.rate_limited));
+        self.track_content_unavailable(
+            &host,
+            result.as_ref().is_err_and(|e| e.content_unavailable),
+        );
+        let result = self.tolerate_low_posts(&host, result);
+        let result = self.require_signals(&host, result);
+        match result {
+            Ok(check) => self.insert_healthy_check(host.id, now, check).await,
+            Err(failed) => {
                 self.insert_failed_health_check(
                     host.id,
                     now,
-                    HostError::new_message(format!("Not a valid URL")),
-                    None,
+                    failed.error,
+                    failed.resp_time,
+                    failed.blocked,
                 )
-                .await;
-                return;
+                .await
+            }
+        }
+    }
+
+    /// Count consecutive rate limited checks, warning once the account pool seems exhausted
+    fn track_rate_limited(&self, host: &host::Model, rate_limited: bool) {
+        let mut counters = self.inner.rate_limited.lock().unwrap();
+        if !rate_limited {
+            counters.remove(&host.id);
+            return;
+        }
+        let count = counters.entry(host.id).or_default();
+        *count += 1;
+        if *count == self.inner.config.rate_limit_threshold {
+            tracing::warn!(
+                host = host.domain,
+                checks = *count,
+                "account pool exhausted, host is rate limited"
+            );
+        }
+    }
+
+    /// Remember whether the latest check of *host* found an error page instead of the profile
+    fn track_content_unavailable(&self, host: &host::Model, unavailable: bool) {
+        let mut hosts = self.inner.content_unavailable.lock().unwrap();
+        match unavailable {
+            true => {
+                if hosts.insert(host.id) {
+                    tracing::info!(host = host.domain, "host is up, but content unavailable");
+                }
+            }
+            false => {
+                hosts.remove(&host.id);
+            }
+        }
+    }
+
+    /// Whether the latest health check of *host* found an error page instead of the profile
+    pub(crate) fn is_content_unavailable(&self, host: i32) -> bool {
+        self.inner
+            .content_unavailable
+            .lock()
+            .unwrap()
+            .contains(&host)
+    }
+
+    /// Treat up to the configured amount of consecutive checks with too few profile posts as healthy
+    fn tolerate_low_posts(
+        &self,
+        host: &host::Model,
+        result: std::result::Result<HealthyCheck, FailedCheck>,
+    ) -> std::result::Result<HealthyCheck, FailedCheck> {
+        let mut counters = self.inner.low_posts.lock().unwrap();
+        match result {
+            Err(failed) if failed.low_posts => {
+                let count = counters.entry(host.id).or_default();
+                *count += 1;
+                if *count > self.inner.config.profile_posts_tolerance {
+                    return Err(failed);
+                }
+                tracing::debug!(
+                    host = host.domain,
+                    checks = *count,
+                    "tolerating profile with too few posts"
+                );
+                Ok(HealthyCheck {
+                    resp_time: failed.resp_time.unwrap_or_default(),
+                    http_code: failed.error.http_status.unwrap_or_default() as _,
+                })
+            }
+            result => {
+                counters.remove(&host.id);
+                result
+            }
+        }
+    }
+
+    /// Fail healthy checks if the host lacks any additionally required signal
+    ///
+    /// Also requires the last reported version to match the configured pattern, if any.
+    fn require_signals(
+        &self,
+        host: &host::Model,
+        result: std::result::Result<HealthyCheck, FailedCheck>,
+    ) -> std::result::Result<HealthyCheck, FailedCheck> {
+        let check = result?;
+        let missing = self
+            .inner
+            .config
+            .healthy_requires
+            .iter()
+            .find(|signal| match signal {
+                HealthSignal::Profile => false,
+                HealthSignal::Rss => !host.rss,
+                HealthSignal::Connectivity => host.connectivity.is_none(),
+            });
+        let message = match missing {
+            Some(signal) => format!("required signal missing: {signal:?}"),
+            // hosts without an about page can't report a version
+            None if host.version_neutral && host.version.is_none() => return Ok(check),
+            None => match &self.inner.version_regex {
+                Some(regex) if !host.version.as_deref().is_some_and(|v| regex.is_match(v)) => {
+                    format!(
+                        "version {:?} doesn't match the required pattern",
+                        host.version.as_deref().unwrap_or_default()
+                    )
+                }
+                _ => return Ok(check),
+            },
+        };
+        Err(FailedCheck {
+            error: HostError::new_without_body(message, check.http_code as _),
+            resp_time: Some(check.resp_time),
+            rate_limited: false,
+            low_posts: false,
+            content_unavailable: false,
+            blocked: false,
+        })
+    }
+
+    /// Whether the host was rate limited for at least the configured amount of consecutive checks
+    pub(crate) fn is_account_pool_exhausted(&self, host: i32) -> bool {
+        self.inner
+            .rate_limited
+            .lock()
+            .unwrap()
+            .get(&host)
+            .is_some_and(|count| *count >= self.inner.config.rate_limit_threshold)
+    }
+
+    /// User-Agent for the next health check request, rotating over all configured ones
+    ///
+    /// None uses the default User-Agent of the client.
+    fn probe_user_agent(&self) -> Option<&HeaderValue> {
+        let agents = &self.inner.probe_user_agents;
+        if agents.is_empty() {
+            return None;
+        }
+        let round = self.inner.user_agent_round.fetch_add(1, Ordering::Relaxed);
+        agents.get(round % agents.len())
+    }
+
+    /// Fetch and verify the profile of an instance, without storing the result
+    pub(crate) async fn check_profile(
+        &self,
+        instance_url: &str,
+        muted: bool,
+    ) -> std::result::Result<HealthyCheck, FailedCheck> {
+        let mut url = match parse_instance_url(instance_url) {
+            Err(e) => {
+                if !muted {
+                    tracing::error!(error=%e, "failed to parse instance URL");
+                }
+                return Err(FailedCheck {
+                    error: HostError::new_message(e.to_string()),
+                    resp_time: None,
+                    rate_limited: false,
+                    low_posts: false,
+                    content_unavailable: false,
+                    blocked: false,
+                });
             }
             Ok(v) => v,
         };
-        url.set_path(&self.inner.config.profile_path);
+        let round = self.inner.profile_round.fetch_add(1, Ordering::Relaxed);
+        let (profile_path, profile_name) = self.inner.config.profile_probe(round);
+        url.set_path(profile_path);
+        if self.inner.config.health_precheck {
+            let start = Instant::now();
+            if let Err(e) = self.precheck_host(&url, self.probe_user_agent()).await {
+                let took_ms = start.elapsed().as_millis();
+                if !muted {
+                    tracing::info!(
+                        host = instance_url,
+                        took = took_ms,
+                        "pre-check failed: {e}, marking as dead"
+                    );
+                }
+                let blocked = e.is_blocked();
+                let mut error = e.to_host_error();
+                error.message = format!("pre-check failed: {}", error.message);
+                return Err(FailedCheck {
+                    error,
+                    resp_time: Some(took_ms as _),
+                    rate_limited: false,
+                    low_posts: false,
+                    content_unavailable: false,
+                    blocked,
+                });
+            }
+        }
         let start = Instant::now();
-        let fetch_res = self.fetch_url(url.as_str()).await;
+        let fetch_res = self
+            .fetch_url_typed_as(url.as_str(), PROFILE_CONTENT_TYPES, self.probe_user_agent())
+            .await;
         let end = Instant::now();
         let took_ms = end.saturating_duration_since(start).as_millis();
-        match fetch_res {
+        let (http_code, content) = match fetch_res {
             Err(e) => {
                 if !muted {
                     tracing::info!(
-                        host = host.url,
+                        host = instance_url,
                         took = took_ms,
                         "couldn't ping host: {e}, marking as dead"
                     );
                 }
-                self.insert_failed_health_check(
-                    host.id,
-                    now,
-                    e.to_host_error(),
-                    Some(took_ms as _),
-                )
-                .await;
+                return Err(FailedCheck {
+                    rate_limited: e.is_rate_limited(),
+                    low_posts: false,
+                    content_unavailable: false,
+                    blocked: e.is_blocked(),
+                    error: e.to_host_error(),
+                    resp_time: Some(took_ms as _),
+                });
             }
-            Ok((http_code, content)) => {
+            Ok(v) => v,
+        };
+        if !muted {
+            tracing::trace!(host = instance_url, took = took_ms);
+        }
+        // check for valid profile
+        let profile_content = match self.inner.profile_parser.parse_profile_content(&content) {
+            Err(e) => {
                 if !muted {
-                    tracing::trace!(host = host.url, took = took_ms);
-                }
-                // check for valid profile
-                match self.inner.profile_parser.parse_profile_content(&content) {
-                    Err(e) => {
-                        if !muted {
-                            tracing::debug!(
-                                error=?e,
-                                content = content,
-                                "host doesn't contain a valid profile"
-                            );
-                        }
-                        self.insert_failed_health_check(
-                            host.id,
-                            now,
-                            HostError::new(e.to_string(), content, http_code),
-                            Some(took_ms as _),
-                        )
-                        .await;
-                    }
-                    Ok(profile_content) => {
-                        if self.inner.config.profile_name != profile_content.name
-                            || self.inner.config.profile_posts_min > profile_content.post_count
-                        {
-                            if !muted {
-                                tracing::debug!(
-                                    profile_content = ?profile_content,
-                                    "host doesn't contain expected profile content"
-                                );
-                            }
-                            self.insert_failed_health_check(
-                                host.id,
-                                now,
-                                HostError::new(
-                                    format!("profile content mismatch"),
-                                    content,
-                                    http_code,
-                                ),
-                                Some(took_ms as _),
-                            )
-                            .await;
-                        } else {
-                            // create successful uptime entry
-                            if let Err(e) = (health_check::ActiveModel {
-                                time: ActiveValue::Set(now.timestamp()),
-                                host: ActiveValue::Set(host.id),
-                                resp_time: ActiveValue::Set(Some(took_ms as _)),
-                                response_code: ActiveValue::Set(Some(http_code as _)),
-                                healthy: ActiveValue::Set(true),
-                            }
-                            .insert(&self.inner.db)
-                            .await)
-                            {
-                                tracing::error!(host=host.id, error=?e,"Failed to insert update check");
-                            }
-                        }
-                    }
+                    tracing::debug!(
+                        error=?e,
+                        content = content,
+                        "host doesn't contain a valid profile"
+                    );
                 }
+                return Err(FailedCheck {
+                    content_unavailable: matches!(e, ProfileParseError::ContentUnavailable(_)),
+                    error: HostError::new(e.to_string(), content, http_code),
+                    resp_time: Some(took_ms as _),
+                    rate_limited: false,
+                    low_posts: false,
+                    blocked: false,
+                });
             }
+            Ok(v) => v,
+        };
+        if profile_name != profile_content.name {
+            if !muted {
+                tracing::debug!(
+                    profile_path,
+                    profile_content = ?profile_content,
+                    "host doesn't contain expected profile content"
+                );
+            }
+            return Err(FailedCheck {
+                error: HostError::new(format!("profile content mismatch"), content, http_code),
+                resp_time: Some(took_ms as _),
+                rate_limited: false,
+                low_posts: false,
+                content_unavailable: false,
+                blocked: false,
+            });
+        }
+        if self.inner.config.profile_posts_min > profile_content.post_count {
+            if !muted {
+                tracing::debug!(
+                    profile_content = ?profile_content,
+                    "host profile contains too few posts"
+                );
+            }
+            return Err(FailedCheck {
+                error: HostError::new(
+                    String::from("profile post count too low"),
+                    content,
+                    http_code,
+                ),
+                resp_time: Some(took_ms as _),
+                rate_limited: false,
+                low_posts: true,
+                content_unavailable: false,
+                blocked: false,
+            });
         }
+        Ok(HealthyCheck {
+            resp_time: took_ms as _,
+            http_code,
+        })
+    }
+
+    /// Run the full check pipeline for one instance, without storing anything
+    pub(crate) async fn check_report(&self, instance_url: &str) -> InstanceReport {
+        let profile = self.check_profile(instance_url, false).await;
+        let (rss, version, connectivity) = match parse_instance_url(instance_url) {
+            Err(_) => (false, None, None),
+            Ok(mut url) => (
+                self.has_rss(&mut url, false).await,
+                self.nitter_version(&mut url, false).await.ok(),
+                self.check_connectivity(&mut url).await,
+            ),
+        };
+        InstanceReport {
+            url: instance_url.to_owned(),
+            profile,
+            rss,
+            version,
+            connectivity,
+        }
+    }
+
+    /// Cheap reachability check via HEAD request.
+    ///
+    /// Only fails for hosts that can't be reached or respond with a gateway error,
+    /// everything else is left to the full profile check.
+    async fn precheck_host(
+        &self,
+        url: &Url,
+        user_agent: Option<&HeaderValue>,
+    ) -> std::result::Result<(), FetchError> {
+        self.count_request(url.as_str());
+        let mut request = self
+            .inner
+            .client
+            .head(url.as_str())
+            .timeout(PRECHECK_TIMEOUT);
+        if let Some(user_agent) = user_agent {
+            request = request.header(USER_AGENT, user_agent.clone());
+        }
+        let res = request.send().await?;
+        let code = res.status().as_u16();
+        if (502..=504).contains(&code) || (520..=527).contains(&code) {
+            let message = res
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_owned();
+            return Err(FetchError::KnownHttpResponseStatus(code, message));
+        }
+        Ok(())
     }
 
     /// Check if rss is available
     pub(crate) async fn has_rss(&self, url: &mut Url, mute: bool) -> bool {
+        self.check_rss(url, mute).await.available
+    }
+
+    /// Check if rss is available, and in strict mode whether it's a valid feed
+    pub(crate) async fn check_rss(&self, url: &mut Url, mute: bool) -> RssCheck {
         url.set_path(&self.inner.config.rss_path);
-        match self.fetch_url(url.as_str()).await {
-            Ok((code, content)) => match self.inner.rss_check_regex.is_match(&content) {
-                true => return true,
-                false => {
-                    if !mute {
-                        // 404 = disabled
-                        tracing::debug!(
-                            url = url.as_str(),
-                            code = code,
-                            content = content,
-                            "rss content not found"
-                        );
+        let strict = self.inner.config.rss_strict;
+        let start = Instant::now();
+        let res = self.fetch_url_typed(url.as_str(), RSS_CONTENT_TYPES).await;
+        let took_ms = start.elapsed().as_millis() as i32;
+        match res {
+            Ok((code, content)) => {
+                let (available, valid) = match self.inner.rss_check_regex.is_match(&content) {
+                    true => {
+                        let valid = strict.then(|| is_valid_feed(&content));
+                        if valid == Some(false) && !mute {
+                            tracing::debug!(
+                                url = url.as_str(),
+                                "rss content matched, but no valid feed"
+                            );
+                        }
+                        (true, valid)
+                    }
+                    false => {
+                        if !mute {
+                            // 404 = disabled
+                            tracing::debug!(
+                                url = url.as_str(),
+                                code = code,
+                                content = content,
+                                "rss content not found"
+                            );
+                        }
+                        (false, strict.then_some(false))
                     }
-                    return false;
+                };
+                RssCheck {
+                    available,
+                    valid,
+                    resp_time: Some(took_ms),
+                    http_status: Some(code),
                 }
-            },
+            }
             Err(e) => {
                 if !mute && e.http_status_code() != Some(404) {
                     tracing::debug!(error=?e,url=url.as_str(),"fetching rss feed failed");
                 }
-                return false;
+                let http_status = e.http_status_code();
+                RssCheck {
+                    available: false,
+                    valid: strict.then_some(false),
+                    // no response time without any response
+                    resp_time: http_status.map(|_| took_ms),
+                    http_status,
+                }
             }
         }
     }
 
     /// Check nitter version
-    pub(crate) async fn nitter_version(&self, url: &mut Url, mute: bool) -> Option<AboutParsed> {
+    pub(crate) async fn nitter_version(
+        &self,
+        url: &mut Url,
+        mute: bool,
+    ) -> std::result::Result<AboutParsed, VersionCheckError> {
         url.set_path(&self.inner.config.about_path);
         match self.fetch_url(url.as_str()).await {
             Ok((code, content)) => match self.inner.about_parser.parse_about_version(&content) {
-                Ok(v) => Some(v),
+                Ok(v) => Ok(v),
                 Err(e) => {
                     if !mute {
                         tracing::debug!(url=url.as_str(),code,content,error=?e,"failed parsing version from about page");
                     }
-                    None
+                    Err(VersionCheckError::Failed)
                 }
             },
+            Err(FetchError::Captcha(_)) => {
+                if !mute {
+                    tracing::debug!(url = url.as_str(), "about page is captcha gated");
+                }
+                Err(VersionCheckError::Captcha)
+            }
             Err(e) => {
                 if !mute {
                     tracing::debug!(url=url.as_str(),error=?e,"failed fetching about page");
                 }
-                None
+                Err(VersionCheckError::Failed)
             }
         }
     }
 
+    async fn insert_healthy_check(&self, host: i32, time: DateTimeUtc, check: HealthyCheck) {
+        if self.inner.config.dry_run {
+            tracing::info!(
+                dry_run = true,
+                host,
+                resp_time = check.resp_time,
+                http_code = check.http_code,
+                "dry-run: would insert healthy check"
+            );
+            return;
+        }
+        if let Err(e) = (health_check::ActiveModel {
+            time: ActiveValue::Set(time.timestamp()),
+            host: ActiveValue::Set(host),
+            resp_time: ActiveValue::Set(Some(check.resp_time)),
+            response_code: ActiveValue::Set(Some(check.http_code as _)),
+            healthy: ActiveValue::Set(true),
+            blocked: ActiveValue::Set(false),
+        }
+        .insert(&self.inner.db)
+        .await)
+        {
+            tracing::error!(host=host, error=?e,"Failed to insert update check");
+        }
+    }
+
     async fn insert_failed_health_check(
         &self,
         host: i32,
         time: DateTimeUtc,
         host_error: HostError,
         resp_time: Option<i32>,
+        blocked: bool,
     ) {
+        if self.inner.config.dry_run {
+            tracing::info!(
+                dry_run = true,
+                host,
+                resp_time,
+                blocked,
+                message = host_error.message,
+                http_status = host_error.http_status,
+                "dry-run: would insert failed check"
+            );
+            return;
+        }
         if let Err(e) = (health_check::ActiveModel {
             time: ActiveValue::Set(time.timestamp()),
             host: ActiveValue::Set(host),
             resp_time: ActiveValue::Set(resp_time),
             healthy: ActiveValue::Set(false),
             response_code: ActiveValue::Set(host_error.http_status),
+            blocked: ActiveValue::Set(blocked),
         }
         .insert(&self.inner.db)
         .await)
@@ -240,3 +692,426 @@ impl Scanner {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{http::StatusCode, response::Html, routing::get, Router};
+    use entities::state::scanner::Config;
+    use sea_orm::DatabaseConnection;
+    use std::sync::Arc;
+
+    use crate::test::{
+        db_memory, insert_host, mock_instance, mock_nitter, new_host, test_host, test_scanner,
+        test_scanner_with,
+    };
+
+    /// Captcha page served instead of the real content
+    const CAPTCHA_BODY: &str = r#"<html><head><title>Just a moment...</title></head><body><script>window._cf_chl_opt={cType: 'managed'};</script><script src="/cdn-cgi/challenge-platform/h/b/orchestrate/chl_page/v1"></script></body></html>"#;
+
+    #[tokio::test]
+    async fn check_profile_healthy() {
+        let url = mock_instance(mock_nitter()).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let check = scanner.check_profile(&url, false).await.unwrap();
+        assert_eq!(check.http_code, 200);
+    }
+
+    #[tokio::test]
+    async fn check_profile_cloudflare_script() {
+        // regular pages behind Cloudflare also load its challenge platform script
+        let router = Router::new().route(
+            "/jack",
+            get(|| async {
+                Html(include_str!("../test_data/profile.html").replace(
+                    "</body>",
+                    r#"<script src="/cdn-cgi/challenge-platform/scripts/jsd/main.js"></script></body>"#,
+                ))
+            }),
+        );
+        let url = mock_instance(router).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let check = scanner.check_profile(&url, false).await.unwrap();
+        assert_eq!(check.http_code, 200);
+    }
+
+    #[tokio::test]
+    async fn check_profile_mismatch() {
+        let router = Router::new().route("/jack", get(|| async { "<html></html>" }));
+        let url = mock_instance(router).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let failed = scanner.check_profile(&url, false).await.unwrap_err();
+        assert_eq!(failed.error.http_status, Some(200));
+        assert!(failed.resp_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn check_profile_content_unavailable() {
+        let router = Router::new().route(
+            "/jack",
+            get(|| async {
+                r#"<html><body><div class="error-panel"><span>User "jack" not found</span></div></body></html>"#
+            }),
+        );
+        let url = mock_instance(router).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let failed = scanner.check_profile(&url, false).await.unwrap_err();
+        assert!(failed.content_unavailable);
+        assert_eq!(
+            failed.error.message,
+            r#"Instance up, but content unavailable: User "jack" not found"#
+        );
+
+        let router = Router::new().route("/jack", get(|| async { "<html></html>" }));
+        let url = mock_instance(router).await;
+        let failed = scanner.check_profile(&url, false).await.unwrap_err();
+        assert!(!failed.content_unavailable);
+    }
+
+    #[tokio::test]
+    async fn profile_rotation() {
+        let url = mock_instance(mock_nitter()).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.profile_rotation = vec!["/missing|@jack".parse().unwrap()];
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        assert!(scanner.check_profile(&url, false).await.is_ok());
+        let failed = scanner.check_profile(&url, false).await.unwrap_err();
+        assert_eq!(failed.error.http_status, Some(404));
+        assert!(scanner.check_profile(&url, false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn probe_user_agents() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_c = seen.clone();
+        let router = Router::new().route(
+            "/agent",
+            get(move |headers: axum::http::HeaderMap| async move {
+                let agent = headers[USER_AGENT].to_str().unwrap().to_owned();
+                seen_c.lock().unwrap().push(agent);
+                axum::response::Html(include_str!("../test_data/profile.html"))
+            }),
+        );
+        let url = mock_instance(router).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.profile_path = String::from("/agent");
+        let build =
+            |config: &Config| test_scanner_with(DatabaseConnection::Disconnected, config.clone());
+        // default without configured agents
+        assert!(build(&config).check_profile(&url, false).await.is_ok());
+        assert!(seen.lock().unwrap()[0].starts_with("nitter-status"));
+
+        config.probe_user_agents = vec![String::from("agent, one"), String::from("agent two")];
+        let scanner = build(&config);
+        for _ in 0..3 {
+            assert!(scanner.check_profile(&url, false).await.is_ok());
+        }
+        assert_eq!(
+            seen.lock().unwrap()[1..],
+            ["agent, one", "agent two", "agent, one"]
+        );
+    }
+
+    #[tokio::test]
+    async fn check_profile_invalid_url() {
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let failed = scanner
+            .check_profile("nitter.example.com", false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            failed.error.message,
+            "Invalid instance URL 'nitter.example.com'"
+        );
+    }
+
+    #[tokio::test]
+    async fn check_profile_unreachable() {
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        // nothing listening on the discard port
+        let failed = scanner
+            .check_profile("http://127.0.0.1:9", false)
+            .await
+            .unwrap_err();
+        assert_eq!(failed.error.http_status, None);
+    }
+
+    #[tokio::test]
+    async fn check_content_type() {
+        let router = Router::new()
+            .route(
+                "/jack",
+                get(|| async { include_str!("../test_data/profile.html") }),
+            )
+            .route(
+                "/jack/rss",
+                get(|| async {
+                    (
+                        [("content-type", "application/rss+xml; charset=utf-8")],
+                        r#"<?xml version="1.0"?><rss xmlns:atom="http://www.w3.org/2005/Atom" version="2.0"></rss>"#,
+                    )
+                }),
+            );
+        let url = mock_instance(router).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.check_content_type = true;
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        // profile served as text/plain
+        let failed = scanner.check_profile(&url, false).await.unwrap_err();
+        assert!(failed.error.message.contains("content type"));
+        assert_eq!(failed.error.http_status, Some(200));
+        assert!(scanner.has_rss(&mut Url::parse(&url).unwrap(), false).await);
+
+        // unchecked by default
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        assert!(scanner.check_profile(&url, false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn health_check_host_inserts() {
+        let url = mock_instance(mock_nitter()).await;
+        let db = db_memory().await;
+        let host = insert_host(&db, new_host("localhost", &url)).await;
+        let scanner = test_scanner(db);
+        scanner.health_check_host(host.clone(), false).await;
+
+        let checks = HealthCheck::find()
+            .filter(health_check::Column::Host.eq(host.id))
+            .all(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].healthy);
+        assert_eq!(checks[0].response_code, Some(200));
+    }
+
+    #[tokio::test]
+    async fn account_pool_exhausted() {
+        let router = Router::new().route(
+            "/jack",
+            get(|| async {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Instance has been rate limited.",
+                )
+            }),
+        );
+        let url = mock_instance(router).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.dry_run = true;
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        let host = test_host(&url);
+        for _ in 0..2 {
+            scanner.health_check_host(host.clone(), true).await;
+        }
+        assert!(!scanner.is_account_pool_exhausted(host.id));
+        scanner.health_check_host(host.clone(), true).await;
+        assert!(scanner.is_account_pool_exhausted(host.id));
+
+        // reset by any other result
+        scanner.track_rate_limited(&host, false);
+        assert!(!scanner.is_account_pool_exhausted(host.id));
+    }
+
+    #[tokio::test]
+    async fn profile_posts_tolerance() {
+        let url = mock_instance(mock_nitter()).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.profile_posts_min = 10_000;
+        config.profile_posts_tolerance = 2;
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        let host = test_host(&url);
+        let check = || async {
+            let result = scanner.check_profile(&url, true).await;
+            scanner.tolerate_low_posts(&host, result)
+        };
+        assert!(check().await.is_ok());
+        assert!(check().await.is_ok());
+        let failed = check().await.unwrap_err();
+        assert!(failed.low_posts);
+
+        // reset by any other result
+        scanner
+            .tolerate_low_posts(
+                &host,
+                Ok(HealthyCheck {
+                    resp_time: 1,
+                    http_code: 200,
+                }),
+            )
+            .ok();
+        assert!(check().await.is_ok());
+    }
+
+    #[test]
+    fn healthy_requires() {
+        let mut config = (*Config::test_defaults()).clone();
+        config.healthy_requires = vec![HealthSignal::Profile, HealthSignal::Rss];
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        let mut host = test_host("http://localhost");
+        let healthy = || {
+            Ok(HealthyCheck {
+                resp_time: 1,
+                http_code: 200,
+            })
+        };
+        let failed = scanner.require_signals(&host, healthy()).unwrap_err();
+        assert_eq!(failed.error.http_status, Some(200));
+        assert_eq!(failed.resp_time, Some(1));
+        host.rss = true;
+        assert!(scanner.require_signals(&host, healthy()).is_ok());
+        // connectivity isn't required
+        assert!(host.connectivity.is_none());
+
+        // default only requires the profile
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        host.rss = false;
+        assert!(scanner.require_signals(&host, healthy()).is_ok());
+    }
+
+    #[test]
+    fn version_pattern() {
+        let mut config = (*Config::test_defaults()).clone();
+        config.version_pattern = Some(String::from(r"^\d{4}\.\d{2}\.\d{2}-[0-9a-f]{7}$"));
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        let mut host = host::Model {
+            version: Some(String::from("2023.08.24-2ba1e51")),
+            ..test_host("http://localhost")
+        };
+        let healthy = || {
+            Ok(HealthyCheck {
+                resp_time: 1,
+                http_code: 200,
+            })
+        };
+        assert!(scanner.require_signals(&host, healthy()).is_ok());
+        host.version = Some(String::from("fake"));
+        let failed = scanner.require_signals(&host, healthy()).unwrap_err();
+        assert!(failed.error.message.contains("fake"));
+        // no version reported
+        host.version = None;
+        assert!(scanner.require_signals(&host, healthy()).is_err());
+    }
+
+    #[test]
+    fn version_pattern_neutral() {
+        let mut config = (*Config::test_defaults()).clone();
+        config.version_pattern = Some(String::from(r"^\d{4}\.\d{2}\.\d{2}-[0-9a-f]{7}$"));
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        let mut host = host::Model {
+            version_neutral: true,
+            ..test_host("http://localhost")
+        };
+        let healthy = || {
+            Ok(HealthyCheck {
+                resp_time: 1,
+                http_code: 200,
+            })
+        };
+        // no about page to report a version
+        assert!(scanner.require_signals(&host, healthy()).is_ok());
+        // reported versions still have to match
+        host.version = Some(String::from("fake"));
+        assert!(scanner.require_signals(&host, healthy()).is_err());
+    }
+
+    #[tokio::test]
+    async fn has_rss() {
+        let url = mock_instance(mock_nitter()).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        assert!(scanner.has_rss(&mut Url::parse(&url).unwrap(), false).await);
+
+        let router = Router::new().route("/jack/rss", get(|| async { StatusCode::NOT_FOUND }));
+        let url = mock_instance(router).await;
+        assert!(!scanner.has_rss(&mut Url::parse(&url).unwrap(), false).await);
+    }
+
+    #[tokio::test]
+    async fn check_rss_status() {
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let url = mock_instance(mock_nitter()).await;
+        let check = scanner
+            .check_rss(&mut Url::parse(&url).unwrap(), false)
+            .await;
+        assert!(check.available);
+        assert_eq!(check.http_status, Some(200));
+        assert!(check.resp_time.is_some());
+
+        // rate limited feeds, while profiles may still work
+        let router =
+            Router::new().route("/jack/rss", get(|| async { StatusCode::TOO_MANY_REQUESTS }));
+        let url = mock_instance(router).await;
+        let check = scanner
+            .check_rss(&mut Url::parse(&url).unwrap(), false)
+            .await;
+        assert!(!check.available);
+        assert_eq!(check.http_status, Some(429));
+        assert!(check.resp_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn check_rss_strict() {
+        let feed = r#"<rss xmlns:atom="http://www.w3.org/2005/Atom" version="2.0"><channel><item><title>x</title></item></channel></rss>"#;
+        let router = Router::new()
+            .route("/valid/jack/rss", get(move || async move { feed }))
+            .route(
+                "/empty/jack/rss",
+                get(|| async { r#"<rss xmlns:atom="http://www.w3.org/2005/Atom"></rss>"# }),
+            );
+        let url = mock_instance(router).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.rss_path = String::from("/valid/jack/rss");
+        let url = Url::parse(&url).unwrap();
+        let check = |config: &Config| {
+            let scanner = test_scanner_with(DatabaseConnection::Disconnected, config.clone());
+            let mut url = url.clone();
+            async move {
+                let check = scanner.check_rss(&mut url, false).await;
+                (check.available, check.valid)
+            }
+        };
+        // regex only by default
+        assert_eq!(check(&config).await, (true, None));
+        config.rss_strict = true;
+        assert_eq!(check(&config).await, (true, Some(true)));
+        config.rss_path = String::from("/empty/jack/rss");
+        assert_eq!(check(&config).await, (true, Some(false)));
+        config.rss_path = String::from("/missing/jack/rss");
+        assert_eq!(check(&config).await, (false, Some(false)));
+    }
+
+    #[tokio::test]
+    async fn nitter_version() {
+        let url = mock_instance(mock_nitter()).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        let version = scanner
+            .nitter_version(&mut Url::parse(&url).unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(&version.version_name, "2023.07.22-72d8f35");
+
+        let router = Router::new().route("/about", get(|| async { "<html></html>" }));
+        let url = mock_instance(router).await;
+        assert_eq!(
+            scanner
+                .nitter_version(&mut Url::parse(&url).unwrap(), false)
+                .await
+                .unwrap_err(),
+            VersionCheckError::Failed
+        );
+    }
+
+    #[tokio::test]
+    async fn nitter_version_captcha() {
+        let router = Router::new().route("/about", get(|| async { CAPTCHA_BODY }));
+        let url = mock_instance(router).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        assert_eq!(
+            scanner
+                .nitter_version(&mut Url::parse(&url).unwrap(), false)
+                .await
+                .unwrap_err(),
+            VersionCheckError::Captcha
+        );
+    }
+}
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 use std::collections::HashMap;
 
+use regex::Regex;
 use reqwest::Url;
 use scraper::{ElementRef, Html, Selector};
 use thiserror::Error;
@@ -9,8 +10,10 @@ pub type Result<T> = std::result::Result<T, InstanceListError>;
 
 pub static EXPECT_CSS_SELCTOR: &'static str = "failed to parse css selector";
 static CHECKBOX: &'static str = "✅";
+/// Columns parsed per instance row, see [InstanceParser::parse_row]
+const INSTANCE_TABLE_COLUMNS: usize = 5;
 
-type InstanceMap = HashMap<String, InstanceParsed>;
+pub(crate) type InstanceMap = HashMap<String, InstanceParsed>;
 
 #[derive(Error, Debug)]
 pub enum InstanceListError {
@@ -43,6 +46,13 @@ pub(crate) struct InstanceParser {
     selector_tr: Selector,
     selector_td: Selector,
     selector_a: Selector,
+    selector_th: Selector,
+    /// Header texts all required for the instance table
+    table_headers: Vec<String>,
+    /// Index of the instance table, used if no table has all [Self::table_headers]
+    table_index: Option<usize>,
+    /// Treat `www.` subdomains as their apex domain
+    strip_www: bool,
 }
 
 impl InstanceParser {
@@ -53,17 +63,93 @@ impl InstanceParser {
             selector_tr: Selector::parse("tbody > tr").expect(EXPECT_CSS_SELCTOR),
             selector_td: Selector::parse("td").expect(EXPECT_CSS_SELCTOR),
             selector_a: Selector::parse("a").expect(EXPECT_CSS_SELCTOR),
+            selector_th: Selector::parse("th").expect(EXPECT_CSS_SELCTOR),
+            table_headers: vec![String::from("Online")],
+            table_index: None,
+            strip_www: false,
         }
     }
 
+    /// Use the apex domain for instances listed with a `www.` subdomain
+    pub fn strip_www(mut self, strip_www: bool) -> Self {
+        self.strip_www = strip_www;
+        self
+    }
+
+    /// Normalize the *domain* and *url* of an instance
+    ///
+    /// Replaces a leading `www.` by the apex domain in both, if enabled.
+    fn normalize_url(&self, domain: String, url: String) -> (String, String) {
+        let apex = match domain.strip_prefix("www.") {
+            Some(apex) if self.strip_www && apex.contains('.') => apex,
+            _ => return (domain, url),
+        };
+        let Ok(mut parsed) = Url::parse(&url) else {
+            return (domain, url);
+        };
+        if parsed.set_host(Some(apex)).is_err() {
+            return (domain, url);
+        }
+        let parsed = parsed.as_str();
+        let url = parsed.strip_suffix('/').unwrap_or(parsed).to_owned();
+        (apex.to_owned(), url)
+    }
+
+    /// Configure the instance table detection
+    ///
+    /// Tables are tried by *headers*, then by *index* and last by the amount of columns.
+    pub fn table_detection(mut self, headers: Vec<String>, index: Option<usize>) -> Self {
+        self.table_headers = headers;
+        self.table_index = index;
+        self
+    }
+
+    /// Find the instance table out of *tables*, falling back over all detection strategies
+    fn find_instance_table<'a>(&self, tables: &[ElementRef<'a>]) -> Option<ElementRef<'a>> {
+        let headers = |table: &ElementRef| -> Vec<String> {
+            table
+                .select(&self.selector_th)
+                .map(|th| th.text().collect::<String>().to_lowercase())
+                .collect()
+        };
+        if !self.table_headers.is_empty() {
+            let found = tables.iter().find(|table| {
+                let headers = headers(table);
+                self.table_headers.iter().all(|wanted| {
+                    let wanted = wanted.to_lowercase();
+                    headers.iter().any(|header| header.contains(&wanted))
+                })
+            });
+            if let Some(table) = found {
+                tracing::debug!(headers=?self.table_headers, "instance table found by headers");
+                return Some(*table);
+            }
+        }
+        if let Some(table) = self.table_index.and_then(|index| tables.get(index)) {
+            tracing::info!(index = self.table_index, "instance table found by index");
+            return Some(*table);
+        }
+        let table = tables
+            .iter()
+            .find(|table| headers(table).len() >= INSTANCE_TABLE_COLUMNS)?;
+        tracing::info!("instance table found by column count");
+        Some(*table)
+    }
+
     /// Parse a html rendered version of the instance list
     ///
+    /// *require_https* skips all instances not using https, including additional instances
+    ///
+    /// *failed_rows* collects the HTML of all malformed table rows
+    ///
     /// *abort_on_err* is just for testing and return an error for any malformed table entry
     pub fn parse_instancelist(
         &self,
         html: &str,
         additional_instances: &[String],
         additional_instances_country: &str,
+        require_https: bool,
+        mut failed_rows: Option<&mut Vec<String>>,
         abort_on_err: bool,
     ) -> Result<InstanceMap> {
         let fragment = Html::parse_fragment(html);
@@ -72,25 +158,42 @@ impl InstanceParser {
         // first result
         let first_wiki = wiki_divs.next().ok_or(InstanceListError::NoWikiDiv)?;
         // all <table> element
-        let mut tables = first_wiki.select(&self.selector_table);
-        // find the one with "Online" text inside
-        let instance_table = tables
-            .find(|t| t.text().any(|text| text.contains("Online")))
+        let tables: Vec<_> = first_wiki.select(&self.selector_table).collect();
+        let instance_table = self
+            .find_instance_table(&tables)
             .ok_or(InstanceListError::NoInstanceTable)?;
 
         let mut instances = HashMap::with_capacity(50);
         // iterate over all <body> > <tr> inside
         for row in instance_table.select(&self.selector_tr) {
             match self.parse_row(row) {
-                Ok(instance) => {
-                    if let Some(old) = instances.insert(instance.domain.clone(), instance) {
-                        tracing::warn!(domain = old.domain, "Parsed duplicate instance domain!");
-                    }
+                Ok(instance) if require_https && !is_https(&instance.url) => {
+                    tracing::info!(url = instance.url, "Skipping non-https instance");
                 }
+                Ok(instance) => match instances.get(&instance.domain) {
+                    None => {
+                        instances.insert(instance.domain.clone(), instance);
+                    }
+                    // prefer https, otherwise keep the first entry
+                    Some(old) => {
+                        tracing::warn!(
+                            domain = old.domain,
+                            kept = old.url,
+                            other = instance.url,
+                            "Parsed duplicate instance domain!"
+                        );
+                        if !is_https(&old.url) && is_https(&instance.url) {
+                            instances.insert(instance.domain.clone(), instance);
+                        }
+                    }
+                },
                 Err(e) => {
                     if abort_on_err {
                         return Err(e);
                     }
+                    if let Some(failed_rows) = failed_rows.as_mut() {
+                        failed_rows.push(row.html());
+                    }
                     continue;
                 }
             }
@@ -98,13 +201,17 @@ impl InstanceParser {
 
         for entry in additional_instances {
             match Url::parse(entry.as_ref()) {
+                Ok(v) if require_https && v.scheme() != "https" => {
+                    tracing::info!(instance = entry, "Skipping non-https additional instance");
+                }
                 Ok(v) => {
                     if let Some(domain) = v.domain() {
+                        let (domain, url) = self.normalize_url(domain.to_owned(), entry.clone());
                         instances.insert(
-                            domain.to_owned(),
+                            domain.clone(),
                             InstanceParsed {
-                                domain: domain.to_owned(),
-                                url: entry.clone(),
+                                domain,
+                                url,
                                 online: true,
                                 ssl_provider: String::new(),
                                 country: additional_instances_country.to_owned(),
@@ -156,6 +263,8 @@ impl InstanceParser {
             }
         };
 
+        let (domain, url) = self.normalize_url(domain, url);
+
         // map all remaining cols into Strings
         let columns: Vec<_> = cols
             .map(|col| {
@@ -165,7 +274,7 @@ impl InstanceParser {
                 })
             })
             .collect();
-        if columns.len() < 4 {
+        if columns.len() < INSTANCE_TABLE_COLUMNS - 1 {
             tracing::error!(instance_data=?columns,"Parsed instance missing fields, skipping!");
             return Err(InstanceListError::MalformedRow);
         }
@@ -180,6 +289,33 @@ impl InstanceParser {
     }
 }
 
+/// Removes all excluded instances
+///
+/// *domains* excludes these domains and their subdomains, *regex* is matched against the domain.
+pub(crate) fn exclude_instances(
+    instances: &mut InstanceMap,
+    domains: &[String],
+    regex: Option<&Regex>,
+) {
+    instances.retain(|domain, _| {
+        let excluded = domains.iter().any(|excluded| {
+            domain == excluded
+                || domain
+                    .strip_suffix(excluded.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        }) || regex.is_some_and(|regex| regex.is_match(domain));
+        if excluded {
+            tracing::debug!(domain, "Excluding instance");
+        }
+        !excluded
+    });
+}
+
+/// Whether the URL uses https
+fn is_https(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|v| v.scheme() == "https")
+}
+
 #[cfg(test)]
 mod test {
     use csv;
@@ -192,7 +328,9 @@ mod test {
     fn parse() {
         let html = include_str!("../test_data/instancelist.html");
         let parser = InstanceParser::new();
-        let res = parser.parse_instancelist(html, &[], "", true).unwrap();
+        let res = parser
+            .parse_instancelist(html, &[], "", false, None, true)
+            .unwrap();
 
         // writeback for new tests
         // write_data(res.values());
@@ -208,6 +346,184 @@ mod test {
         }
     }
 
+    #[test]
+    fn parse_require_https() {
+        let html = include_str!("../test_data/instancelist.html");
+        let parser = InstanceParser::new();
+        let additional = [
+            String::from("http://plain.example.com"),
+            String::from("https://secure.example.com"),
+        ];
+        let res = parser
+            .parse_instancelist(html, &additional, "", true, None, true)
+            .unwrap();
+        assert!(res.contains_key("secure.example.com"));
+        assert!(!res.contains_key("plain.example.com"));
+        assert!(res.values().all(|instance| is_https(&instance.url)));
+
+        let res = parser
+            .parse_instancelist(html, &additional, "", false, None, true)
+            .unwrap();
+        assert!(res.contains_key("plain.example.com"));
+    }
+
+    #[test]
+    fn parse_duplicate_domain() {
+        let row = |url: &str| {
+            format!(
+                r#"<tr><td><a href="{url}">x</a></td><td>✅</td><td>❌</td><td>🇩🇪</td><td>Let's Encrypt</td></tr>"#
+            )
+        };
+        let html = |rows: &[&str]| {
+            let rows: String = rows.iter().map(|url| row(url)).collect();
+            format!(
+                r#"<div id="wiki-body"><table><thead><tr><th>Online</th></tr></thead><tbody>{rows}</tbody></table></div>"#
+            )
+        };
+        let parser = InstanceParser::new();
+        for rows in [
+            ["http://nitter.example.com", "https://nitter.example.com"],
+            ["https://nitter.example.com", "http://nitter.example.com"],
+        ] {
+            let res = parser
+                .parse_instancelist(&html(&rows), &[], "", false, None, true)
+                .unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res["nitter.example.com"].url, "https://nitter.example.com");
+        }
+        // same scheme, first one wins
+        let res = parser
+            .parse_instancelist(
+                &html(&[
+                    "https://nitter.example.com/a",
+                    "https://nitter.example.com/b",
+                ]),
+                &[],
+                "",
+                false,
+                None,
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            res["nitter.example.com"].url,
+            "https://nitter.example.com/a"
+        );
+
+        // malformed rows are collected
+        let mut failed_rows = Vec::new();
+        let html = html(&["https://nitter.example.com"]).replace(
+            "</tbody>",
+            r#"<tr><td><a href="https://broken.example.com">x</a></td></tr></tbody>"#,
+        );
+        let res = parser
+            .parse_instancelist(&html, &[], "", false, Some(&mut failed_rows), false)
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(failed_rows.len(), 1);
+        assert!(failed_rows[0].contains("broken.example.com"));
+    }
+
+    #[test]
+    fn strip_www() {
+        let row = |url: &str| {
+            format!(
+                r#"<tr><td><a href="{url}">x</a></td><td>✅</td><td>❌</td><td>🇩🇪</td><td>Let's Encrypt</td></tr>"#
+            )
+        };
+        let html = format!(
+            r#"<div id="wiki-body"><table><thead><tr><th>Online</th></tr></thead><tbody>{}{}{}</tbody></table></div>"#,
+            row("https://www.example.com/"),
+            row("https://example.com"),
+            row("https://www.com"),
+        );
+        let additional = [String::from("https://www.other.example.com/path")];
+
+        let parser = InstanceParser::new().strip_www(true);
+        let res = parser
+            .parse_instancelist(&html, &additional, "", false, None, true)
+            .unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res["example.com"].url, "https://example.com");
+        // not a subdomain
+        assert_eq!(res["www.com"].url, "https://www.com");
+        assert_eq!(
+            res["other.example.com"].url,
+            "https://other.example.com/path"
+        );
+
+        // distinct by default
+        let res = InstanceParser::new()
+            .parse_instancelist(&html, &additional, "", false, None, true)
+            .unwrap();
+        assert_eq!(res.len(), 4);
+        assert_eq!(res["www.example.com"].url, "https://www.example.com");
+        assert!(res.contains_key("www.other.example.com"));
+    }
+
+    #[test]
+    fn table_detection() {
+        let html = include_str!("../test_data/instancelist.html");
+        let expected = InstanceParser::new()
+            .parse_instancelist(html, &[], "", false, None, true)
+            .unwrap();
+        let parse = |parser: InstanceParser, html: &str| {
+            parser.parse_instancelist(html, &[], "", false, None, true)
+        };
+        // renamed column, localized header config
+        let renamed = html.replace("Online¹", "Status¹");
+        let localized = InstanceParser::new().table_detection(vec![String::from("status")], None);
+        assert_eq!(parse(localized, &renamed).unwrap(), expected);
+        // unknown headers fall back to the column count
+        let unknown = InstanceParser::new().table_detection(vec![String::from("Missing")], None);
+        assert_eq!(parse(unknown, &renamed).unwrap(), expected);
+        // index before column count, the first table has too few columns
+        let first = InstanceParser::new().table_detection(vec![String::from("Missing")], Some(0));
+        assert!(matches!(
+            parse(first, &renamed),
+            Err(InstanceListError::MalformedRow)
+        ));
+        // no table with enough columns
+        let row = r#"<tr><td><a href="https://nitter.example.com">x</a></td><td>✅</td></tr>"#;
+        let small = format!(
+            r#"<div id="wiki-body"><table><thead><tr><th>URL</th><th>Up</th></tr></thead><tbody>{row}</tbody></table></div>"#
+        );
+        assert!(matches!(
+            parse(InstanceParser::new(), &small),
+            Err(InstanceListError::NoInstanceTable)
+        ));
+    }
+
+    #[test]
+    fn exclude() {
+        let mut instances: InstanceMap = [
+            "nitter.net",
+            "eu.nitter.net",
+            "notnitter.net",
+            "nitter.example.com",
+            "example.org",
+        ]
+        .into_iter()
+        .map(|domain| {
+            (
+                domain.to_owned(),
+                InstanceParsed {
+                    domain: domain.to_owned(),
+                    url: format!("https://{domain}"),
+                    online: true,
+                    ssl_provider: String::new(),
+                    country: String::new(),
+                },
+            )
+        })
+        .collect();
+        let regex = Regex::new(r"\.example\.com$").unwrap();
+        exclude_instances(&mut instances, &[String::from("nitter.net")], Some(&regex));
+        let mut left: Vec<_> = instances.keys().map(String::as_str).collect();
+        left.sort_unstable();
+        assert_eq!(left, vec!["example.org", "notnitter.net"]);
+    }
+
     fn expected_data() -> Vec<InstanceParsed> {
         let file = std::fs::File::open("test_data/instancelist_expected.csv").unwrap();
         let mut rdr = csv::Reader::from_reader(file);
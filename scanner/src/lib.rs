@@ -1,22 +1,29 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 use std::{
+    collections::{HashMap, HashSet},
     net::IpAddr,
-    sync::{Arc, Mutex},
+    sync::{atomic::AtomicUsize, Arc, Mutex},
 };
 
 use about_parser::AboutParser;
+use cache_update::SingleFlight;
 use chrono::{DateTime, Duration, TimeZone, Utc};
+pub use config_check::validate_config;
 use entities::{
     health_check,
-    state::{error_cache::HostError, scanner::ScannerConfig, AppState},
+    state::{error_cache::HostError, scanner::ScannerConfig, AppState, ScannerSchedule},
 };
+pub use instance_check::InstanceReport;
 use instance_parser::InstanceParser;
 use miette::{Context, IntoDiagnostic};
 use profile_parser::ProfileParser;
 use regex::{Regex, RegexBuilder};
 use reqwest::{
-    header::{HeaderMap, HeaderValue},
-    Client, ClientBuilder,
+    header::{
+        HeaderMap, HeaderValue, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED, USER_AGENT,
+    },
+    Client, ClientBuilder, StatusCode,
 };
 use sea_orm::{
     ColumnTrait, ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, FromQueryResult,
@@ -30,6 +37,8 @@ type Result<T> = std::result::Result<T, ScannerError>;
 mod about_parser;
 mod cache_update;
 mod cleanup;
+mod config_check;
+mod feed_parser;
 mod instance_check;
 mod instance_parser;
 mod list_update;
@@ -38,6 +47,22 @@ mod version_check;
 
 const CAPTCHA_TEXT: &'static str = "Enable JavaScript and cookies to continue";
 const CAPTCHA_CODE: u16 = 403;
+/// Time the challenge solver may take for solving
+const CHALLENGE_SOLVER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+/// Markers of JS challenge pages, which can also be served with a 200
+///
+/// Not the bare `/cdn-cgi/challenge-platform/` path, Cloudflare also injects its scripts into regular pages.
+const CAPTCHA_MARKERS: [&str; 4] = [
+    CAPTCHA_TEXT,
+    "cf-chl-",
+    "_cf_chl_opt",
+    "<title>Just a moment...",
+];
+
+/// Whether the body is a captcha/JS challenge page
+fn is_captcha(body: &str) -> bool {
+    CAPTCHA_MARKERS.iter().any(|marker| body.contains(marker))
+}
 
 static ACCEPT: &str =
     "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
@@ -64,6 +89,19 @@ pub enum ScannerError {
     GitFetch(#[from] git2::Error),
     #[error("Couldn't find git branch")]
     GitBranch,
+    #[error("Failed accessing cache file: {0}")]
+    CacheFile(#[from] std::io::Error),
+    #[error("Invalid cache file: {0}")]
+    CacheFormat(#[from] serde_json::Error),
+    #[error("Challenge solver failed: {0}")]
+    ChallengeSolver(String),
+    #[error("Invalid instance URL '{0}'")]
+    InstanceUrlParse(String),
+}
+
+/// Parse the URL of an instance, keeping the offending URL on failure
+fn parse_instance_url(url: &str) -> Result<reqwest::Url> {
+    reqwest::Url::parse(url).map_err(|_| ScannerError::InstanceUrlParse(url.to_owned()))
 }
 
 #[derive(Error, Debug)]
@@ -76,8 +114,10 @@ pub enum FetchError {
     KnownHttpResponseStatus(u16, String),
     #[error("Reading response body failed for Host {0}: {1}")]
     RetrievingBody(String, reqwest::Error),
-    #[error("Host responded with captcha")]
-    Captcha,
+    #[error("Host responded with captcha, status {0}")]
+    Captcha(u16),
+    #[error("Unexpected content type '{1}', status {0}")]
+    ContentType(u16, String),
 }
 
 impl FetchError {
@@ -87,10 +127,27 @@ impl FetchError {
             FetchError::Reqwest(e) => e.status().map(|v| v.as_u16()),
             FetchError::HttpResponseStatus(code, _, _) => Some(*code),
             FetchError::KnownHttpResponseStatus(code, _) => Some(*code),
-            FetchError::Captcha | FetchError::RetrievingBody(_, _) => None,
+            FetchError::Captcha(code) => Some(*code),
+            FetchError::ContentType(code, _) => Some(*code),
+            FetchError::RetrievingBody(_, _) => None,
         }
     }
 
+    /// Whether the host responded that all of its accounts are rate limited
+    fn is_rate_limited(&self) -> bool {
+        matches!(self, FetchError::KnownHttpResponseStatus(429, _))
+    }
+
+    /// Whether the host blocked us, for example by a cloudflare firewall rule or captcha.
+    ///
+    /// The host may still be reachable for other users.
+    fn is_blocked(&self) -> bool {
+        matches!(
+            self,
+            FetchError::Captcha(_) | FetchError::KnownHttpResponseStatus(403, _)
+        )
+    }
+
     fn to_host_error(self) -> HostError {
         match self {
             FetchError::Reqwest(e) => HostError::new_message(e.to_string()),
@@ -103,16 +160,34 @@ impl FetchError {
             FetchError::RetrievingBody(_url, reqwest_error) => {
                 HostError::new_message(reqwest_error.to_string())
             }
-            FetchError::Captcha => HostError::new_message(format!("Captcha detected")),
+            FetchError::Captcha(http_status) => {
+                HostError::new_without_body(format!("Captcha detected"), http_status)
+            }
+            FetchError::ContentType(http_status, _) => {
+                HostError::new_without_body(self.to_string(), http_status)
+            }
         }
     }
 }
 
+/// HTTP cache validators for conditional requests
+#[derive(Debug, Clone)]
+struct ListValidators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Time of the last full fetch
+    fetched: DateTime<Utc>,
+}
+
 #[derive(Debug, FromQueryResult, Default)]
 pub(crate) struct LatestCheck {
     pub host: i32,
     pub healthy: bool,
+    /// Check was blocked by the host
+    pub blocked: bool,
     pub domain: String,
+    /// Unix timestamp of the check
+    pub time: i64,
 }
 
 pub async fn run_scanner(
@@ -124,7 +199,11 @@ pub async fn run_scanner(
     let scanner = Scanner::new(db, config, app_state)
         .await
         .wrap_err("Initializing scanner!")?;
-    scanner.schedule_cleanup().unwrap();
+    if scanner.inner.config.dry_run {
+        tracing::warn!("Scanner dry-run enabled, no data will be written!");
+    } else {
+        scanner.schedule_cleanup().unwrap();
+    }
 
     if disable_health_checks {
         tracing::error!("Health checks disabled!");
@@ -137,6 +216,17 @@ pub async fn run_scanner(
     Ok(())
 }
 
+/// Run all checks against a single instance URL, without using the database.
+pub async fn check_instance(config: ScannerConfig, url: &str) -> miette::Result<InstanceReport> {
+    let scanner = Scanner::build(
+        DatabaseConnection::Disconnected,
+        config,
+        entities::state::new(),
+        Utc::now(),
+    )?;
+    Ok(scanner.check_report(url).await)
+}
+
 #[derive(Clone)]
 struct Scanner {
     inner: Arc<InnerScanner>,
@@ -147,14 +237,72 @@ struct InnerScanner {
     app_state: AppState,
     config: ScannerConfig,
     client: reqwest::Client,
+    /// Client for the instance list, only proxied if configured
+    list_client: reqwest::Client,
+    /// Client for the state webhook, never proxied and not counted as host connection
+    webhook_client: reqwest::Client,
     instance_parser: InstanceParser,
     about_parser: AboutParser,
     profile_parser: ProfileParser,
     last_list_fetch: Mutex<DateTime<Utc>>,
+    /// Cache validators of the last instance list fetch
+    list_validators: Mutex<Option<ListValidators>>,
+    /// Consecutive rate limited health checks per host
+    rate_limited: Mutex<HashMap<i32, usize>>,
+    /// Consecutive health checks with too few profile posts per host
+    low_posts: Mutex<HashMap<i32, usize>>,
+    /// Hosts serving an error page instead of the profile in their latest health check
+    content_unavailable: Mutex<HashSet<i32>>,
+    /// Round of the profile rotation for the next health check
+    profile_round: AtomicUsize,
+    /// User-Agents for health check requests, the client default if empty
+    probe_user_agents: Vec<HeaderValue>,
+    /// Round of the User-Agent rotation for the next health check request
+    user_agent_round: AtomicUsize,
     last_uptime_check: Mutex<DateTime<Utc>>,
+    /// Last version update, by the list update or version-only re-check
+    last_version_check: Mutex<DateTime<Utc>>,
+    /// Coalesces overlapping cache regenerations
+    cache_update: SingleFlight,
     rss_check_regex: Regex,
+    exclude_hosts_regex: Option<Regex>,
+    /// Pattern the version of healthy hosts has to match
+    version_regex: Option<Regex>,
     client_ipv4: Client,
     client_ipv6: Client,
+    /// TLS setup for reading the served certificate of pinned hosts, accepts any certificate
+    tls_config: Arc<tokio_rustls::rustls::ClientConfig>,
+    /// Time source for scheduling
+    clock: Arc<dyn Clock>,
+}
+
+/// Source of the current time, replaceable for testing the scheduling
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Real system time
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// System DNS resolver, counting every resolution as new connection in the [AppState]
+///
+/// The connection pool only resolves hosts for new connections, IP hosts are never resolved.
+struct CountingResolver(AppState);
+
+impl reqwest::dns::Resolve for CountingResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        self.0.count_connection(name.as_str());
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host(format!("{}:0", name.as_str())).await?;
+            Ok(Box::new(addrs) as reqwest::dns::Addrs)
+        })
+    }
 }
 
 impl Scanner {
@@ -174,17 +322,89 @@ impl Scanner {
             .connect_timeout(std::time::Duration::from_secs(3))
             .timeout(std::time::Duration::from_secs(10))
             .default_headers(headers);
-        http_client
+        let http_client = match config.pool_max_idle_per_host {
+            Some(max) => http_client.pool_max_idle_per_host(max),
+            None => http_client,
+        };
+        match config.pool_idle_timeout {
+            Some(timeout) => http_client.pool_idle_timeout(timeout),
+            None => http_client,
+        }
     }
 
     async fn new(
         db: DatabaseConnection,
         config: ScannerConfig,
         app_state: AppState,
+    ) -> miette::Result<Self> {
+        let last_uptime_check = Self::query_last_fetch(&db)
+            .await
+            .into_diagnostic()
+            .wrap_err("Fetching last uptime check failed!")?;
+        tracing::info!(?last_uptime_check);
+        let scanner = Self::build(db, config, app_state, last_uptime_check)?;
+        // serve the last snapshot right away, refreshed after the first scheduled checks
+        if !scanner.load_cache_file().await {
+            scanner
+                .update_cache()
+                .await
+                .into_diagnostic()
+                .wrap_err("Initial cache update failed!")?;
+        }
+        Ok(scanner)
+    }
+
+    /// Setup clients and parsers, without touching the database or cache
+    fn build(
+        db: DatabaseConnection,
+        config: ScannerConfig,
+        app_state: AppState,
+        last_uptime_check: DateTime<Utc>,
     ) -> miette::Result<Self> {
         let mut builder_regex_rss = RegexBuilder::new(&config.rss_content);
         builder_regex_rss.case_insensitive(true);
-        let http_client = Self::client_builder(&config);
+        let exclude_hosts_regex = config
+            .exclude_hosts_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .into_diagnostic()
+            .wrap_err("Invalid exclude hosts regex!")?;
+        let version_regex = config
+            .version_pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .into_diagnostic()
+            .wrap_err("Invalid required version regex!")?;
+        let probe_user_agents = config
+            .probe_user_agents
+            .iter()
+            .map(|v| HeaderValue::from_str(v))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .into_diagnostic()
+            .wrap_err("Invalid probe User-Agent!")?;
+        let proxy = config
+            .http_proxy
+            .as_deref()
+            .map(reqwest::Proxy::all)
+            .transpose()
+            .into_diagnostic()
+            .wrap_err("Invalid HTTP proxy URL!")?;
+        let resolver = Arc::new(CountingResolver(app_state.clone()));
+        let http_client = match &proxy {
+            Some(proxy) => Self::client_builder(&config).proxy(proxy.clone()),
+            None => Self::client_builder(&config),
+        }
+        .dns_resolver(resolver)
+        .build()
+        .into_diagnostic()?;
+        let list_client = match proxy.is_some() && !config.http_proxy_instance_list {
+            true => Self::client_builder(&config).build().into_diagnostic()?,
+            false => http_client.clone(),
+        };
+        let webhook_client = Self::client_builder(&config).build().into_diagnostic()?;
+        // the proxy would hide the IP version used
         let client_ipv4 = Scanner::client_builder(&config)
             .local_address("0.0.0.0".parse::<IpAddr>().unwrap())
             .build()
@@ -194,36 +414,54 @@ impl Scanner {
             .build()
             .into_diagnostic()?;
 
-        let last_uptime_check = Self::query_last_fetch(&db)
-            .await
-            .into_diagnostic()
-            .wrap_err("Fetching last uptime check failed!")?;
-        tracing::info!(?last_uptime_check);
-        let scanner = Self {
+        let tls_config = tokio_rustls::rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(list_update::AnyServerCert))
+            .with_no_client_auth();
+
+        let instance_parser = InstanceParser::new()
+            .table_detection(
+                config.instance_table_headers.clone(),
+                config.instance_table_index,
+            )
+            .strip_www(config.strip_www);
+        let profile_parser =
+            ProfileParser::new().unavailable_markers(&config.content_unavailable_markers);
+
+        Ok(Self {
             inner: Arc::new(InnerScanner {
                 db,
                 app_state,
-                client: http_client.build().unwrap(),
+                client: http_client,
+                list_client,
+                webhook_client,
                 config,
                 client_ipv4,
                 client_ipv6,
-                instance_parser: InstanceParser::new(),
+                tls_config: Arc::new(tls_config),
+                instance_parser,
                 about_parser: AboutParser::new(),
-                profile_parser: ProfileParser::new(),
+                profile_parser,
                 last_list_fetch: Mutex::new(last_uptime_check),
+                list_validators: Mutex::new(None),
+                rate_limited: Mutex::new(HashMap::new()),
+                low_posts: Mutex::new(HashMap::new()),
+                content_unavailable: Mutex::new(HashSet::new()),
+                profile_round: AtomicUsize::new(0),
+                probe_user_agents,
+                user_agent_round: AtomicUsize::new(0),
+                clock: Arc::new(SystemClock),
                 last_uptime_check: Mutex::new(last_uptime_check),
+                last_version_check: Mutex::new(last_uptime_check),
+                cache_update: SingleFlight::default(),
                 rss_check_regex: builder_regex_rss
                     .build()
                     .into_diagnostic()
                     .wrap_err("Invalid RSS Content regex!")?,
+                exclude_hosts_regex,
+                version_regex,
             }),
-        };
-        scanner
-            .update_cache()
-            .await
-            .into_diagnostic()
-            .wrap_err("Initial cache update failed!")?;
-        Ok(scanner)
+        })
     }
 
     /// Retrieves the last uptime fetch that happened
@@ -258,7 +496,7 @@ impl Scanner {
                 SELECT u.host,MAX(u.time) as time FROM health_check u
                 GROUP BY u.host
             )
-            SELECT u.host,healthy,h.domain FROM health_check u
+            SELECT u.host,healthy,blocked,h.domain,u.time FROM health_check u
             JOIN host h ON h.id = u.host
             JOIN latest l ON l.host = u.host AND l.time = u.time
             WHERE h.enabled = true
@@ -277,6 +515,11 @@ impl Scanner {
                     tracing::error!(error=?e,"Failed updating instance list");
                 }
             }
+            if self.is_version_check_outdated() {
+                if let Err(e) = self.update_versions().await {
+                    tracing::error!(error=?e,"Failed updating instance versions");
+                }
+            }
             if self.is_instance_check_outdated() {
                 if let Err(e) = self.check_uptime().await {
                     tracing::error!(error=?e,"Failed checking instance");
@@ -290,21 +533,50 @@ impl Scanner {
     }
 
     async fn sleep_till_deadline(&self) {
+        let sleep_time = self.time_till_deadline();
+        self.publish_schedule(sleep_time);
+        if let Some(sleep_time) = sleep_time {
+            tracing::trace!(duration=?sleep_time,"scanner sleeping");
+            sleep(sleep_time).await;
+        }
+    }
+
+    /// Time until the next list update or uptime check is due, `None` if one is due now
+    fn time_till_deadline(&self) -> Option<std::time::Duration> {
         let delay_instance_check =
             self.last_uptime_check() + self.inner.config.instance_check_interval;
 
         let delay_list_update = self.last_list_fetch() + self.inner.config.list_fetch_interval;
         tracing::debug!(?delay_list_update, ?delay_instance_check);
-        let next_deadline = delay_instance_check.min(delay_list_update);
-        let now = Utc::now();
-        let sleep_time = next_deadline.signed_duration_since(now);
+        let mut next_deadline = delay_instance_check.min(delay_list_update);
+        if let Some(interval) = self.inner.config.version_check_interval {
+            next_deadline = next_deadline.min(self.last_version_check() + interval);
+        }
+        let sleep_time = next_deadline.signed_duration_since(self.now());
         if sleep_time <= Duration::zero() {
             // schedule right now, also std duration can't be negative
-            return;
+            return None;
         }
-        let sleep_time = sleep_time.to_std().unwrap();
-        tracing::trace!(duration=?sleep_time,"scanner sleeping");
-        sleep(sleep_time).await;
+        Some(sleep_time.to_std().unwrap())
+    }
+
+    /// Store the last runs and next wakeup in the [AppState], for the admin interface
+    fn publish_schedule(&self, sleep_time: Option<std::time::Duration>) {
+        let now = self.now();
+        let next_wakeup = sleep_time
+            .and_then(|v| Duration::from_std(v).ok())
+            .map_or(now, |v| now + v);
+        *self.inner.app_state.schedule.write().unwrap() = ScannerSchedule {
+            last_uptime_check: Some(self.last_uptime_check()),
+            last_list_fetch: Some(self.last_list_fetch()),
+            last_version_check: Some(self.last_version_check()),
+            next_wakeup: Some(next_wakeup),
+        };
+    }
+
+    /// Current time of the scanner clock
+    fn now(&self) -> DateTime<Utc> {
+        self.inner.clock.now()
     }
 
     fn last_uptime_check(&self) -> DateTime<Utc> {
@@ -317,23 +589,231 @@ impl Scanner {
 
     fn is_instance_check_outdated(&self) -> bool {
         let val = self.last_uptime_check();
-        Utc::now().signed_duration_since(val).to_std().unwrap()
-            >= self.inner.config.instance_check_interval
+        // negative if the clock went backwards, wait for it to catch up
+        self.now()
+            .signed_duration_since(val)
+            .to_std()
+            .is_ok_and(|elapsed| elapsed >= self.inner.config.instance_check_interval)
+    }
+
+    fn last_version_check(&self) -> DateTime<Utc> {
+        *self.inner.last_version_check.lock().unwrap()
+    }
+
+    /// Whether the version-only re-check is enabled and due
+    fn is_version_check_outdated(&self) -> bool {
+        let Some(interval) = self.inner.config.version_check_interval else {
+            return false;
+        };
+        self.now()
+            .signed_duration_since(self.last_version_check())
+            .to_std()
+            .is_ok_and(|elapsed| elapsed >= interval)
     }
 
     fn is_instance_list_outdated(&self) -> bool {
         let val = self.last_list_fetch();
-        Utc::now().signed_duration_since(val).to_std().unwrap()
-            >= self.inner.config.list_fetch_interval
+        self.now()
+            .signed_duration_since(val)
+            .to_std()
+            .is_ok_and(|elapsed| elapsed >= self.inner.config.list_fetch_interval)
+    }
+
+    /// Fetch the instance list, `None` if it didn't change since the last fetch
+    ///
+    /// Falls back to the challenge solver if configured and the list serves a JS challenge.
+    async fn fetch_instance_list(&self) -> Result<Option<String>> {
+        let res = self.fetch_instance_list_direct().await;
+        let Some(solver) = self.inner.config.instance_list_challenge_solver.as_deref() else {
+            return res;
+        };
+        match res {
+            Err(ScannerError::FetchError(FetchError::Captcha(code))) => {
+                tracing::info!(
+                    code,
+                    "instance list served a challenge, using the challenge solver"
+                );
+                self.solve_challenge(solver, &self.inner.config.instance_list_url)
+                    .await
+                    .map(Some)
+            }
+            res => res,
+        }
+    }
+
+    /// Fetch *url* via the FlareSolverr compatible challenge *solver*, returns the solved body
+    async fn solve_challenge(&self, solver: &str, url: &str) -> Result<String> {
+        #[derive(serde::Deserialize)]
+        struct SolverResponse {
+            status: String,
+            #[serde(default)]
+            message: String,
+            solution: Option<SolverSolution>,
+        }
+        #[derive(serde::Deserialize)]
+        struct SolverSolution {
+            status: u16,
+            response: String,
+        }
+
+        let request = serde_json::json!({
+            "cmd": "request.get",
+            "url": url,
+            "maxTimeout": CHALLENGE_SOLVER_TIMEOUT.as_millis() as u64,
+        });
+        let res = self
+            .inner
+            .list_client
+            .post(solver)
+            .header(CONTENT_TYPE, "application/json")
+            .body(request.to_string())
+            // the solver waits for the challenge, plus some slack for the browser startup
+            .timeout(CHALLENGE_SOLVER_TIMEOUT * 2)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(FetchError::from)?;
+        let body = Self::read_body(solver, res)
+            .await
+            .map_err(|e| FetchError::RetrievingBody(solver.to_owned(), e))?;
+        let res: SolverResponse = serde_json::from_str(&body)
+            .map_err(|e| ScannerError::ChallengeSolver(format!("invalid response: {e}")))?;
+        match res.solution {
+            Some(solution) if res.status == "ok" => match solution.status {
+                200..=299 => Ok(solution.response),
+                status => Err(ScannerError::ChallengeSolver(format!(
+                    "source responded with status {status}"
+                ))),
+            },
+            _ => Err(ScannerError::ChallengeSolver(format!(
+                "status '{}': {}",
+                res.status, res.message
+            ))),
+        }
     }
 
-    async fn fetch_instance_list(&self) -> Result<String> {
-        let (_, body) = self.fetch_url(&self.inner.config.instance_list_url).await?;
-        Ok(body)
+    async fn fetch_instance_list_direct(&self) -> Result<Option<String>> {
+        let url = self.inner.config.instance_list_url.as_str();
+        let mut request = self.inner.list_client.get(url);
+        // per request, so the token is never sent to instances
+        if let Some(token) = self.inner.config.instance_list_auth.as_deref() {
+            request = request.bearer_auth(token);
+        }
+        let Some(max_age) = self.inner.config.list_conditional_max_age else {
+            let fetch_res = request.send().await.map_err(FetchError::from)?;
+            let (_, body) = Self::read_response(url, fetch_res).await?;
+            return Ok(Some(body));
+        };
+        let validators = self.inner.list_validators.lock().unwrap().clone();
+        // force a full fetch after max_age
+        if let Some(validators) = validators.filter(|v| {
+            self.now()
+                .signed_duration_since(v.fetched)
+                .to_std()
+                .unwrap_or_default()
+                < max_age
+        }) {
+            if let Some(etag) = validators.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = validators.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let fetch_res = request.send().await.map_err(FetchError::from)?;
+        if fetch_res.status() == StatusCode::NOT_MODIFIED {
+            tracing::debug!("instance list not modified");
+            return Ok(None);
+        }
+        let header = |name| {
+            fetch_res
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let (_, body) = Self::read_response(url, fetch_res).await?;
+        *self.inner.list_validators.lock().unwrap() =
+            match etag.is_some() || last_modified.is_some() {
+                true => Some(ListValidators {
+                    etag,
+                    last_modified,
+                    fetched: self.now(),
+                }),
+                false => None,
+            };
+        Ok(Some(body))
+    }
+
+    /// Count a request of the instance checks for the connection stats
+    fn count_request(&self, url: &str) {
+        if let Some(host) = reqwest::Url::parse(url)
+            .ok()
+            .as_ref()
+            .and_then(reqwest::Url::host_str)
+        {
+            self.inner.app_state.count_request(host);
+        }
     }
 
     async fn fetch_url(&self, url: &str) -> std::result::Result<(u16, String), FetchError> {
+        self.count_request(url);
         let fetch_res = self.inner.client.get(url).send().await?;
+        Self::read_response(url, fetch_res).await
+    }
+
+    /// Fetch url, failing successful responses not matching any of the *content_types*
+    ///
+    /// Only verifies the Content-Type if enabled in the config, behaves like [Scanner::fetch_url] otherwise.
+    async fn fetch_url_typed(
+        &self,
+        url: &str,
+        content_types: &[&str],
+    ) -> std::result::Result<(u16, String), FetchError> {
+        self.fetch_url_typed_as(url, content_types, None).await
+    }
+
+    /// Like [Scanner::fetch_url_typed], sending *user_agent* instead of the default one if set
+    async fn fetch_url_typed_as(
+        &self,
+        url: &str,
+        content_types: &[&str],
+        user_agent: Option<&HeaderValue>,
+    ) -> std::result::Result<(u16, String), FetchError> {
+        self.count_request(url);
+        let mut request = self.inner.client.get(url);
+        if let Some(user_agent) = user_agent {
+            request = request.header(USER_AGENT, user_agent.clone());
+        }
+        let fetch_res = request.send().await?;
+        if self.inner.config.check_content_type && fetch_res.status().is_success() {
+            let content_type = fetch_res
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            // ignore parameters like the charset
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            if !content_types
+                .iter()
+                .any(|expected| mime.eq_ignore_ascii_case(expected))
+            {
+                return Err(FetchError::ContentType(
+                    fetch_res.status().as_u16(),
+                    content_type.to_owned(),
+                ));
+            }
+        }
+        Self::read_response(url, fetch_res).await
+    }
+
+    /// Read the body of a response, mapping error responses to [FetchError]
+    async fn read_response(
+        url: &str,
+        fetch_res: reqwest::Response,
+    ) -> std::result::Result<(u16, String), FetchError> {
         let code = fetch_res.status().as_u16();
         if !fetch_res.status().is_success() {
             let message = fetch_res
@@ -341,15 +821,17 @@ impl Scanner {
                 .canonical_reason()
                 .unwrap_or_default()
                 .to_owned();
-            let body_text = match fetch_res.text().await {
+            let body_text = match Self::read_body(url, fetch_res).await {
                 Err(e) => format!("Additionally failed reading response body: {:?}", e),
                 Ok(v) => v,
             };
-            if code == CAPTCHA_CODE && body_text.contains(CAPTCHA_TEXT) {
-                return Err(FetchError::Captcha);
+            if code == CAPTCHA_CODE && is_captcha(&body_text) {
+                return Err(FetchError::Captcha(code));
             }
-            if code == 403 && body_text.contains("You have been blocked") {
-                // cloudflare block
+            if code == 403
+                && (body_text.contains("You have been blocked") || body_text.contains("Error 1020"))
+            {
+                // cloudflare block, 1020 = firewall rule
                 return Err(FetchError::KnownHttpResponseStatus(code, message));
             }
             if code == 429 && body_text.contains("Instance has been rate limited") {
@@ -364,29 +846,74 @@ impl Scanner {
                 // don't spam the body on Bad Gateway/Service Unavailable/Gateway Timeout
                 return Err(FetchError::KnownHttpResponseStatus(code, message));
             }
-            if code >= 520 && code <= 527 {
+            if (code >= 520 && code <= 527) || code == 530 {
                 // don't spam the body on Cloudflare errors
                 // https://en.wikipedia.org/wiki/List_of_HTTP_status_codes
                 return Err(FetchError::KnownHttpResponseStatus(code, message));
             }
             return Err(FetchError::HttpResponseStatus(code, message, body_text));
         }
-        let body = fetch_res
-            .text()
+        let body = Self::read_body(url, fetch_res)
             .await
             .map_err(|e| FetchError::RetrievingBody(url.to_owned(), e))?;
+        if is_captcha(&body) {
+            return Err(FetchError::Captcha(code));
+        }
 
         Ok((code, body))
     }
+
+    /// Read the response body, decoded with the charset of its content type.
+    ///
+    /// Invalid or mislabeled bodies are decoded lossily instead of failing,
+    /// so the parsers can still look for the profile and version markers.
+    async fn read_body(url: &str, fetch_res: reqwest::Response) -> reqwest::Result<String> {
+        let charset = fetch_res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_type_charset)
+            .map(str::to_owned);
+        let bytes = fetch_res.bytes().await?;
+        Ok(decode_body(url, charset.as_deref(), &bytes))
+    }
+}
+
+/// Decode *body* with *charset*, falling back to UTF-8 for missing or unknown charsets
+fn decode_body(url: &str, charset: Option<&str>, body: &[u8]) -> String {
+    let encoding = charset
+        .and_then(|v| encoding_rs::Encoding::for_label(v.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    let (text, encoding, lossy) = encoding.decode(body);
+    if lossy {
+        tracing::info!(
+            url,
+            charset,
+            encoding = encoding.name(),
+            "response body contains invalid characters, decoded lossily"
+        );
+    }
+    text.into_owned()
+}
+
+/// Charset parameter of a Content-Type header value
+fn content_type_charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
+    use axum::{http::StatusCode, response::Html, routing::get, Router};
     use chrono::Duration;
-    use entities::health_check;
     use entities::state::scanner::Config;
+    use entities::{health_check, host};
     use migration::MigratorTrait;
     use sea_orm::{ActiveModelTrait, ActiveValue, ConnectOptions, Database};
     use tokio::{fs::File, io::AsyncWriteExt};
@@ -401,6 +928,580 @@ mod test {
         db
     }
 
+    /// In-memory database, private to the calling test
+    pub(crate) async fn db_memory() -> DatabaseConnection {
+        let mut opts = ConnectOptions::new("sqlite::memory:".to_owned());
+        // every connection would open its own in-memory database
+        opts.max_connections(1);
+        let db = Database::connect(opts).await.unwrap();
+        migration::Migrator::up(&db, None).await.unwrap();
+        db
+    }
+
+    /// Scanner for tests, doesn't fetch the git source or build the cache
+    pub(crate) fn test_scanner(db: DatabaseConnection) -> Scanner {
+        test_scanner_with(db, (*Config::test_defaults()).clone())
+    }
+
+    /// [test_scanner] with a custom *config*
+    pub(crate) fn test_scanner_with(db: DatabaseConnection, config: Config) -> Scanner {
+        Scanner::build(db, Arc::new(config), entities::state::new(), Utc::now()).unwrap()
+    }
+
+    /// Enabled host `localhost` at *url*, with all optional fields unset
+    pub(crate) fn test_host(url: &str) -> host::Model {
+        host::Model {
+            id: 1,
+            domain: String::from("localhost"),
+            url: url.to_owned(),
+            enabled: true,
+            rss: false,
+            version: None,
+            country: String::new(),
+            version_url: None,
+            connectivity: None,
+            updated: 0,
+            captcha: false,
+            expected_version_url: None,
+            cert_sha256: None,
+            rss_valid: None,
+            featured: false,
+            version_neutral: false,
+            force_disabled: false,
+            first_seen: 0,
+            public_last_error: false,
+        }
+    }
+
+    /// Enabled host *domain* at *url* for inserting, only the required fields are set
+    pub(crate) fn new_host(domain: &str, url: &str) -> host::ActiveModel {
+        host::ActiveModel {
+            domain: ActiveValue::Set(domain.to_owned()),
+            url: ActiveValue::Set(url.to_owned()),
+            enabled: ActiveValue::Set(true),
+            rss: ActiveValue::Set(false),
+            country: ActiveValue::Set(String::new()),
+            updated: ActiveValue::Set(0),
+            captcha: ActiveValue::Set(false),
+            ..Default::default()
+        }
+    }
+
+    /// Insert *host* into *db*, see [new_host]
+    pub(crate) async fn insert_host(
+        db: &DatabaseConnection,
+        host: host::ActiveModel,
+    ) -> host::Model {
+        host.insert(db).await.unwrap()
+    }
+
+    /// Routes of a healthy nitter instance, matching [Config::test_defaults]
+    pub(crate) fn mock_nitter() -> Router {
+        Router::new()
+            .route(
+                "/jack",
+                get(|| async { Html(include_str!("../test_data/profile.html")) }),
+            )
+            .route(
+                "/jack/rss",
+                get(|| async { r#"<?xml version="1.0"?><rss xmlns:atom="http://www.w3.org/2005/Atom" version="2.0"></rss>"# }),
+            )
+            .route(
+                "/about",
+                get(|| async { Html(include_str!("../test_data/about.html")) }),
+            )
+    }
+
+    /// Serve `router` on a random local port, returns the instance URL
+    pub(crate) async fn mock_instance(router: Router) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
+    /// Manually advanced clock
+    struct TestClock(Mutex<DateTime<Utc>>);
+
+    impl TestClock {
+        fn advance(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// Scanner with a [TestClock] starting at *start*, last checks and fetches are at *start*
+    fn clock_scanner(start: DateTime<Utc>) -> (Scanner, Arc<TestClock>) {
+        clock_scanner_with(start, (*Config::test_defaults()).clone())
+    }
+
+    /// [clock_scanner] with a custom *config*
+    fn clock_scanner_with(start: DateTime<Utc>, config: Config) -> (Scanner, Arc<TestClock>) {
+        let clock = Arc::new(TestClock(Mutex::new(start)));
+        let mut scanner = Scanner::build(
+            DatabaseConnection::Disconnected,
+            Arc::new(config),
+            entities::state::new(),
+            start,
+        )
+        .unwrap();
+        Arc::get_mut(&mut scanner.inner).unwrap().clock = clock.clone();
+        (scanner, clock)
+    }
+
+    #[test]
+    fn schedule_outdated_boundaries() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (scanner, clock) = clock_scanner(start);
+        // both intervals are 5 minutes in the test config
+        assert!(!scanner.is_instance_check_outdated());
+        assert!(!scanner.is_instance_list_outdated());
+
+        clock.advance(Duration::minutes(5) - Duration::seconds(1));
+        assert!(!scanner.is_instance_check_outdated());
+        assert!(!scanner.is_instance_list_outdated());
+
+        clock.advance(Duration::seconds(1));
+        assert!(scanner.is_instance_check_outdated());
+        assert!(scanner.is_instance_list_outdated());
+    }
+
+    #[test]
+    fn schedule_version_check() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (scanner, clock) = clock_scanner(start);
+        // disabled by default
+        clock.advance(Duration::days(1));
+        assert!(!scanner.is_version_check_outdated());
+
+        let mut config = (*Config::test_defaults()).clone();
+        config.version_check_interval = Some(std::time::Duration::from_secs(60));
+        let (mut scanner, clock) = clock_scanner(start);
+        Arc::get_mut(&mut scanner.inner).unwrap().config = Arc::new(config);
+        assert!(!scanner.is_version_check_outdated());
+        assert_eq!(
+            scanner.time_till_deadline(),
+            Some(std::time::Duration::from_secs(60))
+        );
+        clock.advance(Duration::minutes(1));
+        assert!(scanner.is_version_check_outdated());
+        assert_eq!(scanner.time_till_deadline(), None);
+    }
+
+    #[test]
+    fn schedule_clock_backwards() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (scanner, clock) = clock_scanner(start);
+        clock.advance(Duration::minutes(-10));
+        assert!(!scanner.is_instance_check_outdated());
+        assert!(!scanner.is_instance_list_outdated());
+        assert_eq!(
+            scanner.time_till_deadline(),
+            Some(std::time::Duration::from_secs(15 * 60))
+        );
+    }
+
+    #[test]
+    fn schedule_deadline() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (scanner, clock) = clock_scanner(start);
+        assert_eq!(
+            scanner.time_till_deadline(),
+            Some(std::time::Duration::from_secs(5 * 60))
+        );
+
+        clock.advance(Duration::minutes(4));
+        assert_eq!(
+            scanner.time_till_deadline(),
+            Some(std::time::Duration::from_secs(60))
+        );
+        // earliest deadline wins
+        *scanner.inner.last_list_fetch.lock().unwrap() = start + Duration::minutes(4);
+        assert_eq!(
+            scanner.time_till_deadline(),
+            Some(std::time::Duration::from_secs(60))
+        );
+
+        clock.advance(Duration::minutes(1));
+        assert_eq!(scanner.time_till_deadline(), None);
+        clock.advance(Duration::minutes(1));
+        assert_eq!(scanner.time_till_deadline(), None);
+    }
+
+    #[test]
+    fn schedule_published() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (scanner, clock) = clock_scanner(start);
+        assert!(scanner
+            .inner
+            .app_state
+            .schedule
+            .read()
+            .unwrap()
+            .next_wakeup
+            .is_none());
+        clock.advance(Duration::minutes(4));
+        scanner.publish_schedule(scanner.time_till_deadline());
+        let schedule = scanner.inner.app_state.schedule.read().unwrap().clone();
+        assert_eq!(schedule.last_uptime_check, Some(start));
+        assert_eq!(schedule.next_wakeup, Some(start + Duration::minutes(5)));
+
+        // due now
+        clock.advance(Duration::minutes(2));
+        scanner.publish_schedule(scanner.time_till_deadline());
+        let schedule = scanner.inner.app_state.schedule.read().unwrap().clone();
+        assert_eq!(schedule.next_wakeup, Some(start + Duration::minutes(6)));
+    }
+
+    #[tokio::test]
+    async fn fetch_url_status_mapping() {
+        let router = Router::new()
+            .route(
+                "/captcha",
+                get(|| async { (StatusCode::FORBIDDEN, CAPTCHA_TEXT) }),
+            )
+            .route(
+                "/blocked",
+                get(|| async { (StatusCode::FORBIDDEN, "You have been blocked") }),
+            )
+            .route(
+                "/firewall",
+                get(|| async { (StatusCode::FORBIDDEN, "Access denied | Error 1020") }),
+            )
+            .route(
+                "/limited",
+                get(|| async {
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        "Instance has been rate limited",
+                    )
+                }),
+            )
+            .route(
+                "/unavailable",
+                get(|| async { (StatusCode::SERVICE_UNAVAILABLE, "down") }),
+            )
+            .route(
+                "/cf-down",
+                get(|| async { (StatusCode::from_u16(521).unwrap(), "origin down") }),
+            )
+            .route(
+                "/cf-530",
+                get(|| async { (StatusCode::from_u16(530).unwrap(), "origin error") }),
+            )
+            .route(
+                "/other",
+                get(|| async { (StatusCode::IM_A_TEAPOT, "teapot") }),
+            );
+        let url = mock_instance(router).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+
+        let fetch = |path: &'static str| {
+            let scanner = scanner.clone();
+            let url = format!("{url}{path}");
+            async move { scanner.fetch_url(&url).await.unwrap_err() }
+        };
+
+        let err = fetch("/captcha").await;
+        assert!(matches!(err, FetchError::Captcha(403)), "{err:?}");
+        assert!(err.is_blocked());
+        assert_eq!(err.to_host_error().http_status, Some(403));
+
+        for (path, code) in [
+            ("/blocked", 403),
+            ("/firewall", 403),
+            ("/limited", 429),
+            ("/unavailable", 503),
+            ("/cf-down", 521),
+            ("/cf-530", 530),
+        ] {
+            let err = fetch(path).await;
+            assert!(
+                matches!(err, FetchError::KnownHttpResponseStatus(c, _) if c == code),
+                "{path}: {err:?}"
+            );
+            assert_eq!(err.is_blocked(), code == 403, "{path}");
+            let host_error = err.to_host_error();
+            assert_eq!(host_error.http_status, Some(code as _), "{path}");
+            assert_eq!(host_error.http_body, None, "{path}");
+        }
+
+        let err = fetch("/other").await;
+        assert!(
+            matches!(err, FetchError::HttpResponseStatus(418, _, _)),
+            "{err:?}"
+        );
+        let host_error = err.to_host_error();
+        assert_eq!(host_error.http_status, Some(418));
+        assert_eq!(host_error.http_body.as_deref(), Some("teapot"));
+    }
+
+    #[tokio::test]
+    async fn fetch_url_lossy_body() {
+        let router = Router::new()
+            .route(
+                "/invalid",
+                get(|| async {
+                    (
+                        [("content-type", "text/html; charset=utf-8")],
+                        b"<p>caf\xe9</p>".to_vec(),
+                    )
+                }),
+            )
+            .route(
+                "/latin1",
+                get(|| async {
+                    (
+                        [("content-type", "text/html; charset=\"ISO-8859-1\"")],
+                        b"<p>caf\xe9</p>".to_vec(),
+                    )
+                }),
+            )
+            .route(
+                "/unknown",
+                get(|| async {
+                    (
+                        [("content-type", "text/html; charset=nonsense")],
+                        "<p>café</p>",
+                    )
+                }),
+            );
+        let url = mock_instance(router).await;
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+
+        let (code, body) = scanner.fetch_url(&format!("{url}/invalid")).await.unwrap();
+        assert_eq!(code, 200);
+        assert_eq!(body, "<p>caf\u{FFFD}</p>");
+        let (_, body) = scanner.fetch_url(&format!("{url}/latin1")).await.unwrap();
+        assert_eq!(body, "<p>café</p>");
+        let (_, body) = scanner.fetch_url(&format!("{url}/unknown")).await.unwrap();
+        assert_eq!(body, "<p>café</p>");
+    }
+
+    #[test]
+    fn content_type_charsets() {
+        assert_eq!(
+            content_type_charset("text/html; charset=utf-8"),
+            Some("utf-8")
+        );
+        assert_eq!(
+            content_type_charset("text/html;Charset=\"latin1\""),
+            Some("latin1")
+        );
+        assert_eq!(content_type_charset("text/html"), None);
+        assert_eq!(content_type_charset("charset=utf-8"), None);
+    }
+
+    #[tokio::test]
+    async fn fetch_instance_list_auth() {
+        let router = Router::new()
+            .route(
+                "/wiki",
+                get(|headers: axum::http::HeaderMap| async move {
+                    match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                        Some("Bearer secret") => (StatusCode::OK, "instances"),
+                        _ => (StatusCode::UNAUTHORIZED, ""),
+                    }
+                }),
+            )
+            .route(
+                "/instance",
+                get(|headers: axum::http::HeaderMap| async move {
+                    match headers.contains_key("authorization") {
+                        true => StatusCode::BAD_REQUEST,
+                        false => StatusCode::OK,
+                    }
+                }),
+            );
+        let url = mock_instance(router).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.instance_list_url = format!("{url}/wiki");
+        config.instance_list_auth = Some(String::from("secret"));
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        assert_eq!(
+            scanner.fetch_instance_list().await.unwrap().as_deref(),
+            Some("instances")
+        );
+        // not leaked to instance requests
+        scanner.fetch_url(&format!("{url}/instance")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_proxy() {
+        // plain HTTP proxies receive the request with the absolute URL
+        let proxy = mock_instance(
+            Router::new()
+                .route("/instance", get(|| async { "proxied" }))
+                .route("/wiki", get(|| async { "proxied list" })),
+        )
+        .await;
+        let url =
+            mock_instance(Router::new().route("/wiki", get(|| async { "direct list" }))).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.instance_list_url = format!("{url}/wiki");
+        config.http_proxy = Some(proxy);
+        let build = |config: &Config| {
+            Scanner::build(
+                DatabaseConnection::Disconnected,
+                Arc::new(config.clone()),
+                entities::state::new(),
+                Utc::now(),
+            )
+        };
+        let scanner = build(&config).unwrap();
+        assert_eq!(
+            scanner
+                .fetch_url("http://nitter.invalid/instance")
+                .await
+                .unwrap()
+                .1,
+            "proxied"
+        );
+        assert_eq!(
+            scanner.fetch_instance_list().await.unwrap().as_deref(),
+            Some("direct list")
+        );
+
+        config.http_proxy_instance_list = true;
+        let scanner = build(&config).unwrap();
+        assert_eq!(
+            scanner.fetch_instance_list().await.unwrap().as_deref(),
+            Some("proxied list")
+        );
+
+        config.http_proxy = Some(String::from("not a proxy"));
+        assert!(build(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn connection_stats() {
+        let url = mock_instance(mock_nitter())
+            .await
+            .replace("127.0.0.1", "localhost");
+        let scanner = test_scanner(DatabaseConnection::Disconnected);
+        for _ in 0..3 {
+            scanner.fetch_url(&format!("{url}/about")).await.unwrap();
+        }
+        let stats = scanner.inner.app_state.connection_stats.read().unwrap()["localhost"].clone();
+        assert_eq!(stats.requests, 3);
+        // reused from the pool
+        assert_eq!(stats.connections, 1);
+    }
+
+    #[tokio::test]
+    async fn fetch_instance_list_challenge_solver() {
+        let solver = Router::new().route(
+            "/v1",
+            axum::routing::post(|body: String| async move {
+                let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+                assert_eq!(request["cmd"], "request.get");
+                match request["url"].as_str().unwrap().ends_with("/wiki") {
+                    true => serde_json::json!({
+                        "status": "ok",
+                        "message": "Challenge solved!",
+                        "solution": {"status": 200, "response": "instances"},
+                    }),
+                    false => serde_json::json!({
+                        "status": "error",
+                        "message": "Error solving the challenge",
+                    }),
+                }
+                .to_string()
+            }),
+        );
+        let solver = format!("{}/v1", mock_instance(solver).await);
+        let url = mock_instance(
+            Router::new()
+                .route(
+                    "/wiki",
+                    get(|| async { (StatusCode::FORBIDDEN, CAPTCHA_TEXT) }),
+                )
+                .route(
+                    "/other",
+                    get(|| async { (StatusCode::FORBIDDEN, CAPTCHA_TEXT) }),
+                ),
+        )
+        .await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.instance_list_url = format!("{url}/wiki");
+        let build =
+            |config: &Config| test_scanner_with(DatabaseConnection::Disconnected, config.clone());
+        // opt-in only
+        let err = build(&config).fetch_instance_list().await.unwrap_err();
+        assert!(
+            matches!(err, ScannerError::FetchError(FetchError::Captcha(403))),
+            "{err:?}"
+        );
+
+        config.instance_list_challenge_solver = Some(solver);
+        assert_eq!(
+            build(&config)
+                .fetch_instance_list()
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("instances")
+        );
+        // instance checks never use the solver
+        assert!(matches!(
+            build(&config)
+                .fetch_url(&format!("{url}/other"))
+                .await
+                .unwrap_err(),
+            FetchError::Captcha(403)
+        ));
+
+        config.instance_list_url = format!("{url}/other");
+        let err = build(&config).fetch_instance_list().await.unwrap_err();
+        assert!(matches!(err, ScannerError::ChallengeSolver(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn fetch_instance_list_conditional() {
+        let router = Router::new().route(
+            "/wiki",
+            get(|headers: axum::http::HeaderMap| async move {
+                match headers.get("if-none-match").and_then(|v| v.to_str().ok()) {
+                    Some(r#""v1""#) => (StatusCode::NOT_MODIFIED, [("etag", r#""v1""#)], ""),
+                    _ => (StatusCode::OK, [("etag", r#""v1""#)], "instances"),
+                }
+            }),
+        );
+        let url = mock_instance(router).await;
+        let mut config = (*Config::test_defaults()).clone();
+        config.instance_list_url = format!("{url}/wiki");
+        config.list_conditional_max_age = Some(std::time::Duration::from_secs(60));
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (scanner, clock) = clock_scanner_with(start, config.clone());
+        assert_eq!(
+            scanner.fetch_instance_list().await.unwrap().as_deref(),
+            Some("instances")
+        );
+        clock.advance(Duration::seconds(59));
+        assert_eq!(scanner.fetch_instance_list().await.unwrap(), None);
+
+        // full fetch after max age
+        clock.advance(Duration::seconds(1));
+        assert_eq!(
+            scanner.fetch_instance_list().await.unwrap().as_deref(),
+            Some("instances")
+        );
+
+        // disabled
+        config.list_conditional_max_age = None;
+        let scanner = test_scanner_with(DatabaseConnection::Disconnected, config);
+        assert!(scanner.fetch_instance_list().await.unwrap().is_some());
+        assert!(scanner.fetch_instance_list().await.unwrap().is_some());
+    }
+
     // only for generating fake data
     // still requires copying over the DB for running on it
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
@@ -416,6 +1517,7 @@ mod test {
                 resp_time: ActiveValue::Set(Some(12)),
                 healthy: ActiveValue::Set(v % 2 == 0),
                 response_code: ActiveValue::Set(Some(200)),
+                blocked: ActiveValue::Set(false),
             }
             .insert(&db)
             .await
@@ -430,7 +1532,7 @@ mod test {
         let scanner = Scanner::new(db, Config::test_defaults(), entities::state::new())
             .await
             .unwrap();
-        let res = scanner.fetch_instance_list().await.unwrap();
+        let res = scanner.fetch_instance_list().await.unwrap().unwrap();
         let mut file = File::create("test_data/instancelist.html").await.unwrap();
         file.write_all(&res.as_bytes()).await.unwrap();
     }
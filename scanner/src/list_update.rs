@@ -1,36 +1,197 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //! Updates the list of available instances, fetching all required fields
 
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
 use chrono::Utc;
+use entities::check_errors;
+use entities::connectivity_check;
 use entities::host;
 use entities::prelude::Host;
+use entities::rss_check;
+use entities::state::{ListParseDebug, ParsedInstance};
 use reqwest::Url;
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, TransactionTrait,
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter,
+    TransactionTrait,
 };
 use sea_query::OnConflict;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
 use tokio::task::JoinSet;
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::{Certificate, ServerName};
+use tokio_rustls::TlsConnector;
 use tracing::instrument;
 
+use crate::instance_check::{RssCheck, VersionCheckError};
+use crate::instance_parser::{exclude_instances, InstanceListError, InstanceMap};
+use crate::parse_instance_url;
+use crate::version_check::is_pinned_version;
 use crate::Result;
 use crate::Scanner;
 
+/// Timeout for reading the served certificate of a pinned host
+const CERT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate verifier accepting any server certificate
+///
+/// Used only for reading the served certificate of pinned hosts, which is then compared by fingerprint.
+/// Otherwise a self-signed or invalid certificate would fail the handshake and never be reported as mismatch.
+pub(crate) struct AnyServerCert;
+
+impl ServerCertVerifier for AnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Whether *fingerprint* is one of the comma separated *pins*
+fn is_pinned_cert(pins: &str, fingerprint: &str) -> bool {
+    pins.split(',')
+        .any(|pin| pin.trim().eq_ignore_ascii_case(fingerprint))
+}
+
+/// Mismatch message for hosts reporting a different version than pinned by the operator
+fn pinned_version_mismatch(host: &host::Model, version_url: &str) -> Option<String> {
+    let pinned = host.expected_version_url.as_deref()?;
+    if is_pinned_version(pinned, version_url) {
+        return None;
+    }
+    tracing::warn!(
+        domain = host.domain,
+        pinned,
+        version_url,
+        "host version doesn't match pinned version"
+    );
+    Some(format!(
+        "Version mismatch, pinned '{pinned}' but found '{version_url}'"
+    ))
+}
+
+/// Mismatch message for hosts serving a certificate not pinned by the operator
+///
+/// Multiple fingerprints can be pinned, for hosts with rotating certificates like behind Cloudflare.
+fn pinned_cert_mismatch(host: &host::Model, fingerprint: &str) -> Option<String> {
+    let pins = host.cert_sha256.as_deref()?;
+    if is_pinned_cert(pins, fingerprint) {
+        return None;
+    }
+    tracing::warn!(
+        domain = host.domain,
+        pins,
+        fingerprint,
+        "host certificate doesn't match pinned certificates"
+    );
+    Some(format!(
+        "Certificate mismatch, pinned '{pins}' but served '{fingerprint}'"
+    ))
+}
+
+/// Record the connectivity of host *domain* if it changed, *previous* is the host before the update
+async fn record_connectivity(
+    transaction: &DatabaseTransaction,
+    domain: &str,
+    previous: Option<&host::Model>,
+    connectivity: Option<host::Connectivity>,
+    time: i64,
+) -> Result<()> {
+    let host = match previous {
+        Some(host) if host.connectivity == connectivity => return Ok(()),
+        Some(host) => host.id,
+        // newly inserted
+        None => match Host::find()
+            .filter(host::Column::Domain.eq(domain))
+            .one(transaction)
+            .await?
+        {
+            Some(host) => host.id,
+            None => return Ok(()),
+        },
+    };
+    connectivity_check::ActiveModel {
+        time: ActiveValue::Set(time),
+        host: ActiveValue::Set(host),
+        connectivity: ActiveValue::Set(connectivity),
+    }
+    .insert(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Record the RSS check of host *domain*, *previous* is the host before the update
+async fn record_rss_check(
+    transaction: &DatabaseTransaction,
+    domain: &str,
+    previous: Option<&host::Model>,
+    check: RssCheck,
+    time: i64,
+) -> Result<()> {
+    let host = match previous {
+        Some(host) => host.id,
+        // newly inserted
+        None => match Host::find()
+            .filter(host::Column::Domain.eq(domain))
+            .one(transaction)
+            .await?
+        {
+            Some(host) => host.id,
+            None => return Ok(()),
+        },
+    };
+    rss_check::ActiveModel {
+        time: ActiveValue::Set(time),
+        host: ActiveValue::Set(host),
+        resp_time: ActiveValue::Set(check.resp_time),
+        http_status: ActiveValue::Set(check.http_status.map(i32::from)),
+        available: ActiveValue::Set(check.available),
+        valid: ActiveValue::Set(check.valid),
+    }
+    .insert(transaction)
+    .await?;
+    Ok(())
+}
+
 impl Scanner {
     /// Fetches the list of all instances from the wiki.  
     /// Updates all fields for host::Model, including connectivity, rss, version and enabled.
     #[instrument]
     pub(crate) async fn update_instacelist(&mut self) -> Result<()> {
         let start = Instant::now();
-        let html: String = self.fetch_instance_list().await?;
-        let parsed_instances = self.inner.instance_parser.parse_instancelist(
+        let Some(html) = self.fetch_instance_list().await? else {
+            // unchanged, skip parsing and re-checking all hosts
+            *self.inner.last_list_fetch.lock().unwrap() = self.now();
+            return Ok(());
+        };
+        let mut failed_rows = Vec::new();
+        let parsed = self.inner.instance_parser.parse_instancelist(
             &html,
             &self.inner.config.additional_hosts,
             &self.inner.config.additional_host_country,
+            self.inner.config.require_https,
+            Some(&mut failed_rows),
             false,
-        )?;
+        );
+        self.store_list_debug(&parsed, failed_rows);
+        let mut parsed_instances = parsed?;
+        // excluded hosts are missing from the list and thus disabled below
+        exclude_instances(
+            &mut parsed_instances,
+            &self.inner.config.exclude_hosts,
+            self.inner.exclude_hosts_regex.as_ref(),
+        );
 
         let transaction = self.inner.db.begin().await?;
 
@@ -44,6 +205,13 @@ impl Scanner {
         let mut removed = 0;
         for host in enabled_hosts.iter() {
             if !parsed_instances.contains_key(&host.domain) {
+                if self.inner.config.dry_run {
+                    tracing::info!(
+                        dry_run = true,
+                        domain = host.domain,
+                        "dry-run: would disable host"
+                    );
+                }
                 host::ActiveModel {
                     id: ActiveValue::Set(host.id),
                     enabled: ActiveValue::Set(false),
@@ -57,6 +225,13 @@ impl Scanner {
         }
         // now update/insert the existing ones
         let found_instances: usize = parsed_instances.len();
+        // state before this update, by domain
+        let known_hosts: HashMap<String, host::Model> = Host::find()
+            .all(&transaction)
+            .await?
+            .into_iter()
+            .map(|host| (host.domain.clone(), host))
+            .collect();
         // find last update checks to detect spam
         let last_status = self.query_latest_check(&transaction).await?;
         let mut join_set = JoinSet::new();
@@ -71,34 +246,55 @@ impl Scanner {
                     .find(|v| v.domain == instance.domain)
                     .map_or(false, |check| !check.healthy),
             };
+            let cert_pin = known_hosts
+                .get(&instance.domain)
+                .and_then(|host| host.cert_sha256.clone());
             // tracing::trace!(muted_host,instance=?instance,last_status=?last_status);
             join_set.spawn(async move {
-                let (connectivity, rss, version, version_url) = match Url::parse(&instance.url) {
-                    Err(_) => {
-                        if !muted_host {
-                            tracing::info!(url = instance.url, "Instance URL invalid");
-                        }
-                        (None, false, None, None)
-                    }
-                    Ok(mut url) => {
-                        let connectivity = scanner_c.check_connectivity(&mut url).await;
-                        // prevent DoS
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        let rss = scanner_c.has_rss(&mut url, muted_host).await;
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        match scanner_c.nitter_version(&mut url, muted_host).await {
-                            Some(version) => (
-                                connectivity,
-                                rss,
-                                Some(version.version_name),
-                                Some(version.url),
-                            ),
-                            None => (connectivity, rss, None, None),
+                let served_cert = match (&cert_pin, parse_instance_url(&instance.url)) {
+                    (Some(_), Ok(url)) => match scanner_c.served_cert_sha256(&url).await {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            tracing::info!(error=%e, domain = instance.domain, url = instance.url, "reading served certificate failed");
+                            None
                         }
-                    }
+                    },
+                    _ => None,
                 };
+                let (connectivity, rss, version, version_url, captcha) =
+                    match parse_instance_url(&instance.url) {
+                        Err(e) => {
+                            if !muted_host {
+                                tracing::info!(error = %e, domain = instance.domain, "Instance URL invalid");
+                            }
+                            (None, None, None, None, false)
+                        }
+                        Ok(mut url) => {
+                            let connectivity = scanner_c.check_connectivity(&mut url).await;
+                            // prevent DoS
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            let rss = Some(scanner_c.check_rss(&mut url, muted_host).await);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            match scanner_c.nitter_version(&mut url, muted_host).await {
+                                Ok(version) => (
+                                    connectivity,
+                                    rss,
+                                    Some(version.version_name),
+                                    Some(version.url),
+                                    false,
+                                ),
+                                Err(e) => (
+                                    connectivity,
+                                    rss,
+                                    None,
+                                    None,
+                                    e == VersionCheckError::Captcha,
+                                ),
+                            }
+                        }
+                    };
 
-                host::ActiveModel {
+                let model = host::ActiveModel {
                     id: ActiveValue::NotSet,
                     domain: ActiveValue::Set(instance.domain),
                     country: ActiveValue::Set(instance.country),
@@ -106,13 +302,51 @@ impl Scanner {
                     enabled: ActiveValue::Set(true),
                     version: ActiveValue::Set(version),
                     version_url: ActiveValue::Set(version_url),
-                    rss: ActiveValue::Set(rss),
+                    rss: ActiveValue::Set(rss.as_ref().is_some_and(|v| v.available)),
+                    rss_valid: ActiveValue::Set(rss.as_ref().and_then(|v| v.valid)),
                     updated: ActiveValue::Set(time.timestamp()),
                     connectivity: ActiveValue::Set(connectivity),
-                }
+                    captcha: ActiveValue::Set(captcha),
+                    expected_version_url: ActiveValue::NotSet,
+                    cert_sha256: ActiveValue::NotSet,
+                    force_disabled: ActiveValue::NotSet,
+                    featured: ActiveValue::NotSet,
+                    version_neutral: ActiveValue::NotSet,
+                    // only set on insert, not part of the conflict update
+                    first_seen: ActiveValue::Set(time.timestamp()),
+                    public_last_error: ActiveValue::NotSet,
+                };
+                (model, served_cert, rss)
             });
         }
-        while let Some(update_model) = join_set.join_next().await.map(|v| v.unwrap()) {
+        while let Some((update_model, served_cert, rss)) =
+            join_set.join_next().await.map(|v| v.unwrap())
+        {
+            if self.inner.config.dry_run {
+                tracing::info!(dry_run = true, host = ?update_model, "dry-run: would upsert host");
+            }
+            let (ActiveValue::Set(domain), ActiveValue::Set(connectivity)) = (
+                update_model.domain.clone(),
+                update_model.connectivity.clone(),
+            ) else {
+                unreachable!("domain and connectivity are always set");
+            };
+            let previous = known_hosts.get(&domain);
+            if let Some(previous) = previous {
+                let version_mismatch = match &update_model.version_url {
+                    ActiveValue::Set(Some(version_url)) => {
+                        pinned_version_mismatch(previous, version_url)
+                    }
+                    _ => None,
+                };
+                let cert_mismatch = served_cert
+                    .as_deref()
+                    .and_then(|cert| pinned_cert_mismatch(previous, cert));
+                let mismatches: Vec<String> =
+                    version_mismatch.into_iter().chain(cert_mismatch).collect();
+                self.record_pin_mismatches(&transaction, previous, &mismatches)
+                    .await?;
+            }
             Host::insert(update_model)
                 .on_conflict(
                     OnConflict::column(host::Column::Domain)
@@ -121,22 +355,41 @@ impl Scanner {
                             host::Column::Updated,
                             host::Column::Url,
                             host::Column::Rss,
+                            host::Column::RssValid,
                             host::Column::Version,
                             host::Column::VersionUrl,
                             host::Column::Country,
                             host::Column::Connectivity,
+                            host::Column::Captcha,
                         ])
                         .to_owned(),
                 )
                 .exec(&transaction)
                 .await?;
+            record_connectivity(
+                &transaction,
+                &domain,
+                previous,
+                connectivity,
+                time.timestamp(),
+            )
+            .await?;
+            if let Some(rss) = rss {
+                record_rss_check(&transaction, &domain, previous, rss, time.timestamp()).await?;
+            }
         }
 
-        transaction.commit().await?;
+        if self.inner.config.dry_run {
+            tracing::info!(dry_run = true, "dry-run: rolling back instance list update");
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
         let end = Instant::now();
         let took_ms = end.saturating_duration_since(start).as_millis();
         {
-            *self.inner.last_list_fetch.lock().unwrap() = Utc::now();
+            *self.inner.last_list_fetch.lock().unwrap() = self.now();
+            *self.inner.last_version_check.lock().unwrap() = self.now();
         }
         tracing::debug!(
             removed = removed,
@@ -146,8 +399,159 @@ impl Scanner {
         Ok(())
     }
 
+    /// Re-check only the version of all enabled hosts, without fetching the instance list.
+    ///
+    /// Hosts failing the version check keep their version until the next list update.
+    #[instrument]
+    pub(crate) async fn update_versions(&self) -> Result<()> {
+        let start = Instant::now();
+        let hosts = Host::find()
+            .filter(host::Column::Enabled.eq(true))
+            .all(&self.inner.db)
+            .await?;
+        let mut join_set = JoinSet::new();
+        for host in hosts {
+            let scanner_c = self.clone();
+            join_set.spawn(async move {
+                let version = match parse_instance_url(&host.url) {
+                    Err(e) => {
+                        tracing::debug!(error = %e, domain = host.domain, "skipping version check");
+                        None
+                    }
+                    Ok(mut url) => scanner_c.nitter_version(&mut url, true).await.ok(),
+                };
+                (host, version)
+            });
+        }
+        let transaction = self.inner.db.begin().await?;
+        let mut changed = 0;
+        while let Some((host, version)) = join_set.join_next().await.map(|v| v.unwrap()) {
+            let Some(version) = version else {
+                continue;
+            };
+            if host.version_url.as_deref() == Some(version.url.as_str()) {
+                continue;
+            }
+            if self.inner.config.dry_run {
+                tracing::info!(
+                    dry_run = true,
+                    domain = host.domain,
+                    version = version.version_name,
+                    "dry-run: would update host version"
+                );
+            }
+            let mismatches: Vec<String> = pinned_version_mismatch(&host, &version.url)
+                .into_iter()
+                .collect();
+            self.record_pin_mismatches(&transaction, &host, &mismatches)
+                .await?;
+            host::ActiveModel {
+                id: ActiveValue::Set(host.id),
+                version: ActiveValue::Set(Some(version.version_name)),
+                version_url: ActiveValue::Set(Some(version.url)),
+                ..Default::default()
+            }
+            .update(&transaction)
+            .await?;
+            changed += 1;
+        }
+        if self.inner.config.dry_run {
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
+        *self.inner.last_version_check.lock().unwrap() = self.now();
+        tracing::debug!(
+            changed,
+            took_ms = start.elapsed().as_millis(),
+            "updated versions"
+        );
+        Ok(())
+    }
+
+    /// Store the raw parsing result for debugging
+    fn store_list_debug(
+        &self,
+        parsed: &std::result::Result<InstanceMap, InstanceListError>,
+        failed_rows: Vec<String>,
+    ) {
+        let (instances, error) = match parsed {
+            Ok(instances) => (
+                instances
+                    .values()
+                    .map(|instance| ParsedInstance {
+                        domain: instance.domain.clone(),
+                        url: instance.url.clone(),
+                        online: instance.online,
+                        ssl_provider: instance.ssl_provider.clone(),
+                        country: instance.country.clone(),
+                    })
+                    .collect(),
+                None,
+            ),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        *self.inner.app_state.list_debug.write().unwrap() = ListParseDebug {
+            time: Some(Utc::now()),
+            error,
+            instances,
+            failed_rows,
+        };
+    }
+
+    /// Record one error for all pin *mismatches* of *host*
+    ///
+    /// Combined into a single row, as errors are keyed by host and time.
+    async fn record_pin_mismatches(
+        &self,
+        transaction: &DatabaseTransaction,
+        host: &host::Model,
+        mismatches: &[String],
+    ) -> Result<()> {
+        if mismatches.is_empty() || self.inner.config.dry_run {
+            return Ok(());
+        }
+        check_errors::ActiveModel {
+            time: ActiveValue::Set(Utc::now().timestamp()),
+            host: ActiveValue::Set(host.id),
+            message: ActiveValue::Set(mismatches.join("; ")),
+            http_body: ActiveValue::Set(None),
+            http_status: ActiveValue::Set(None),
+        }
+        .insert(transaction)
+        .await?;
+        Ok(())
+    }
+
+    /// SHA-256 fingerprint of the leaf certificate served by *url*, in lowercase hex
+    ///
+    /// The certificate itself is not verified, see [AnyServerCert].
+    pub(crate) async fn served_cert_sha256(&self, url: &Url) -> std::io::Result<String> {
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+        if url.scheme() != "https" {
+            return Err(invalid("not a https URL"));
+        }
+        let host = url.host_str().ok_or_else(|| invalid("URL without host"))?;
+        let name = ServerName::try_from(host).map_err(|_| invalid("invalid server name"))?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let connector = TlsConnector::from(Arc::clone(&self.inner.tls_config));
+        let stream = tokio::time::timeout(CERT_TIMEOUT, async {
+            let tcp = TcpStream::connect((host, port)).await?;
+            connector.connect(name, tcp).await
+        })
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        let cert = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .ok_or_else(|| invalid("no certificate served"))?;
+        Ok(base16ct::lower::encode_string(&Sha256::digest(&cert.0)))
+    }
+
     /// Check ipv4/6 connectivity of host
-    async fn check_connectivity(&self, url: &mut Url) -> Option<host::Connectivity> {
+    pub(crate) async fn check_connectivity(&self, url: &mut Url) -> Option<host::Connectivity> {
         url.set_path(&self.inner.config.connectivity_path);
         let ipv4 = self
             .inner
@@ -181,7 +585,280 @@ mod test {
     use entities::state::scanner::Config;
     use tracing_test::traced_test;
 
-    use crate::{test::db_init, Scanner};
+    use crate::{
+        test::{
+            db_init, db_memory, insert_host, mock_instance, mock_nitter, new_host, test_scanner,
+        },
+        Scanner,
+    };
+
+    #[tokio::test]
+    async fn update_versions() {
+        let url = mock_instance(mock_nitter()).await;
+        let db = db_memory().await;
+        let mut hosts = Vec::new();
+        for (domain, url) in [("up", url.as_str()), ("down", "http://127.0.0.1:1")] {
+            let host = insert_host(
+                &db,
+                host::ActiveModel {
+                    version: ActiveValue::Set(Some(String::from("old"))),
+                    version_url: ActiveValue::Set(Some(String::from("https://old"))),
+                    ..new_host(domain, url)
+                },
+            )
+            .await;
+            hosts.push(host.id);
+        }
+        let scanner = test_scanner(db);
+        scanner.update_versions().await.unwrap();
+
+        let up = Host::find_by_id(hosts[0])
+            .one(&scanner.inner.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(up.version.as_deref(), Some("2023.07.22-72d8f35"));
+        // failed checks keep the last version
+        let down = Host::find_by_id(hosts[1])
+            .one(&scanner.inner.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(down.version.as_deref(), Some("old"));
+        assert_eq!(down.version_url.as_deref(), Some("https://old"));
+    }
+
+    #[tokio::test]
+    async fn version_pin_mismatch() {
+        let db = db_memory().await;
+        let host = insert_host(
+            &db,
+            host::ActiveModel {
+                expected_version_url: ActiveValue::Set(Some(String::from(
+                    "https://github.com/zedeus/nitter/commit/72d8f35",
+                ))),
+                ..new_host("localhost", "https://localhost")
+            },
+        )
+        .await;
+        let scanner = test_scanner(db);
+
+        assert_eq!(
+            pinned_version_mismatch(&host, "https://github.com/zedeus/nitter/commit/72d8f35"),
+            None
+        );
+        let mismatch =
+            pinned_version_mismatch(&host, "https://github.com/zedeus/nitter/commit/b62d73d")
+                .unwrap();
+        let transaction = scanner.inner.db.begin().await.unwrap();
+        scanner
+            .record_pin_mismatches(&transaction, &host, &[mismatch])
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let errors = check_errors::Entity::find()
+            .filter(check_errors::Column::Host.eq(host.id))
+            .all(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("b62d73d"));
+    }
+
+    #[tokio::test]
+    async fn cert_pin_mismatch() {
+        let served = "3a7f".repeat(16);
+        let db = db_memory().await;
+        let host = insert_host(
+            &db,
+            host::ActiveModel {
+                cert_sha256: ActiveValue::Set(Some(format!("{},{served}", "00".repeat(32)))),
+                ..new_host("localhost", "https://localhost")
+            },
+        )
+        .await;
+        let scanner = test_scanner(db);
+
+        // any of the pins is accepted, case insensitive
+        assert_eq!(pinned_cert_mismatch(&host, &served.to_uppercase()), None);
+        let mismatch = pinned_cert_mismatch(&host, &"ff".repeat(32)).unwrap();
+        let transaction = scanner.inner.db.begin().await.unwrap();
+        scanner
+            .record_pin_mismatches(&transaction, &host, &[mismatch])
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let errors = check_errors::Entity::find()
+            .filter(check_errors::Column::Host.eq(host.id))
+            .all(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains(&"ff".repeat(32)));
+    }
+
+    #[tokio::test]
+    async fn version_and_cert_pin_mismatch() {
+        let db = db_memory().await;
+        let host = insert_host(
+            &db,
+            host::ActiveModel {
+                expected_version_url: ActiveValue::Set(Some(String::from(
+                    "https://github.com/zedeus/nitter/commit/72d8f35",
+                ))),
+                cert_sha256: ActiveValue::Set(Some("00".repeat(32))),
+                ..new_host("localhost", "https://localhost")
+            },
+        )
+        .await;
+        let scanner = test_scanner(db);
+
+        let mismatches: Vec<String> = [
+            pinned_version_mismatch(&host, "https://github.com/zedeus/nitter/commit/b62d73d"),
+            pinned_cert_mismatch(&host, &"ff".repeat(32)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let transaction = scanner.inner.db.begin().await.unwrap();
+        scanner
+            .record_pin_mismatches(&transaction, &host, &mismatches)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let errors = check_errors::Entity::find()
+            .filter(check_errors::Column::Host.eq(host.id))
+            .all(&scanner.inner.db)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("b62d73d"));
+        assert!(errors[0].message.contains(&"ff".repeat(32)));
+    }
+
+    #[tokio::test]
+    async fn served_cert_plain_http() {
+        let scanner = test_scanner(sea_orm::DatabaseConnection::Disconnected);
+        let url = Url::parse("http://127.0.0.1:1").unwrap();
+        assert!(scanner.served_cert_sha256(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn served_cert_test() {
+        let scanner = test_scanner(sea_orm::DatabaseConnection::Disconnected);
+        let fingerprint = scanner
+            .served_cert_sha256(&Url::parse("https://github.com").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(fingerprint.len(), 64);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn served_cert_self_signed_test() {
+        let scanner = test_scanner(sea_orm::DatabaseConnection::Disconnected);
+        // invalid certificates are still read for comparing against the pins
+        let fingerprint = scanner
+            .served_cert_sha256(&Url::parse("https://self-signed.badssl.com").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(fingerprint.len(), 64);
+    }
+
+    #[tokio::test]
+    async fn connectivity_history() {
+        let db = db_memory().await;
+        let host = insert_host(
+            &db,
+            host::ActiveModel {
+                connectivity: ActiveValue::Set(Some(host::Connectivity::IPv4)),
+                ..new_host("localhost", "https://localhost")
+            },
+        )
+        .await;
+        let transaction = db.begin().await.unwrap();
+        // unchanged
+        record_connectivity(
+            &transaction,
+            &host.domain,
+            Some(&host),
+            Some(host::Connectivity::IPv4),
+            1,
+        )
+        .await
+        .unwrap();
+        // changed
+        record_connectivity(
+            &transaction,
+            &host.domain,
+            Some(&host),
+            Some(host::Connectivity::All),
+            2,
+        )
+        .await
+        .unwrap();
+        // new host, looked up by domain
+        record_connectivity(&transaction, &host.domain, None, None, 3)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let history: Vec<_> = connectivity_check::Entity::find()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|check| (check.time, check.connectivity))
+            .collect();
+        assert_eq!(history, vec![(2, Some(host::Connectivity::All)), (3, None)]);
+    }
+
+    #[tokio::test]
+    async fn rss_history() {
+        let db = db_memory().await;
+        let host = insert_host(
+            &db,
+            host::ActiveModel {
+                rss: ActiveValue::Set(true),
+                ..new_host("localhost", "https://localhost")
+            },
+        )
+        .await;
+        let transaction = db.begin().await.unwrap();
+        let check = RssCheck {
+            available: true,
+            valid: None,
+            resp_time: Some(20),
+            http_status: Some(200),
+        };
+        record_rss_check(&transaction, &host.domain, Some(&host), check, 1)
+            .await
+            .unwrap();
+        // new host, looked up by domain
+        let check = RssCheck {
+            available: false,
+            valid: None,
+            resp_time: Some(5),
+            http_status: Some(429),
+        };
+        record_rss_check(&transaction, &host.domain, None, check, 2)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let history: Vec<_> = rss_check::Entity::find()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|check| (check.time, check.available, check.http_status))
+            .collect();
+        assert_eq!(history, vec![(1, true, Some(200)), (2, false, Some(429))]);
+    }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     #[traced_test]
@@ -13,12 +13,18 @@ pub enum ProfileParseError {
     NoProfileCard,
     #[error("No timeline div found!")]
     NoTimeline,
+    /// Nitter served its own error page, like "User not found"
+    #[error("Instance up, but content unavailable: {0}")]
+    ContentUnavailable(String),
 }
 
 pub(crate) struct ProfileParser {
     selector_profile_card_name: Selector,
     selector_timeline: Selector,
     selector_timeline_item: Selector,
+    selector_error_panel: Selector,
+    /// Additional lowercase body markers of error pages
+    unavailable_markers: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -33,9 +39,12 @@ impl ProfileParser {
         let fragment = Html::parse_fragment(html);
         // get profile info div
         let mut profile_card_name_divs = fragment.select(&self.selector_profile_card_name);
-        let first_card = profile_card_name_divs
-            .next()
-            .ok_or(ProfileParseError::NoProfileCard)?;
+        let Some(first_card) = profile_card_name_divs.next() else {
+            return Err(self.unavailable_reason(&fragment, html).map_or(
+                ProfileParseError::NoProfileCard,
+                ProfileParseError::ContentUnavailable,
+            ));
+        };
         let profile_name = first_card.text().fold(String::new(), |mut acc, text| {
             acc.push_str(text);
             acc
@@ -54,14 +63,35 @@ impl ProfileParser {
         })
     }
 
+    /// Message of a nitter error page, or the first configured marker found in *html*
+    fn unavailable_reason(&self, fragment: &Html, html: &str) -> Option<String> {
+        if let Some(panel) = fragment.select(&self.selector_error_panel).next() {
+            let message = panel.text().collect::<String>();
+            return Some(message.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+        let html = html.to_lowercase();
+        self.unavailable_markers
+            .iter()
+            .find(|marker| html.contains(marker.as_str()))
+            .cloned()
+    }
+
     pub fn new() -> Self {
         Self {
             selector_profile_card_name: Selector::parse(".profile-card-username")
                 .expect(EXPECT_CSS_SELCTOR),
             selector_timeline: Selector::parse(".timeline").expect(EXPECT_CSS_SELCTOR),
             selector_timeline_item: Selector::parse(".timeline-item").expect(EXPECT_CSS_SELCTOR),
+            selector_error_panel: Selector::parse(".error-panel").expect(EXPECT_CSS_SELCTOR),
+            unavailable_markers: Vec::new(),
         }
     }
+
+    /// Additional body markers of error pages without a nitter error panel, matched case-insensitive
+    pub fn unavailable_markers(mut self, markers: &[String]) -> Self {
+        self.unavailable_markers = markers.iter().map(|v| v.to_lowercase()).collect();
+        self
+    }
 }
 
 #[cfg(test)]
@@ -75,4 +105,26 @@ mod test {
         assert_eq!(&res.name, "@jack");
         assert_eq!(res.post_count, 20);
     }
+
+    #[test]
+    fn content_unavailable() {
+        let parser = ProfileParser::new().unavailable_markers(&[String::from("Account Gone")]);
+        let html = r#"<html><body><div class="error-panel"><span>User "jack" not found</span></div></body></html>"#;
+        match parser.parse_profile_content(html) {
+            Err(ProfileParseError::ContentUnavailable(message)) => {
+                assert_eq!(message, r#"User "jack" not found"#)
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+        match parser.parse_profile_content("<p>this account gone away</p>") {
+            Err(ProfileParseError::ContentUnavailable(message)) => {
+                assert_eq!(message, "account gone")
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+        assert!(matches!(
+            parser.parse_profile_content("<p>something else</p>"),
+            Err(ProfileParseError::NoProfileCard)
+        ));
+    }
 }
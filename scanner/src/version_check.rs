@@ -37,6 +37,19 @@ impl CurrentVersion {
     }
 }
 
+/// Whether a version URL points to the commit pinned by `pinned_url`
+///
+/// Only the commit, the last path segment, is compared and has to match exactly.
+pub(crate) fn is_pinned_version(pinned_url: &str, url: &str) -> bool {
+    let commit = |url: &str| {
+        url.trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .map(str::to_owned)
+    };
+    commit(pinned_url) == commit(url)
+}
+
 pub(crate) fn fetch_git_state(config: ScannerConfig) -> Result<CurrentVersion> {
     let mut remote = Remote::create_detached(config.source_git_url.as_str())?;
 
@@ -63,10 +76,32 @@ pub(crate) fn fetch_git_state(config: ScannerConfig) -> Result<CurrentVersion> {
 mod test {
     use entities::state::scanner::Config;
 
-    use super::fetch_git_state;
+    use super::{fetch_git_state, is_pinned_version};
 
     #[test]
     fn test_git() {
         fetch_git_state(Config::test_defaults()).unwrap();
     }
+
+    #[test]
+    fn pinned_version() {
+        let pinned = "https://github.com/zedeus/nitter/commit/72d8f35";
+        assert!(is_pinned_version(
+            pinned,
+            "https://github.com/zedeus/nitter/commit/72d8f35"
+        ));
+        assert!(is_pinned_version(
+            pinned,
+            "https://github.com/zedeus/nitter/commit/72d8f35/"
+        ));
+        // no prefix matching
+        assert!(!is_pinned_version(
+            pinned,
+            "https://github.com/zedeus/nitter/commit/72d8f35a"
+        ));
+        assert!(!is_pinned_version(
+            pinned,
+            "https://github.com/zedeus/nitter/commit/b62d73d"
+        ));
+    }
 }
@@ -1,29 +1,41 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use axum::extract::Path;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::response::Html;
 use axum::response::IntoResponse;
 use axum::response::Redirect;
 use axum::Form;
 use axum::Json;
+use chrono::TimeZone;
 use constant_time_eq::constant_time_eq;
 use entities::check_errors;
+use entities::connectivity_check;
 use entities::health_check;
 use entities::host;
-use entities::state::AppState;
+use entities::host_meta;
+use entities::points_history;
+use entities::protocol_probe;
+use entities::rss_check;
+use entities::state::scanner::ScannerConfig;
+use entities::state::{AppState, CacheHost, ConnectionStats};
 use hyper::header::REFERER;
 use hyper::HeaderMap;
 use hyper::StatusCode;
 use reqwest::Client;
 use reqwest::Url;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveModelTrait;
+use sea_orm::ActiveValue;
 use sea_orm::ColumnTrait;
 use sea_orm::DatabaseConnection;
 use sea_orm::EntityTrait;
 use sea_orm::QueryFilter;
 use sea_orm::QueryOrder;
 use sea_orm::QuerySelect;
+use sea_orm::TransactionTrait;
 use serde::Deserialize;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
@@ -34,6 +46,7 @@ use trust_dns_resolver::config::ResolverOpts;
 use trust_dns_resolver::AsyncResolver;
 
 use crate::Config;
+use crate::ReadDb;
 use crate::Result;
 use crate::ServerError;
 use crate::ADMIN_OVERVIEW_URL;
@@ -46,6 +59,12 @@ pub struct ActiveLogin {
     admin: bool,
 }
 const LOGIN_KEY: &'static str = "LOGIN";
+/// Results per group of the admin search
+const SEARCH_LIMIT: u64 = 50;
+/// Minimum query length of the admin search, shorter ones match almost everything
+const SEARCH_MIN_LENGTH: usize = 2;
+/// Only errors of the last 7 days are searched
+const SEARCH_ERRORS_MAX_AGE_S: i64 = 7 * 86400;
 
 /// Error shown to user, details aren't part of the error message, as they're displayed separately.
 #[derive(Error, Debug)]
@@ -70,9 +89,33 @@ pub enum LoginError {
     DNSError(#[from] trust_dns_resolver::error::ResolveError),
     #[error("No valid DNS TXT entry found for your key, found:")]
     DNSNoValidEntry(String),
+    #[error("HTTP verification failed: {http} DNS verification failed: {dns}")]
+    AllFailed {
+        http: Box<LoginError>,
+        dns: Box<LoginError>,
+    },
 }
 type LoginResult<T> = std::result::Result<T, LoginError>;
 
+impl LoginError {
+    /// Value found at the instance, to show with the error
+    fn quote(&self) -> Option<String> {
+        match self {
+            LoginError::InvalidResponse(_, val)
+            | LoginError::ServerResponse(_, val)
+            | LoginError::DNSNoValidEntry(val)
+            | LoginError::InvalidHash(val) => Some(val.clone()),
+            LoginError::AllFailed { http, dns } => match (http.quote(), dns.quote()) {
+                (Some(http), Some(dns)) => Some(format!("HTTP: {http} DNS: {dns}")),
+                (Some(http), None) => Some(format!("HTTP: {http}")),
+                (None, Some(dns)) => Some(format!("DNS: {dns}")),
+                (None, None) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
 pub async fn logout(session: Session) -> Result<axum::response::Response> {
     session.delete();
     Ok(Redirect::temporary(LOGIN_URL).into_response())
@@ -139,13 +182,9 @@ pub async fn login(
             context.insert("ERROR", &e.to_string());
             context.insert("LOGIN_METHOD", &input.verification_method);
             context.insert("DOMAIN", &input.domain);
-            context.insert("VERIFY_TOKEN_NAME", &config.login_token_name);
-            match e {
-                LoginError::InvalidResponse(_, val)
-                | LoginError::ServerResponse(_, val)
-                | LoginError::DNSNoValidEntry(val)
-                | LoginError::InvalidHash(val) => context.insert("QUOTE", &val),
-                _ => (),
+            insert_verify_context(&mut context, config);
+            if let Some(quote) = e.quote() {
+                context.insert("QUOTE", &quote);
             }
             let mut res = Html(template.render("login.html.j2", &context)?).into_response();
             *res.status_mut() = StatusCode::FORBIDDEN;
@@ -167,20 +206,54 @@ async fn login_inner(
     }
 
     match input.verification_method {
-        VerificationMethod::DNS => {
-            let entries = fetch_host_dns(&host.domain, &config).await?;
-            for entry in &entries {
-                if let Ok(_) = verify_key(entry, &input.key) {
-                    return Ok(host);
-                }
-            }
-            Err(LoginError::DNSNoValidEntry(entries.join(",")))
-        }
+        VerificationMethod::DNS => verify_dns(&host.domain, &input.key, config).await?,
         VerificationMethod::HTTP => {
-            let fetched_key = fetch_host_txt(&host.url, login_client, &config).await?;
-            verify_key(&fetched_key, &input.key).map(|_| host)
+            verify_http(&host.url, &input.key, login_client, config).await?
+        }
+        VerificationMethod::Auto => {
+            // whichever succeeds first, otherwise wait for both errors
+            let http = verify_http(&host.url, &input.key, login_client, config);
+            let dns = verify_dns(&host.domain, &input.key, config);
+            tokio::pin!(http, dns);
+            let res = tokio::select! {
+                res = &mut http => match res {
+                    Ok(()) => Ok(()),
+                    Err(http) => dns.await.map_err(|dns| (http, dns)),
+                },
+                res = &mut dns => match res {
+                    Ok(()) => Ok(()),
+                    Err(dns) => http.await.map_err(|http| (http, dns)),
+                },
+            };
+            res.map_err(|(http, dns)| LoginError::AllFailed {
+                http: Box::new(http),
+                dns: Box::new(dns),
+            })?
+        }
+    }
+    Ok(host)
+}
+
+/// Verify *key* against the DNS TXT entries of the instance
+async fn verify_dns(domain: &str, key: &str, config: &Config) -> LoginResult<()> {
+    let entries = fetch_host_dns(domain, config).await?;
+    for entry in &entries {
+        if let Ok(_) = verify_key(entry, key) {
+            return Ok(());
         }
     }
+    Err(LoginError::DNSNoValidEntry(entries.join(",")))
+}
+
+/// Verify *key* against the login file served by the instance
+async fn verify_http(
+    instance_url: &str,
+    key: &str,
+    client: &Client,
+    config: &Config,
+) -> LoginResult<()> {
+    let fetched_key = fetch_host_txt(instance_url, client, config).await?;
+    verify_key(&fetched_key, key)
 }
 
 #[derive(Deserialize, Debug)]
@@ -195,6 +268,8 @@ pub struct LoginInput {
 enum VerificationMethod {
     DNS,
     HTTP,
+    /// Both at once, either has to succeed
+    Auto,
 }
 
 async fn fetch_host_txt(
@@ -204,7 +279,7 @@ async fn fetch_host_txt(
 ) -> LoginResult<String> {
     let mut request_url =
         Url::parse(&instance_url).map_err(|_| LoginError::InstanceUrl(instance_url.to_string()))?;
-    request_url.set_path(&[".well-known/", &config.login_token_name].concat());
+    request_url.set_path(&config.login_token_path);
     request_url.set_query(None);
     let result = client
         .get(request_url.clone())
@@ -226,7 +301,12 @@ async fn fetch_host_dns(instance_domain: &str, config: &Config) -> LoginResult<V
     // TODO: cache resolver ?
     let resolver = AsyncResolver::tokio(ResolverConfig::cloudflare_tls(), ResolverOpts::default());
     let hashed_key = resolver
-        .txt_lookup(format!("{}.{}.", &config.login_token_name, instance_domain))
+        .txt_lookup(format!(
+            "{}.",
+            config
+                .login_token_dns_name
+                .replace("{domain}", instance_domain)
+        ))
         .await?;
 
     let mut entries = Vec::with_capacity(2);
@@ -257,6 +337,14 @@ fn verify_key(hash: &str, key: &str) -> LoginResult<()> {
     }
 }
 
+/// Insert the login token locations for the login template
+fn insert_verify_context(context: &mut tera::Context, config: &Config) {
+    let (dns_prefix, dns_suffix) = config.login_token_dns_parts();
+    context.insert("VERIFY_TOKEN_PATH", &config.login_token_path);
+    context.insert("VERIFY_DNS_PREFIX", dns_prefix);
+    context.insert("VERIFY_DNS_SUFFIX", dns_suffix);
+}
+
 pub async fn login_view(
     State(ref template): State<Arc<tera::Tera>>,
     State(ref config): State<Arc<crate::Config>>,
@@ -266,7 +354,7 @@ pub async fn login_view(
     let referrer = headers.get(REFERER).and_then(|v| v.to_str().ok());
     let mut context = tera::Context::new();
     context.insert("REFERRER", &referrer); // FIXME: won't work, handle this in the error part to extract the current situation
-    context.insert("VERIFY_TOKEN_NAME", &config.login_token_name);
+    insert_verify_context(&mut context, config);
     let res = Html(template.render("login.html.j2", &context)?).into_response();
     Ok(res)
 }
@@ -291,6 +379,14 @@ pub async fn overview(
         context.insert("last_updated", &time);
         context.insert("instances", &hosts);
         context.insert("is_admin", &login.admin);
+        // current cache state of all listed hosts, by domain
+        let statuses: HashMap<&str, &CacheHost> = guard
+            .hosts
+            .iter()
+            .filter(|host| hosts.iter().any(|v| v.domain == host.domain))
+            .map(|host| (host.domain.as_str(), host))
+            .collect();
+        context.insert("statuses", &statuses);
 
         let res = Html(template.render("admin.html.j2", &context)?).into_response();
         drop(guard);
@@ -301,6 +397,7 @@ pub async fn overview(
 
 pub async fn history_json(
     State(ref db): State<DatabaseConnection>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
     Path(host): Path<i32>,
     session: Session,
 ) -> Result<axum::response::Response> {
@@ -310,7 +407,7 @@ pub async fn history_json(
         .filter(health_check::Column::Host.eq(host.id))
         .order_by_asc(health_check::Column::Time)
         .limit(20)
-        .all(db)
+        .all(read_db)
         .await?;
 
     Ok(Json(history).into_response())
@@ -348,6 +445,7 @@ pub async fn errors_view(
     State(ref app_state): State<AppState>,
     State(ref template): State<Arc<tera::Tera>>,
     State(ref db): State<DatabaseConnection>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
     Path(host): Path<i32>,
     session: Session,
 ) -> Result<axum::response::Response> {
@@ -359,7 +457,7 @@ pub async fn errors_view(
         .filter(check_errors::Column::Host.eq(host.id))
         .order_by_desc(check_errors::Column::Time)
         .limit(20)
-        .all(db)
+        .all(read_db)
         .await?;
 
     let mut context = tera::Context::new();
@@ -380,6 +478,835 @@ pub async fn errors_view(
     Ok(res)
 }
 
+#[derive(Serialize)]
+struct HostConnections<'a> {
+    #[serde(skip_serializing_if = "str::is_empty")]
+    host: &'a str,
+    #[serde(flatten)]
+    stats: ConnectionStats,
+    /// Share of requests sent over an already open connection
+    reused_percent: u64,
+}
+
+impl<'a> HostConnections<'a> {
+    fn new(host: &'a str, stats: ConnectionStats) -> Self {
+        let reused = stats.requests.saturating_sub(stats.connections);
+        let reused_percent = match stats.requests {
+            0 => 0,
+            requests => reused * 100 / requests,
+        };
+        Self {
+            host,
+            stats,
+            reused_percent,
+        }
+    }
+}
+
+/// Admin only: connection reuse of the scanner instance checks since startup
+pub async fn debug_connections(
+    State(ref app_state): State<AppState>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let login = get_session_login(&session)?;
+    if !login.admin {
+        return Err(ServerError::MissingPermission);
+    }
+    let guard = app_state
+        .connection_stats
+        .read()
+        .map_err(|_| ServerError::MutexFailure)?;
+    let mut total = ConnectionStats::default();
+    let mut hosts: Vec<_> = guard
+        .iter()
+        .map(|(host, stats)| {
+            total.requests += stats.requests;
+            total.connections += stats.connections;
+            HostConnections::new(host, stats.clone())
+        })
+        .collect();
+    hosts.sort_unstable_by_key(|host| std::cmp::Reverse(host.stats.requests));
+    Ok(Json(serde_json::json!({
+        "total": HostConnections::new("", total),
+        "hosts": hosts,
+    }))
+    .into_response())
+}
+
+/// Last and next run of a scheduled scanner task
+#[derive(Serialize)]
+struct ScheduledTask {
+    last_run: Option<chrono::DateTime<chrono::Utc>>,
+    interval_s: u64,
+    next_run: Option<chrono::DateTime<chrono::Utc>>,
+    /// Seconds until the next run, negative if overdue
+    due_in_s: Option<i64>,
+}
+
+impl ScheduledTask {
+    fn new(
+        last_run: Option<chrono::DateTime<chrono::Utc>>,
+        interval: std::time::Duration,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        let next_run = last_run.and_then(|last| {
+            chrono::Duration::from_std(interval)
+                .ok()
+                .map(|interval| last + interval)
+        });
+        Self {
+            last_run,
+            interval_s: interval.as_secs(),
+            next_run,
+            due_in_s: next_run.map(|next| (next - now).num_seconds()),
+        }
+    }
+}
+
+/// Admin only: last and next runs of the scanner tasks
+pub async fn debug_schedule(
+    State(ref app_state): State<AppState>,
+    State(ref scanner_config): State<ScannerConfig>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let login = get_session_login(&session)?;
+    if !login.admin {
+        return Err(ServerError::MissingPermission);
+    }
+    let schedule = app_state
+        .schedule
+        .read()
+        .map_err(|_| ServerError::MutexFailure)?
+        .clone();
+    let now = chrono::Utc::now();
+    let version_check = scanner_config
+        .version_check_interval
+        .map(|interval| ScheduledTask::new(schedule.last_version_check, interval, now));
+    Ok(Json(serde_json::json!({
+        "now": now,
+        "next_wakeup": schedule.next_wakeup,
+        "uptime_check": ScheduledTask::new(
+            schedule.last_uptime_check,
+            scanner_config.instance_check_interval,
+            now
+        ),
+        "list_fetch": ScheduledTask::new(
+            schedule.last_list_fetch,
+            scanner_config.list_fetch_interval,
+            now
+        ),
+        "version_check": version_check,
+    }))
+    .into_response())
+}
+
+/// Single stored error of a host as JSON, including the full response body
+pub async fn error_json(
+    State(ref db): State<DatabaseConnection>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
+    Path((host, time)): Path<(i32, i64)>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let host = get_specific_login_host(host, &session, db).await?;
+
+    let error = check_errors::Entity::find_by_id((time, host.id))
+        .one(read_db)
+        .await?
+        .ok_or(ServerError::CheckErrorNotFound(host.id, time))?;
+    Ok(Json(error).into_response())
+}
+
+/// Admin only: raw result of the last instance list parsing
+pub async fn debug_instancelist(
+    State(ref app_state): State<AppState>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let login = get_session_login(&session)?;
+    if !login.admin {
+        return Err(ServerError::MissingPermission);
+    }
+    let guard = app_state
+        .list_debug
+        .read()
+        .map_err(|_| ServerError::MutexFailure)?;
+    Ok(Json(&*guard).into_response())
+}
+
+/// Connectivity changes of a host as CSV, oldest first
+pub async fn connectivity_csv(
+    State(ref db): State<DatabaseConnection>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
+    Path(host): Path<i32>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let host = get_specific_login_host(host, &session, db).await?;
+
+    let history = connectivity_check::Entity::find()
+        .filter(connectivity_check::Column::Host.eq(host.id))
+        .order_by_asc(connectivity_check::Column::Time)
+        .all(read_db)
+        .await?;
+
+    let mut csv = String::from("time,connectivity\n");
+    for entry in history {
+        let time = chrono::Utc
+            .timestamp_opt(entry.time, 0)
+            .single()
+            .unwrap_or_default();
+        let connectivity = match entry.connectivity {
+            Some(host::Connectivity::All) => "All",
+            Some(host::Connectivity::IPv4) => "IPv4",
+            Some(host::Connectivity::IPv6) => "IPv6",
+            None => "none",
+        };
+        csv.push_str(&format!("{},{connectivity}\n", time.to_rfc3339()));
+    }
+    Ok((
+        [
+            (
+                hyper::header::CONTENT_TYPE,
+                "text/csv; charset=utf-8".to_owned(),
+            ),
+            (
+                hyper::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"connectivity-{}.csv\"", host.domain),
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
+
+/// Hourly points of a host as CSV, oldest first
+pub async fn points_csv(
+    State(ref db): State<DatabaseConnection>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
+    Path(host): Path<i32>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let host = get_specific_login_host(host, &session, db).await?;
+
+    let history = points_history::Entity::find()
+        .filter(points_history::Column::Host.eq(host.id))
+        .order_by_asc(points_history::Column::Time)
+        .all(read_db)
+        .await?;
+
+    let mut csv = String::from("time,points\n");
+    for entry in history {
+        let time = chrono::Utc
+            .timestamp_opt(entry.time, 0)
+            .single()
+            .unwrap_or_default();
+        csv.push_str(&format!("{},{}\n", time.to_rfc3339(), entry.points));
+    }
+    Ok((
+        [
+            (
+                hyper::header::CONTENT_TYPE,
+                "text/csv; charset=utf-8".to_owned(),
+            ),
+            (
+                hyper::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"points-{}.csv\"", host.domain),
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
+
+/// Amount of RSS checks shown in the admin graph
+const RSS_HISTORY_LIMIT: u64 = 120;
+/// Height of the RSS graph in px
+const RSS_GRAPH_HEIGHT: i32 = 60;
+
+#[derive(Serialize)]
+struct RssGraphEntry {
+    #[serde(flatten)]
+    check: rss_check::Model,
+    /// Bar height relative to the slowest check
+    bar_height: i32,
+}
+
+/// RSS feed check history of a host, separate from the profile health checks
+pub async fn rss_view(
+    State(ref template): State<Arc<tera::Tera>>,
+    State(ref db): State<DatabaseConnection>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
+    Path(host): Path<i32>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let host = get_specific_login_host(host, &session, db).await?;
+
+    let mut checks = rss_check::Entity::find()
+        .filter(rss_check::Column::Host.eq(host.id))
+        .order_by_desc(rss_check::Column::Time)
+        .limit(RSS_HISTORY_LIMIT)
+        .all(read_db)
+        .await?;
+    checks.reverse();
+
+    let max_resp_time = checks
+        .iter()
+        .filter_map(|v| v.resp_time)
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    let available = checks.iter().filter(|v| v.available).count();
+    let graph: Vec<_> = checks
+        .into_iter()
+        .map(|check| {
+            // failed checks without any response use the full height
+            let bar_height = match (check.available, check.resp_time) {
+                (true, Some(resp_time)) => (resp_time * RSS_GRAPH_HEIGHT / max_resp_time).max(1),
+                (true, None) => 1,
+                (false, _) => RSS_GRAPH_HEIGHT,
+            };
+            RssGraphEntry { check, bar_height }
+        })
+        .collect();
+
+    let mut context = tera::Context::new();
+    context.insert("HOST_DOMAIN", &host.domain);
+    context.insert("CHECKS", &graph);
+    context.insert("AVAILABLE", &available);
+    context.insert("MAX_RESP_TIME", &max_resp_time);
+    context.insert("GRAPH_HEIGHT", &RSS_GRAPH_HEIGHT);
+    Ok(Html(template.render("rss_admin.html.j2", &context)?).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Serialize)]
+struct SearchError {
+    #[serde(flatten)]
+    error: check_errors::Model,
+    domain: String,
+}
+
+/// Admin only: hosts and recent errors containing the query
+pub async fn search(
+    State(ref template): State<Arc<tera::Tera>>,
+    State(ReadDb(ref read_db)): State<ReadDb>,
+    Query(query): Query<SearchQuery>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let login = get_session_login(&session)?;
+    if !login.admin {
+        return Err(ServerError::MissingPermission);
+    }
+    let q = query.q.trim();
+
+    let mut context = tera::Context::new();
+    context.insert("QUERY", q);
+    if q.len() >= SEARCH_MIN_LENGTH {
+        let hosts = host::Entity::find()
+            .filter(host::Column::Domain.contains(q))
+            .order_by_asc(host::Column::Domain)
+            .limit(SEARCH_LIMIT)
+            .all(read_db)
+            .await?;
+        let since = chrono::Utc::now().timestamp() - SEARCH_ERRORS_MAX_AGE_S;
+        let errors = check_errors::Entity::find()
+            .filter(check_errors::Column::Time.gte(since))
+            .filter(check_errors::Column::Message.contains(q))
+            .order_by_desc(check_errors::Column::Time)
+            .limit(SEARCH_LIMIT)
+            .all(read_db)
+            .await?;
+        let mut domains: HashMap<i32, String> = host::Entity::find()
+            .filter(host::Column::Id.is_in(errors.iter().map(|e| e.host)))
+            .all(read_db)
+            .await?
+            .into_iter()
+            .map(|host| (host.id, host.domain))
+            .collect();
+        let errors: Vec<_> = errors
+            .into_iter()
+            .map(|error| SearchError {
+                domain: domains.remove(&error.host).unwrap_or_default(),
+                error,
+            })
+            .collect();
+        context.insert("HOSTS", &hosts);
+        context.insert("ERRORS", &errors);
+    }
+    context.insert("SEARCH_LIMIT", &SEARCH_LIMIT);
+    context.insert("SEARCH_ERRORS_DAYS", &(SEARCH_ERRORS_MAX_AGE_S / 86400));
+    Ok(Html(template.render("search_admin.html.j2", &context)?).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SettingsInput {
+    /// Pinned version URL, empty to remove the pin
+    expected_version_url: String,
+    /// Accepted certificate fingerprints, comma separated, empty to remove the pin
+    cert_sha256: String,
+    /// Admin only, checkbox value if set
+    force_disabled: Option<String>,
+    /// Admin only, checkbox value if set
+    featured: Option<String>,
+    /// Admin only, checkbox value if set
+    version_neutral: Option<String>,
+    /// Checkbox value if set
+    public_last_error: Option<String>,
+}
+
+/// Normalize comma separated SHA-256 fingerprints to lowercase hex, `None` if any is invalid
+///
+/// Accepts the colon separated format of browsers and openssl.
+fn parse_cert_pins(input: &str) -> Option<Option<String>> {
+    let mut pins = Vec::new();
+    for pin in input.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let pin = pin.replace(':', "");
+        let mut decoded = [0u8; 32];
+        match base16ct::mixed::decode(&pin, &mut decoded) {
+            Ok(v) if v.len() == 32 => pins.push(pin.to_ascii_lowercase()),
+            _ => return None,
+        }
+    }
+    Some((!pins.is_empty()).then(|| pins.join(",")))
+}
+
+pub async fn settings_view(
+    State(ref template): State<Arc<tera::Tera>>,
+    State(ref db): State<DatabaseConnection>,
+    State(ref scanner_config): State<ScannerConfig>,
+    Path(host): Path<i32>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let host = get_specific_login_host(host, &session, db).await?;
+    let login = get_session_login(&session)?;
+    let probe = protocol_probe::Entity::find_by_id(host.id).one(db).await?;
+    let meta = host_meta::Entity::find_by_id(host.id).one(db).await?;
+    render_settings(
+        template,
+        scanner_config,
+        &host,
+        probe.as_ref(),
+        meta.as_ref(),
+        login.admin,
+        None,
+    )
+}
+
+pub async fn settings_update(
+    State(ref template): State<Arc<tera::Tera>>,
+    State(ref db): State<DatabaseConnection>,
+    State(ref scanner_config): State<ScannerConfig>,
+    Path(host): Path<i32>,
+    session: Session,
+    Form(input): Form<SettingsInput>,
+) -> Result<axum::response::Response> {
+    tracing::debug!(settings=?input);
+    let host = get_specific_login_host(host, &session, db).await?;
+    let login = get_session_login(&session)?;
+
+    let expected_version_url = input.expected_version_url.trim();
+    let expected_version_url = match expected_version_url.is_empty() {
+        true => None,
+        false => match Url::parse(expected_version_url) {
+            Ok(_) => Some(expected_version_url.to_owned()),
+            Err(_) => {
+                return settings_error(
+                    template,
+                    scanner_config,
+                    db,
+                    &host,
+                    login.admin,
+                    "Invalid version URL",
+                )
+                .await;
+            }
+        },
+    };
+
+    let Some(cert_sha256) = parse_cert_pins(&input.cert_sha256) else {
+        return settings_error(
+            template,
+            scanner_config,
+            db,
+            &host,
+            login.admin,
+            "Invalid certificate fingerprint, expected SHA-256 in hex",
+        )
+        .await;
+    };
+
+    host::ActiveModel {
+        id: ActiveValue::Set(host.id),
+        expected_version_url: ActiveValue::Set(expected_version_url),
+        cert_sha256: ActiveValue::Set(cert_sha256),
+        force_disabled: match login.admin {
+            true => ActiveValue::Set(input.force_disabled.is_some()),
+            false => ActiveValue::NotSet,
+        },
+        featured: match login.admin {
+            true => ActiveValue::Set(input.featured.is_some()),
+            false => ActiveValue::NotSet,
+        },
+        version_neutral: match login.admin {
+            true => ActiveValue::Set(input.version_neutral.is_some()),
+            false => ActiveValue::NotSet,
+        },
+        public_last_error: ActiveValue::Set(input.public_last_error.is_some()),
+        ..Default::default()
+    }
+    .update(db)
+    .await?;
+
+    let mut res =
+        Redirect::to(&format!("{ADMIN_OVERVIEW_URL}/settings/{}", host.id)).into_response();
+    *res.status_mut() = StatusCode::FOUND;
+    Ok(res)
+}
+
+/// Settings page with a validation *error* and status 400
+async fn settings_error(
+    template: &tera::Tera,
+    scanner_config: &ScannerConfig,
+    db: &DatabaseConnection,
+    host: &host::Model,
+    is_admin: bool,
+    error: &str,
+) -> Result<axum::response::Response> {
+    let probe = protocol_probe::Entity::find_by_id(host.id).one(db).await?;
+    let meta = host_meta::Entity::find_by_id(host.id).one(db).await?;
+    let mut res = render_settings(
+        template,
+        scanner_config,
+        host,
+        probe.as_ref(),
+        meta.as_ref(),
+        is_admin,
+        Some(error),
+    )?;
+    *res.status_mut() = StatusCode::BAD_REQUEST;
+    Ok(res)
+}
+
+fn render_settings(
+    template: &tera::Tera,
+    scanner_config: &ScannerConfig,
+    host: &host::Model,
+    probe: Option<&protocol_probe::Model>,
+    meta: Option<&host_meta::Model>,
+    is_admin: bool,
+    error: Option<&str>,
+) -> Result<axum::response::Response> {
+    let mut context = tera::Context::new();
+    context.insert("HOST", host);
+    context.insert("PROTOCOL_PROBE", &probe);
+    context.insert("META", &meta);
+    context.insert("CHECKED_URLS", &checked_urls(scanner_config, &host.url));
+    context.insert("is_admin", &is_admin);
+    context.insert("ERROR", &error);
+    Ok(Html(template.render("settings_admin.html.j2", &context)?).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MetaInput {
+    country: String,
+    contact_email: String,
+    tor_mirror: String,
+    donation_url: String,
+}
+
+/// Maximum length of the country override, enough for flags and short names
+const META_COUNTRY_MAX_LEN: usize = 32;
+
+/// Trimmed value, `None` if empty
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|v| !v.is_empty())
+}
+
+/// Whether *email* looks like a mail address
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            email.len() <= 254
+                && !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Parse an optional http(s) URL, *onion* requires an onion service
+fn parse_meta_url(value: &str, onion: bool) -> Option<Option<String>> {
+    let Some(value) = non_empty(value) else {
+        return Some(None);
+    };
+    let url = Url::parse(value).ok()?;
+    let valid = matches!(url.scheme(), "http" | "https")
+        && url
+            .host_str()
+            .is_some_and(|host| !onion || host.ends_with(".onion"));
+    valid.then(|| Some(value.to_owned()))
+}
+
+/// Update the public metadata of a host
+pub async fn meta_update(
+    State(ref template): State<Arc<tera::Tera>>,
+    State(ref db): State<DatabaseConnection>,
+    State(ref scanner_config): State<ScannerConfig>,
+    Path(host): Path<i32>,
+    session: Session,
+    Form(input): Form<MetaInput>,
+) -> Result<axum::response::Response> {
+    tracing::debug!(meta=?input);
+    let host = get_specific_login_host(host, &session, db).await?;
+    let login = get_session_login(&session)?;
+    let error = |error| settings_error(template, scanner_config, db, &host, login.admin, error);
+
+    let country = non_empty(&input.country);
+    if country.is_some_and(|v| v.chars().count() > META_COUNTRY_MAX_LEN) {
+        return error("Country is too long").await;
+    }
+    let contact_email = non_empty(&input.contact_email);
+    if contact_email.is_some_and(|v| !is_valid_email(v)) {
+        return error("Invalid contact email").await;
+    }
+    let Some(tor_mirror) = parse_meta_url(&input.tor_mirror, true) else {
+        return error("Invalid Tor mirror, expected an URL of an onion service").await;
+    };
+    let Some(donation_url) = parse_meta_url(&input.donation_url, false) else {
+        return error("Invalid donation URL").await;
+    };
+
+    host_meta::Entity::insert(host_meta::ActiveModel {
+        host: ActiveValue::Set(host.id),
+        updated: ActiveValue::Set(chrono::Utc::now().timestamp()),
+        country: ActiveValue::Set(country.map(str::to_owned)),
+        contact_email: ActiveValue::Set(contact_email.map(str::to_owned)),
+        tor_mirror: ActiveValue::Set(tor_mirror),
+        donation_url: ActiveValue::Set(donation_url),
+    })
+    .on_conflict(
+        OnConflict::column(host_meta::Column::Host)
+            .update_columns([
+                host_meta::Column::Updated,
+                host_meta::Column::Country,
+                host_meta::Column::ContactEmail,
+                host_meta::Column::TorMirror,
+                host_meta::Column::DonationUrl,
+            ])
+            .to_owned(),
+    )
+    .exec(db)
+    .await?;
+
+    let mut res =
+        Redirect::to(&format!("{ADMIN_OVERVIEW_URL}/settings/{}", host.id)).into_response();
+    *res.status_mut() = StatusCode::FOUND;
+    Ok(res)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PurgeInput {
+    /// Has to match the domain of the host
+    confirm: String,
+    /// Also delete the host itself, re-added by the next instance list update if still listed
+    delete_host: Option<String>,
+}
+
+/// Admin only: delete the stored history of a host
+pub async fn purge_host(
+    State(ref template): State<Arc<tera::Tera>>,
+    State(ref db): State<DatabaseConnection>,
+    State(ref scanner_config): State<ScannerConfig>,
+    Path(host): Path<i32>,
+    session: Session,
+    Form(input): Form<PurgeInput>,
+) -> Result<axum::response::Response> {
+    let login = get_session_login(&session)?;
+    if !login.admin {
+        return Err(ServerError::MissingPermission);
+    }
+    let host = get_specific_login_host(host, &session, db).await?;
+    if input.confirm.trim() != host.domain {
+        return settings_error(
+            template,
+            scanner_config,
+            db,
+            &host,
+            login.admin,
+            "Confirmation doesn't match the domain, nothing was purged",
+        )
+        .await;
+    }
+    let delete_host = input.delete_host.is_some();
+
+    let txn = db.begin().await?;
+    let health_checks = health_check::Entity::delete_many()
+        .filter(health_check::Column::Host.eq(host.id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    let errors = check_errors::Entity::delete_many()
+        .filter(check_errors::Column::Host.eq(host.id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    let connectivity = connectivity_check::Entity::delete_many()
+        .filter(connectivity_check::Column::Host.eq(host.id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    let points = points_history::Entity::delete_many()
+        .filter(points_history::Column::Host.eq(host.id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    let rss_checks = rss_check::Entity::delete_many()
+        .filter(rss_check::Column::Host.eq(host.id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    if delete_host {
+        // protocol probe and metadata are removed by the FK cascade
+        host::Entity::delete_by_id(host.id).exec(&txn).await?;
+    }
+    txn.commit().await?;
+    tracing::warn!(
+        host = host.domain,
+        health_checks,
+        errors,
+        connectivity,
+        points,
+        rss_checks,
+        delete_host,
+        "purged host history"
+    );
+
+    let target = match delete_host {
+        true => ADMIN_OVERVIEW_URL.to_owned(),
+        false => format!("{ADMIN_OVERVIEW_URL}/settings/{}", host.id),
+    };
+    let mut res = Redirect::to(&target).into_response();
+    *res.status_mut() = StatusCode::FOUND;
+    Ok(res)
+}
+
+/// Minimum time between two protocol probes of a host
+const PROTOCOL_PROBE_COOLDOWN_S: i64 = 60;
+
+/// Probe a host once over HTTP/1.1 and once over HTTP/2, storing which of them work
+pub async fn protocol_probe(
+    State(ref db): State<DatabaseConnection>,
+    State(ref scanner_config): State<ScannerConfig>,
+    Path(host): Path<i32>,
+    session: Session,
+) -> Result<axum::response::Response> {
+    let host = get_specific_login_host(host, &session, db).await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let last_probe = protocol_probe::Entity::find_by_id(host.id).one(db).await?;
+    if last_probe.is_none_or(|v| now - v.time >= PROTOCOL_PROBE_COOLDOWN_S) {
+        let (http1, http2) = tokio::join!(
+            probe_protocol(scanner_config, &host.url, false),
+            probe_protocol(scanner_config, &host.url, true)
+        );
+        tracing::debug!(host = host.id, ?http1, ?http2, "protocol probe");
+        protocol_probe::Entity::insert(protocol_probe::ActiveModel {
+            host: ActiveValue::Set(host.id),
+            time: ActiveValue::Set(now),
+            http1: ActiveValue::Set(http1.is_ok()),
+            http2: ActiveValue::Set(http2.is_ok()),
+            http1_error: ActiveValue::Set(http1.err()),
+            http2_error: ActiveValue::Set(http2.err()),
+        })
+        .on_conflict(
+            OnConflict::column(protocol_probe::Column::Host)
+                .update_columns([
+                    protocol_probe::Column::Time,
+                    protocol_probe::Column::Http1,
+                    protocol_probe::Column::Http2,
+                    protocol_probe::Column::Http1Error,
+                    protocol_probe::Column::Http2Error,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    }
+
+    let mut res =
+        Redirect::to(&format!("{ADMIN_OVERVIEW_URL}/settings/{}", host.id)).into_response();
+    *res.status_mut() = StatusCode::FOUND;
+    Ok(res)
+}
+
+/// Request *url* forcing HTTP/2 or HTTP/1.1, any response counts as success
+async fn probe_protocol(
+    scanner_config: &ScannerConfig,
+    url: &str,
+    http2: bool,
+) -> std::result::Result<(), String> {
+    let mut builder = Client::builder()
+        .use_rustls_tls()
+        .user_agent(format!(
+            "nitter-status (+{}/about)",
+            scanner_config.website_url
+        ))
+        .connect_timeout(std::time::Duration::from_secs(3))
+        .timeout(std::time::Duration::from_secs(10));
+    builder = match http2 {
+        true => builder.http2_prior_knowledge(),
+        false => builder.http1_only(),
+    };
+    if let Some(proxy) = scanner_config.http_proxy.as_deref() {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).map_err(|e| e.to_string())?);
+    }
+    let client = builder.build().map_err(|e| e.to_string())?;
+    client
+        .get(url)
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|e| error_chain(&e))
+}
+
+/// Display an error with all of its sources, reqwest hides the cause otherwise
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        // hyper errors already contain their cause
+        let text = e.to_string();
+        if !message.contains(&text) {
+            message.push_str(": ");
+            message.push_str(&text);
+        }
+        source = e.source();
+    }
+    message
+}
+
+/// Full URLs the scanner requests for the instance at *host_url*
+fn checked_urls(scanner_config: &ScannerConfig, host_url: &str) -> Vec<String> {
+    let Ok(url) = Url::parse(host_url) else {
+        return Vec::new();
+    };
+    crate::website::checked_paths(scanner_config)
+        .into_iter()
+        .map(|path| {
+            let mut url = url.clone();
+            url.set_path(path);
+            url.to_string()
+        })
+        .collect()
+}
+
 /// Get all [host::Model] for current [Session]
 async fn get_all_login_hosts(
     session: &Session,
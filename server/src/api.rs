@@ -1,27 +1,338 @@
 // SPDX-License-Identifier: AGPL-3.0-only
-use crate::{Result, ServerError};
+use crate::sorting::{SortKey, SortQuery};
+use crate::{ReadDb, Result, ServerError};
+use axum::middleware::Next;
 use axum::response::IntoResponse;
-use axum::{extract::State, Json};
-use entities::state::AppState;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use constant_time_eq::constant_time_eq;
+use entities::host::{self, Connectivity};
+use entities::prelude::Host;
+use entities::state::{AppState, CacheData, CacheHost, InstanceMeta, VersionState};
+use hyper::header::AUTHORIZATION;
 use hyper::http::HeaderValue;
-use std::sync::Arc;
+use hyper::Request;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Version of the public API response shape, bump on breaking changes
+const SCHEMA_VERSION: u32 = 1;
+
+/// Public response of `/api/v1/instances`.
+///
+/// Decoupled from [CacheData] so internal changes don't break API consumers.
+#[derive(Serialize)]
+struct InstancesResponse<'a> {
+    /// Version of this response format
+    schema_version: u32,
+    /// Instances, ordered by their rank
+    hosts: Vec<InstanceEntry<'a>>,
+    /// Time of the last data update
+    last_update: DateTime<Utc>,
+    /// Whether the data is outdated, for example due to a stuck scanner
+    stale: bool,
+    /// Latest upstream nitter commit
+    latest_commit: &'a str,
+}
+
+/// Public representation of a single instance
+#[derive(Serialize)]
+struct InstanceEntry<'a> {
+    /// Connection URL
+    url: &'a str,
+    /// Instance domain
+    domain: &'a str,
+    /// Weighted instance score
+    points: i32,
+    /// Whether RSS feeds are available
+    rss: bool,
+    /// Response times in ms over the ping range, `null` for failed checks
+    recent_pings: &'a [Option<i32>],
+    /// Maximum response time in ms over the ping range
+    ping_max: Option<i32>,
+    /// Minimum response time in ms over the ping range
+    ping_min: Option<i32>,
+    /// Average response time in ms over the ping range
+    ping_avg: Option<i32>,
+    /// Median response time in ms over the ping range
+    ping_p50: Option<i32>,
+    /// 95th percentile response time in ms over the ping range
+    ping_p95: Option<i32>,
+    /// Version reported by the instance
+    version: Option<&'a str>,
+    /// Source URL of the reported version
+    version_url: Option<&'a str>,
+    /// Whether the last health check was successful, always `false` while the data is stale
+    healthy: bool,
+    /// Health of the instance, `unknown` while the data is stale
+    health_state: HealthState,
+    /// Time of the last successful health check
+    last_healthy: Option<DateTime<Utc>>,
+    /// Time of the latest health check, healthy or not
+    last_checked: Option<DateTime<Utc>>,
+    /// State of the reported version compared to upstream
+    version_state: VersionState,
+    /// Whether this host is known to block health checks
+    is_bad_host: bool,
+    /// Reason for the bad host flag
+    bad_host_reason: Option<&'a str>,
+    /// Expiry of the bad host flag
+    bad_host_until: Option<DateTime<Utc>>,
+    /// Whether this host serves a captcha/JS challenge
+    is_captcha_gated: bool,
+    /// Whether this host is only healthy according to remote probe peers
+    is_healthy_remote: bool,
+    /// Whether the latest check was blocked, the host may still be up for other users
+    is_blocked: bool,
+    /// Whether the host is up, but served an error page instead of the checked profile
+    is_content_unavailable: bool,
+    /// Category of the last error, only if enabled by the instance operator
+    last_error: Option<&'a str>,
+    /// Country from the wiki
+    country: &'a str,
+    /// Latest health checks as formatted time and healthy state, in ascending order
+    recent_checks: &'a [(String, bool)],
+    /// Percentage of healthy checks since first seen
+    healthy_percentage_overall: u8,
+    /// New host with too few checks for ranking, listed after all ranked ones
+    insufficient_data: bool,
+    /// Recommended by the admin of this site, listed before all other hosts
+    is_featured: bool,
+    /// Whether the RSS feed is a well-formed feed, `null` unless checked
+    rss_valid: Option<bool>,
+    /// Public metadata provided by the operator
+    meta: &'a InstanceMeta,
+    /// IP connectivity support
+    connectivity: Option<Connectivity>,
+}
+
+/// Health state of an instance
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HealthState {
+    Healthy,
+    Unhealthy,
+    /// Data is too old to tell, the scanner may be down
+    Unknown,
+}
+
+impl<'a> InstanceEntry<'a> {
+    /// Create a new entry, reporting an unknown state for *stale* data
+    fn new(host: &'a CacheHost, stale: bool) -> Self {
+        let health_state = match (stale, host.healthy) {
+            (true, _) => HealthState::Unknown,
+            (false, true) => HealthState::Healthy,
+            (false, false) => HealthState::Unhealthy,
+        };
+        Self {
+            url: &host.url,
+            domain: &host.domain,
+            points: host.points,
+            rss: host.rss,
+            recent_pings: &host.recent_pings,
+            ping_max: host.ping_max,
+            ping_min: host.ping_min,
+            ping_avg: host.ping_avg,
+            ping_p50: host.ping_p50,
+            ping_p95: host.ping_p95,
+            version: host.version.as_deref(),
+            version_url: host.version_url.as_deref(),
+            healthy: host.healthy && !stale,
+            health_state,
+            last_healthy: host.last_healthy,
+            last_checked: host.last_checked,
+            version_state: host.version_state,
+            is_bad_host: host.is_bad_host,
+            bad_host_reason: host.bad_host_reason.as_deref(),
+            bad_host_until: host.bad_host_until,
+            is_captcha_gated: host.is_captcha_gated,
+            is_healthy_remote: host.is_healthy_remote,
+            is_blocked: host.is_blocked,
+            is_content_unavailable: host.is_content_unavailable,
+            last_error: host.last_error.as_deref(),
+            country: &host.country,
+            recent_checks: &host.recent_checks,
+            healthy_percentage_overall: host.healthy_percentage_overall,
+            insufficient_data: host.insufficient_data,
+            is_featured: host.is_featured,
+            rss_valid: host.rss_valid,
+            meta: &host.meta,
+            connectivity: host.connectivity,
+        }
+    }
+}
+
+/// Filters of `/api/v1/instances`
+#[derive(Deserialize, Default)]
+pub struct InstancesFilter {
+    /// Only instances with or without an onion service
+    has_tor_mirror: Option<bool>,
+}
+
+impl InstancesFilter {
+    fn matches(&self, host: &CacheHost) -> bool {
+        self.has_tor_mirror
+            .is_none_or(|wanted| host.meta.tor_mirror.is_some() == wanted)
+    }
+}
+
+impl<'a> InstancesResponse<'a> {
+    fn new(data: &'a CacheData, sort: SortKey, filter: &InstancesFilter, stale: bool) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            hosts: sort
+                .sort(&data.hosts)
+                .into_iter()
+                .filter(|host| filter.matches(host))
+                .map(|host| InstanceEntry::new(host, stale))
+                .collect(),
+            last_update: data.last_update,
+            stale,
+            latest_commit: &data.latest_commit,
+        }
+    }
+}
+
+/// Default window of `/api/v1/changes` in days
+const CHANGES_DEFAULT_DAYS: u32 = 7;
+/// Maximum window of `/api/v1/changes` in days
+const CHANGES_MAX_DAYS: u32 = 90;
+
+/// Recently computed `/api/v1/changes` responses, by window in days
+pub type ChangesCache = Arc<Mutex<HashMap<u32, (Instant, Arc<ChangesResponse>)>>>;
+
+/// `?days=` query parameter
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    days: Option<u32>,
+}
+
+/// Public response of `/api/v1/changes`.
+#[derive(Serialize)]
+pub struct ChangesResponse {
+    /// Version of this response format
+    schema_version: u32,
+    /// Window in days covered by this response
+    days: u32,
+    /// Instances first seen inside the window, newest first
+    added: Vec<ChangeEntry>,
+    /// Instances removed from the list inside the window, newest first
+    removed: Vec<ChangeEntry>,
+}
+
+/// Single instance added or removed
+#[derive(Serialize)]
+struct ChangeEntry {
+    /// Instance domain
+    domain: String,
+    /// Connection URL
+    url: String,
+    /// Time of the change
+    time: DateTime<Utc>,
+}
+
+impl ChangeEntry {
+    fn new(host: host::Model, time: i64) -> Self {
+        Self {
+            time: Utc.timestamp_opt(time, 0).single().unwrap_or_default(),
+            domain: host.domain,
+            url: host.url,
+        }
+    }
+}
+
+impl ChangesResponse {
+    async fn fetch(db: &sea_orm::DatabaseConnection, days: u32) -> Result<Self> {
+        let since = (Utc::now() - chrono::Duration::days(days as _)).timestamp();
+        let added = Host::find()
+            .filter(host::Column::FirstSeen.gte(since))
+            .order_by_desc(host::Column::FirstSeen)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|host| {
+                let time = host.first_seen;
+                ChangeEntry::new(host, time)
+            })
+            .collect();
+        let removed = Host::find()
+            .filter(host::Column::Enabled.eq(false))
+            .filter(host::Column::Updated.gte(since))
+            .order_by_desc(host::Column::Updated)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|host| {
+                let time = host.updated;
+                ChangeEntry::new(host, time)
+            })
+            .collect();
+        Ok(Self {
+            schema_version: SCHEMA_VERSION,
+            days,
+            added,
+            removed,
+        })
+    }
+}
+
+/// Middleware requiring `Authorization: Bearer <key>` if an API key is configured
+pub async fn require_api_key<B>(
+    State(config): State<Arc<crate::Config>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<axum::response::Response> {
+    if let Some(api_key) = config.api_key.as_deref() {
+        let provided = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim());
+        match provided {
+            Some(key) if constant_time_eq(key.as_bytes(), api_key.as_bytes()) => (),
+            _ => return Err(ServerError::InvalidApiKey),
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Cache-Control of the API responses
+///
+/// Private if *keyed* with an `API_KEY`, shared caches would otherwise serve keyed responses to anyone.
+fn cache_control(keyed: bool, max_age: usize) -> HeaderValue {
+    let scope = match keyed {
+        true => "private",
+        false => "public",
+    };
+    HeaderValue::from_str(&format!("{scope}, max-age={max_age}")).unwrap()
+}
 
 pub async fn instances(
     State(ref app_state): State<AppState>,
     State(ref config): State<Arc<crate::Config>>,
+    Query(sort): Query<SortQuery>,
+    Query(filter): Query<InstancesFilter>,
 ) -> Result<axum::response::Response> {
     let mut res = {
         let guard = app_state
             .cache
             .read()
             .map_err(|_| ServerError::MutexFailure)?;
-        let res = Json(&*guard).into_response();
+        let stale = config.is_stale(guard.last_update);
+        let res = Json(InstancesResponse::new(&guard, sort.key(), &filter, stale)).into_response();
         drop(guard);
         res
     };
     res.headers_mut().insert(
         "cache-control",
-        HeaderValue::from_str(&format!("public, max-age={}", config.max_age)).unwrap(),
+        cache_control(config.api_key.is_some(), config.api_max_age),
     );
     res.headers_mut().insert(
         "X-Robots-Tag",
@@ -29,3 +340,56 @@ pub async fn instances(
     );
     Ok(res)
 }
+
+pub async fn changes(
+    State(ReadDb(ref read_db)): State<ReadDb>,
+    State(ref config): State<Arc<crate::Config>>,
+    State(ref cache): State<ChangesCache>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<axum::response::Response> {
+    let days = query
+        .days
+        .unwrap_or(CHANGES_DEFAULT_DAYS)
+        .clamp(1, CHANGES_MAX_DAYS);
+    let max_age = Duration::from_secs(config.api_max_age as _);
+    let cached = {
+        let guard = cache.lock().map_err(|_| ServerError::MutexFailure)?;
+        guard
+            .get(&days)
+            .filter(|(created, _)| created.elapsed() < max_age)
+            .map(|(_, data)| data.clone())
+    };
+    let data = match cached {
+        Some(data) => data,
+        None => {
+            let data = Arc::new(ChangesResponse::fetch(read_db, days).await?);
+            cache
+                .lock()
+                .map_err(|_| ServerError::MutexFailure)?
+                .insert(days, (Instant::now(), data.clone()));
+            data
+        }
+    };
+    let mut res = Json(&*data).into_response();
+    res.headers_mut().insert(
+        "cache-control",
+        cache_control(config.api_key.is_some(), config.api_max_age),
+    );
+    res.headers_mut().insert(
+        "X-Robots-Tag",
+        HeaderValue::from_static("noindex, nofollow"),
+    );
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_control_keyed() {
+        assert_eq!(cache_control(false, 60), "public, max-age=60");
+        // keyed responses must not end up in shared caches
+        assert_eq!(cache_control(true, 60), "private, max-age=60");
+    }
+}
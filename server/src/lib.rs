@@ -1,17 +1,23 @@
 // SPDX-License-Identifier: AGPL-3.0-only
-use std::{borrow::Cow, collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use axum::{
     error_handling::HandleErrorLayer,
     extract::DefaultBodyLimit,
     http::HeaderValue,
+    middleware,
     response::{Html, Redirect},
-    routing::{get, get_service},
+    routing::{get, get_service, post},
     BoxError, Router,
 };
 use chrono::TimeZone;
 use entities::state::{scanner::ScannerConfig, AppState};
-use hyper::{header, StatusCode};
+use hyper::{header, Method, StatusCode};
 use reqwest::Client;
 use sea_orm::DatabaseConnection;
 use tera::{from_value, to_value, Tera};
@@ -25,10 +31,16 @@ use tower_http::{
     set_header::SetResponseHeaderLayer,
     trace::TraceLayer,
 };
+use tower_sessions::sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
 use tower_sessions::{cookie::SameSite, SessionManagerLayer, SqliteStore};
 
 mod admin;
 mod api;
+mod probe;
+pub use probe::ProbePeers;
+mod rate_limit;
+pub use rate_limit::RateLimit;
+mod sorting;
 mod website;
 
 const LOGIN_URL: &'static str = "/admin/login";
@@ -37,28 +49,87 @@ const ADMIN_OVERVIEW_URL: &'static str = "/admin";
 #[derive(Debug)]
 pub struct Config {
     pub site_url: String,
-    pub max_age: usize,
+    /// cache-control max-age in seconds for `/api`
+    pub api_max_age: usize,
+    /// cache-control max-age in seconds for the instance list page
+    pub page_max_age: usize,
+    /// cache-control max-age in seconds for the about page
+    pub about_max_age: usize,
     pub session_ttl_seconds: u64,
-    pub login_token_name: String,
+    /// Path of the login token file on instances, without leading slash
+    pub login_token_path: String,
+    /// DNS TXT record name of the login token, `{domain}` is replaced with the instance domain
+    pub login_token_dns_name: String,
     pub admin_domains: Vec<String>,
     pub session_db_uri: String,
+    /// Use WAL journal mode for the session DB
+    pub session_db_wal: bool,
+    /// Busy timeout for the session DB
+    pub session_db_busy_timeout: std::time::Duration,
+    /// Interval for deleting expired sessions
+    pub session_cleanup_interval: std::time::Duration,
+    /// Interval for compacting the session DB via VACUUM, disabled if unset
+    pub session_db_vacuum_interval: Option<std::time::Duration>,
+    /// Optional key required as bearer token for the `/api` routes
+    pub api_key: Option<String>,
+    /// Age in seconds after which the cached data is marked as stale
+    pub stale_after_s: u64,
+    /// Additional origins allowed to access `/api` cross-origin, `*` for any origin
+    pub cors_origins: Vec<String>,
+    /// Secrets of remote probe peers allowed to submit health checks, by peer name
+    pub probe_peers: ProbePeers,
+    /// Outbound IPs of the scanner shown on the about page, hidden if empty
+    pub scanner_ips: Vec<String>,
+    /// Echo service returning the caller IP, to resolve `scanner_ips` on startup if unset
+    pub scanner_ip_echo_url: Option<String>,
+    /// Verify logins via the scanner HTTP proxy
+    pub login_proxy: bool,
+    /// Deployment specific HTML shown on the about page, not escaped
+    pub about_extra_html: Option<String>,
+    /// Per client limit for the public `/api` routes, unlimited if unset
+    pub api_rate_limit: Option<RateLimit>,
+    /// Reverse proxies allowed to set the client IP via `X-Forwarded-For`, for rate limits
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
+impl Config {
+    /// DNS record name before and after the instance domain
+    fn login_token_dns_parts(&self) -> (&str, &str) {
+        self.login_token_dns_name
+            .split_once("{domain}")
+            .unwrap_or((&self.login_token_dns_name, ""))
+    }
+
+    /// Whether data last updated at *last_update* is outdated, likely due to a stuck scanner
+    fn is_stale(&self, last_update: chrono::DateTime<chrono::Utc>) -> bool {
+        chrono::Utc::now() - last_update > chrono::Duration::seconds(self.stale_after_s as _)
+    }
+}
+
+/// Read-only connection for heavy queries, to reduce contention with scanner writes
+#[derive(Clone)]
+struct ReadDb(DatabaseConnection);
+
 #[derive(Clone, axum::extract::FromRef)]
 struct WebState {
     db: DatabaseConnection,
+    read_db: ReadDb,
     config: Arc<Config>,
     scanner_config: ScannerConfig,
     app_state: AppState,
     templates: Arc<Tera>,
     login_client: Client,
+    changes_cache: api::ChangesCache,
 }
 
 /// Start webserver
+///
+/// *read_db* is used for read-only analytics queries and can be a clone of *db*.
 pub async fn start(
     addr: &SocketAddr,
     db: DatabaseConnection,
-    config: Config,
+    read_db: DatabaseConnection,
+    mut config: Config,
     scanner_config: ScannerConfig,
     app_state: AppState,
 ) -> Result<()> {
@@ -70,9 +141,24 @@ pub async fn start(
         tracing::warn!("debug build, sessions are not secure!");
     }
 
-    let pool = tower_sessions::sqlx::SqlitePool::connect(&config.session_db_uri)
+    let mut session_db_opts: SqliteConnectOptions = config
+        .session_db_uri
+        .parse()
+        .expect("invalid session DB URI");
+    session_db_opts = session_db_opts
+        .busy_timeout(config.session_db_busy_timeout)
+        .create_if_missing(true);
+    if config.session_db_wal {
+        session_db_opts = session_db_opts
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal);
+    }
+    let pool = tower_sessions::sqlx::SqlitePool::connect_with(session_db_opts)
         .await
         .expect("failed to initialize session store");
+    if let Some(interval) = config.session_db_vacuum_interval {
+        tokio::task::spawn(vacuum_session_db(pool.clone(), interval));
+    }
     let session_store = SqliteStore::new(pool);
     session_store
         .migrate()
@@ -81,7 +167,7 @@ pub async fn start(
     tokio::task::spawn(
         session_store
             .clone()
-            .continuously_delete_expired(tokio::time::Duration::from_secs(60)),
+            .continuously_delete_expired(config.session_cleanup_interval),
     );
 
     let session_service = ServiceBuilder::new()
@@ -99,7 +185,16 @@ pub async fn start(
         );
 
     let user_agent = format!("nitter-status (+{}/about)", scanner_config.website_url);
-    let login_client = Client::builder()
+    let mut login_client = Client::builder();
+    if let Some(proxy) = scanner_config
+        .http_proxy
+        .as_deref()
+        .filter(|_| config.login_proxy)
+    {
+        // already validated by the scanner
+        login_client = login_client.proxy(reqwest::Proxy::all(proxy).unwrap());
+    }
+    let login_client = login_client
         .cookie_store(false)
         .brotli(true)
         .deflate(true)
@@ -111,23 +206,35 @@ pub async fn start(
         .build()
         .unwrap();
 
+    if config.scanner_ips.is_empty() {
+        if let Some(url) = config.scanner_ip_echo_url.as_deref() {
+            config.scanner_ips =
+                website::resolve_outbound_ips(url, scanner_config.http_proxy.as_deref()).await;
+        }
+    }
+
     let config = Arc::new(config);
     let mut tera = Tera::new("server/templates/*")?;
     tera.autoescape_on(vec![".html.j2"]);
     tera.register_function("fmt_date", fmt_date);
+    tera.register_function("fmt_relative", fmt_relative);
     let state = WebState {
         config: config.clone(),
         db,
+        read_db: ReadDb(read_db),
         app_state,
         scanner_config,
         templates: Arc::new(tera),
         login_client,
+        changes_cache: Default::default(),
     };
 
+    let client_ip = rate_limit::ClientIpKeyExtractor::new(config.trusted_proxies.clone());
     let per_ip_governor_conf = Box::new(
         GovernorConfigBuilder::default()
             .per_second(2)
             .burst_size(2)
+            .key_extractor(client_ip.clone())
             .finish()
             .unwrap(),
     );
@@ -139,15 +246,59 @@ pub async fn start(
             config: Box::leak(per_ip_governor_conf),
         });
 
+    let mut api_router = Router::new()
+        .route("/v1/instances", get(api::instances))
+        .route("/v1/changes", get(api::changes))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            api::require_api_key,
+        ));
+    if let Some(limit) = config.api_rate_limit {
+        // more generous than the login limit, only against aggressive polling
+        let api_governor_conf = Box::new(
+            GovernorConfigBuilder::default()
+                .period(limit.period)
+                .burst_size(limit.burst)
+                .key_extractor(client_ip)
+                .finish()
+                .unwrap(),
+        );
+        api_router = api_router.route_layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|e: BoxError| async move {
+                    tower_governor::errors::display_error(e)
+                }))
+                .layer(GovernorLayer {
+                    config: Box::leak(api_governor_conf),
+                }),
+        );
+    }
+
     let router = Router::new()
         .nest_service(
             "/static",
             ServeDir::new("server/static").append_index_html_on_directories(false),
         )
-        .route("/api/v1/instances", get(api::instances))
+        .nest("/api", api_router
+            // authenticated per peer, not by the API key
+            .route("/v1/probe-report", post(probe::probe_report))
+            .layer(cors_policy(&config.site_url, &config.cors_origins))
+        )
         .nest(ADMIN_OVERVIEW_URL, Router::new()
             .route("/", get(admin::overview))
             .route("/errors/:host", get(admin::errors_view))
+            .route("/instance/:host/error/:time", get(admin::error_json))
+            .route("/connectivity/:host", get(admin::connectivity_csv))
+            .route("/points/:host", get(admin::points_csv))
+            .route("/rss/:host", get(admin::rss_view))
+            .route("/debug/instancelist", get(admin::debug_instancelist))
+            .route("/debug/connections", get(admin::debug_connections))
+            .route("/debug/schedule", get(admin::debug_schedule))
+            .route("/search", get(admin::search))
+            .route("/settings/:host", get(admin::settings_view).post(admin::settings_update))
+            .route("/protocols/:host", post(admin::protocol_probe))
+            .route("/meta/:host", post(admin::meta_update))
+            .route("/instance/:host/purge", post(admin::purge_host))
             // .route("/history/:host", get(admin::history_view))
             // .route("/api/history", get(admin::history_json))
             .route("/login", get(admin::login_view).post(admin::login).route_layer(rate_limit_layer))
@@ -157,6 +308,7 @@ pub async fn start(
         )
         // .route("/admin", get(admin::view))
         .route("/about", get(website::about))
+        .route("/theme", post(website::set_theme))
         .route(
             "/robots.txt",
             get_service(ServeFile::new("server/static/robots.txt")),
@@ -167,7 +319,6 @@ pub async fn start(
                 .layer(DefaultBodyLimit::disable())
                 .layer(RequestBodyLimitLayer::new(2usize.pow(20) * 2))
                 .layer(TraceLayer::new_for_http())
-                .layer(cors_policy(&config.site_url))
                 .layer(SetResponseHeaderLayer::overriding(
                     header::CONTENT_SECURITY_POLICY,
                     "default-src 'self'; child-src 'none'; script-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data:;"
@@ -185,11 +336,43 @@ pub async fn start(
     Ok(())
 }
 
-fn cors_policy(_site_url: &str) -> CorsLayer {
-    CorsLayer::new()
-        .allow_origin(tower_http::cors::Any)
-        .allow_methods(tower_http::cors::Any)
-        .allow_headers(tower_http::cors::Any)
+/// Periodically compact the session DB, which only grows by deleting expired sessions
+async fn vacuum_session_db(pool: tower_sessions::sqlx::SqlitePool, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // skip the immediate first tick, nothing to compact on startup
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        match tower_sessions::sqlx::query("VACUUM").execute(&pool).await {
+            Ok(_) => tracing::debug!("vacuumed session DB"),
+            Err(e) => tracing::warn!(error=?e, "failed to vacuum session DB"),
+        }
+    }
+}
+
+/// CORS policy for the `/api` routes, all other routes are same-origin only
+///
+/// Allows *site_url* and all *extra_origins*, any origin if one of them is `*`.
+fn cors_policy(site_url: &str, extra_origins: &[String]) -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
+    if extra_origins.iter().any(|v| v == "*") {
+        return layer.allow_origin(tower_http::cors::Any);
+    }
+    let origins: Vec<HeaderValue> = std::iter::once(site_url)
+        .chain(extra_origins.iter().map(String::as_str))
+        // origins never contain a trailing slash
+        .map(|origin| origin.trim_end_matches('/'))
+        .filter_map(|origin| match origin.parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                tracing::warn!(origin, "Ignoring invalid CORS origin");
+                None
+            }
+        })
+        .collect();
+    layer.allow_origin(origins)
 }
 
 type Result<T = Html<String>> = std::result::Result<T, ServerError>;
@@ -210,6 +393,14 @@ pub enum ServerError {
     HostNotFound(i32),
     #[error("No permission to access this resource")]
     MissingPermission,
+    #[error("Missing or invalid API key")]
+    InvalidApiKey,
+    #[error("Unknown probe peer or invalid signature")]
+    InvalidSignature,
+    #[error("Malformed or outdated probe report")]
+    InvalidProbeReport,
+    #[error("No error recorded at {1} for host '{0}'")]
+    CheckErrorNotFound(i32, i64),
 }
 
 impl axum::response::IntoResponse for ServerError {
@@ -225,6 +416,22 @@ impl axum::response::IntoResponse for ServerError {
                 StatusCode::FORBIDDEN,
                 Cow::Borrowed("Missing permission to access this resource"),
             ),
+            InvalidApiKey => (
+                StatusCode::UNAUTHORIZED,
+                Cow::Borrowed("Missing or invalid API key"),
+            ),
+            InvalidSignature => (
+                StatusCode::UNAUTHORIZED,
+                Cow::Borrowed("Unknown probe peer or invalid signature"),
+            ),
+            InvalidProbeReport => (
+                StatusCode::BAD_REQUEST,
+                Cow::Borrowed("Malformed or outdated probe report"),
+            ),
+            CheckErrorNotFound(_, _) => (
+                StatusCode::NOT_FOUND,
+                Cow::Borrowed("Error not found, it may have been cleaned up"),
+            ),
             MutexFailure | Templating(_) | DBError(_) | SessionError(_) | HostNotFound(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Cow::Borrowed("Internal Server Error"),
@@ -259,3 +466,73 @@ fn fmt_date(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
         None => Err("no value provided".into()),
     }
 }
+
+/// Format a unix timestamp or RFC 3339 date relative to now, for example `5m ago` or `in 2d`
+fn fmt_relative(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let value = args.get("value").ok_or("no value provided")?;
+    let time = match value {
+        tera::Value::String(v) => chrono::DateTime::parse_from_rfc3339(v)
+            .map_err(|_| tera::Error::from("date not in RFC 3339 format"))?
+            .timestamp(),
+        v => from_value::<i64>(v.clone()).map_err(|_| "timestamp not an i64")?,
+    };
+    if time == 0 {
+        return Ok(to_value("never").unwrap());
+    }
+    let diff = chrono::Utc::now().timestamp() - time;
+    let secs = diff.unsigned_abs();
+    if secs < 60 {
+        return Ok(to_value("just now").unwrap());
+    }
+    let formatted = match secs {
+        0..=3599 => format!("{}m", secs / 60),
+        3600..=86_399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86_400),
+    };
+    let formatted = match diff < 0 {
+        true => format!("in {formatted}"),
+        false => format!("{formatted} ago"),
+    };
+    Ok(to_value(formatted).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn relative(value: tera::Value) -> String {
+        let args = HashMap::from([(String::from("value"), value)]);
+        from_value(fmt_relative(&args).unwrap()).unwrap()
+    }
+
+    /// Relative format of *offset* seconds before now
+    fn relative_ago(offset: i64) -> String {
+        relative(to_value(chrono::Utc::now().timestamp() - offset).unwrap())
+    }
+
+    #[test]
+    fn fmt_relative_units() {
+        assert_eq!(relative(to_value(0).unwrap()), "never");
+        assert_eq!(relative_ago(0), "just now");
+        assert_eq!(relative_ago(30), "just now");
+        assert_eq!(relative_ago(5 * 60 + 10), "5m ago");
+        assert_eq!(relative_ago(2 * 3600 + 60), "2h ago");
+        assert_eq!(relative_ago(3 * 86_400 + 60), "3d ago");
+    }
+
+    #[test]
+    fn fmt_relative_future() {
+        assert_eq!(relative_ago(-(5 * 60 + 10)), "in 5m");
+        assert_eq!(relative_ago(-(2 * 86_400 + 60)), "in 2d");
+    }
+
+    #[test]
+    fn fmt_relative_rfc3339() {
+        let time = chrono::Utc::now() - chrono::Duration::hours(2) - chrono::Duration::minutes(1);
+        assert_eq!(relative(to_value(time.to_rfc3339()).unwrap()), "2h ago");
+
+        let args = HashMap::from([(String::from("value"), to_value("yesterday").unwrap())]);
+        assert!(fmt_relative(&args).is_err());
+        assert!(fmt_relative(&HashMap::new()).is_err());
+    }
+}
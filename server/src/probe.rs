@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+//! Health check results submitted by remote probe peers
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{body::Bytes, extract::State, http::HeaderMap};
+use chrono::{DateTime, TimeZone, Utc};
+use entities::state::{AppState, PeerProbe};
+use hmac::{Hmac, Mac};
+use hyper::StatusCode;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{Result, ServerError};
+
+/// Name of the reporting peer
+const PEER_HEADER: &str = "x-probe-peer";
+/// Hex encoded HMAC-SHA256 of the body, keyed with the peer secret
+const SIGNATURE_HEADER: &str = "x-probe-signature";
+/// Maximum age of a report in seconds, prevents replaying old reports
+const MAX_REPORT_AGE_S: i64 = 300;
+
+/// Secrets of remote probe peers by peer name, Debug only prints the names
+#[derive(Default)]
+pub struct ProbePeers(pub HashMap<String, String>);
+
+impl std::fmt::Debug for ProbePeers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.0.keys()).finish()
+    }
+}
+
+/// Body of `/api/v1/probe-report`
+#[derive(Deserialize)]
+struct ProbeReport {
+    /// Unix timestamp of the report creation
+    time: i64,
+    results: Vec<ProbeResult>,
+}
+
+/// Health check result of a single instance
+#[derive(Deserialize)]
+struct ProbeResult {
+    domain: String,
+    healthy: bool,
+}
+
+pub async fn probe_report(
+    State(ref app_state): State<AppState>,
+    State(ref config): State<Arc<crate::Config>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode> {
+    let now = Utc::now();
+    let (peer, report) = read_report(&config.probe_peers, &headers, &body, now.timestamp())?;
+    let time = report_time(&report, now);
+    tracing::debug!(
+        peer,
+        results = report.results.len(),
+        "received probe report"
+    );
+    for result in report.results {
+        app_state.insert_probe(
+            result.domain,
+            PeerProbe {
+                peer: peer.to_owned(),
+                healthy: result.healthy,
+                time,
+            },
+        );
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Creation time of *report*, capped at *now* as reports may be ahead by the allowed clock skew
+fn report_time(report: &ProbeReport, now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.timestamp_opt(report.time, 0)
+        .single()
+        .map_or(now, |time| time.min(now))
+}
+
+/// Authenticate the peer of *headers* and parse its report, *now* is the current unix timestamp
+fn read_report<'a>(
+    peers: &ProbePeers,
+    headers: &'a HeaderMap,
+    body: &[u8],
+    now: i64,
+) -> Result<(&'a str, ProbeReport)> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let peer = header(PEER_HEADER).ok_or(ServerError::InvalidSignature)?;
+    let secret = peers.0.get(peer).ok_or(ServerError::InvalidSignature)?;
+    let signature = header(SIGNATURE_HEADER).ok_or(ServerError::InvalidSignature)?;
+    verify_signature(secret, body, signature)?;
+
+    let report: ProbeReport =
+        serde_json::from_slice(body).map_err(|_| ServerError::InvalidProbeReport)?;
+    if (now - report.time).abs() > MAX_REPORT_AGE_S {
+        return Err(ServerError::InvalidProbeReport);
+    }
+    Ok((peer, report))
+}
+
+/// Verify the hex encoded HMAC-SHA256 *signature* of *body*
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> Result<()> {
+    let mut decoded = [0u8; 32];
+    let decoded = base16ct::mixed::decode(signature, &mut decoded)
+        .map_err(|_| ServerError::InvalidSignature)?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac.verify_slice(decoded)
+        .map_err(|_| ServerError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn peers() -> ProbePeers {
+        ProbePeers(HashMap::from([(
+            String::from("peer-a"),
+            String::from("secret"),
+        )]))
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        let mut buf = [0u8; 64];
+        base16ct::lower::encode_str(&mac.finalize().into_bytes(), &mut buf)
+            .unwrap()
+            .to_owned()
+    }
+
+    fn report_headers(peer: &str, signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(PEER_HEADER, peer.parse().unwrap());
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    fn body(time: i64) -> Vec<u8> {
+        format!(r#"{{"time":{time},"results":[{{"domain":"nitter.example.com","healthy":true}}]}}"#)
+            .into_bytes()
+    }
+
+    #[test]
+    fn valid_report() {
+        let body = body(NOW - 10);
+        let headers = report_headers("peer-a", &sign("secret", &body));
+        let (peer, report) = read_report(&peers(), &headers, &body, NOW).unwrap();
+        assert_eq!(peer, "peer-a");
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(report.results[0].domain, "nitter.example.com");
+        assert!(report.results[0].healthy);
+        // hex case doesn't matter
+        let headers = report_headers("peer-a", &sign("secret", &body).to_uppercase());
+        assert!(read_report(&peers(), &headers, &body, NOW).is_ok());
+    }
+
+    #[test]
+    fn wrong_secret() {
+        let body = body(NOW);
+        let headers = report_headers("peer-a", &sign("other", &body));
+        assert!(matches!(
+            read_report(&peers(), &headers, &body, NOW),
+            Err(ServerError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn malformed_signature() {
+        let body = body(NOW);
+        for signature in ["not hex", "abc", &sign("secret", &body)[..62]] {
+            let headers = report_headers("peer-a", signature);
+            assert!(
+                matches!(
+                    read_report(&peers(), &headers, &body, NOW),
+                    Err(ServerError::InvalidSignature)
+                ),
+                "{signature}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_peer() {
+        let body = body(NOW);
+        let headers = report_headers("peer-b", &sign("secret", &body));
+        assert!(matches!(
+            read_report(&peers(), &headers, &body, NOW),
+            Err(ServerError::InvalidSignature)
+        ));
+        // missing headers
+        assert!(matches!(
+            read_report(&peers(), &HeaderMap::new(), &body, NOW),
+            Err(ServerError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn replayed_report() {
+        let state = entities::state::new();
+        let now = Utc.timestamp_opt(NOW, 0).unwrap();
+        let insert = |time: i64| {
+            let body = body(time);
+            let headers = report_headers("peer-a", &sign("secret", &body));
+            let (peer, report) = read_report(&peers(), &headers, &body, NOW).unwrap();
+            state.insert_probe(
+                String::from("nitter.example.com"),
+                PeerProbe {
+                    peer: peer.to_owned(),
+                    healthy: false,
+                    time: report_time(&report, now),
+                },
+            );
+        };
+        insert(NOW - 100);
+        // replays keep the original report time
+        insert(NOW - 100);
+        insert(NOW - 200);
+        let reports = state.probe_reports.read().unwrap();
+        let probes = &reports["nitter.example.com"];
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].time.timestamp(), NOW - 100);
+        drop(reports);
+        // reports ahead of the server clock are capped at its time
+        insert(NOW + 100);
+        assert_eq!(
+            state.probe_reports.read().unwrap()["nitter.example.com"][0]
+                .time
+                .timestamp(),
+            NOW
+        );
+    }
+
+    #[test]
+    fn expired_report() {
+        for time in [NOW - MAX_REPORT_AGE_S - 1, NOW + MAX_REPORT_AGE_S + 1] {
+            let body = body(time);
+            let headers = report_headers("peer-a", &sign("secret", &body));
+            assert!(matches!(
+                read_report(&peers(), &headers, &body, NOW),
+                Err(ServerError::InvalidProbeReport)
+            ));
+        }
+        let body = body(NOW - MAX_REPORT_AGE_S);
+        let headers = report_headers("peer-a", &sign("secret", &body));
+        assert!(read_report(&peers(), &headers, &body, NOW).is_ok());
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+//! Rate limits keyed on the client IP, resolved behind trusted reverse proxies
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, Request},
+};
+use tower_governor::{errors::GovernorError, key_extractor::KeyExtractor};
+
+/// Requests replenished per *period*, allowing bursts of up to *burst* requests
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub period: Duration,
+    pub burst: u32,
+}
+
+/// Client IP, taken from `X-Forwarded-For` only for requests of a trusted proxy
+#[derive(Debug, Clone)]
+pub(crate) struct ClientIpKeyExtractor {
+    trusted_proxies: Arc<Vec<IpAddr>>,
+}
+
+impl ClientIpKeyExtractor {
+    pub fn new(trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            trusted_proxies: Arc::new(trusted_proxies),
+        }
+    }
+
+    /// Last address in front of our trusted proxies, they may be chained
+    fn forwarded_client(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect::<Vec<_>>();
+        forwarded
+            .into_iter()
+            .rev()
+            .map(|v| v.trim().parse::<IpAddr>().ok())
+            .find(|ip| ip.is_none_or(|ip| !self.trusted_proxies.contains(&ip)))
+            .flatten()
+    }
+}
+
+impl KeyExtractor for ClientIpKeyExtractor {
+    type Key = IpAddr;
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .ok_or(GovernorError::UnableToExtractKey)?;
+        if !self.trusted_proxies.contains(&peer) {
+            return Ok(peer);
+        }
+        Ok(self.forwarded_client(req.headers()).unwrap_or(peer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn extractor() -> ClientIpKeyExtractor {
+        ClientIpKeyExtractor::new(vec![PROXY.parse().unwrap(), "10.0.0.2".parse().unwrap()])
+    }
+
+    /// Client key of a request from *peer* with the `X-Forwarded-For` headers *forwarded*
+    fn client(peer: &str, forwarded: &[&str]) -> IpAddr {
+        let mut req = Request::builder();
+        for value in forwarded {
+            req = req.header("x-forwarded-for", *value);
+        }
+        let mut req = req.body(()).unwrap();
+        let peer = SocketAddr::new(peer.parse().unwrap(), 1234);
+        req.extensions_mut().insert(ConnectInfo(peer));
+        extractor().extract(&req).unwrap()
+    }
+
+    fn ip(v: &str) -> IpAddr {
+        v.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        // spoofed header of a direct client is ignored
+        assert_eq!(client("1.2.3.4", &["5.6.7.8"]), ip("1.2.3.4"));
+        assert_eq!(client("1.2.3.4", &[]), ip("1.2.3.4"));
+    }
+
+    #[test]
+    fn trusted_proxy() {
+        assert_eq!(client(PROXY, &["1.2.3.4"]), ip("1.2.3.4"));
+        // only entries added by our proxies are trusted
+        assert_eq!(client(PROXY, &["5.6.7.8, 1.2.3.4"]), ip("1.2.3.4"));
+        assert_eq!(client(PROXY, &[]), ip(PROXY));
+    }
+
+    #[test]
+    fn chained_proxies() {
+        assert_eq!(
+            client(PROXY, &["5.6.7.8, 1.2.3.4, 10.0.0.2"]),
+            ip("1.2.3.4")
+        );
+        // all entries trusted
+        assert_eq!(client(PROXY, &["10.0.0.2"]), ip(PROXY));
+    }
+
+    #[test]
+    fn invalid_entry() {
+        // unparsable entries in front of our proxies fall back to the peer
+        assert_eq!(client(PROXY, &["1.2.3.4, unknown"]), ip(PROXY));
+        assert_eq!(client(PROXY, &["unknown, 1.2.3.4"]), ip("1.2.3.4"));
+    }
+
+    #[test]
+    fn multiple_headers() {
+        assert_eq!(
+            client(PROXY, &["5.6.7.8", "1.2.3.4, 10.0.0.2"]),
+            ip("1.2.3.4")
+        );
+        assert_eq!(client(PROXY, &["1.2.3.4", "10.0.0.2"]), ip("1.2.3.4"));
+    }
+}
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+//! Alternative orderings of the cached instance list
+use std::cmp::Ordering;
+
+use entities::state::{CacheHost, VersionState};
+use serde::Deserialize;
+
+/// `?sort=` query parameter
+#[derive(Deserialize, Default)]
+pub struct SortQuery {
+    sort: Option<String>,
+}
+
+/// Sort order for the public instance list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    /// Ranking computed by the scanner
+    #[default]
+    Points,
+    /// Highest overall uptime first
+    Uptime,
+    /// Lowest average ping first
+    Ping,
+    /// Latest upstream version first
+    Version,
+}
+
+impl SortQuery {
+    /// Requested sort key, falling back to [SortKey::Points] for unknown values
+    pub fn key(&self) -> SortKey {
+        match self.sort.as_deref().map(str::trim) {
+            None | Some("") | Some("points") => SortKey::Points,
+            Some("uptime") => SortKey::Uptime,
+            Some("ping") => SortKey::Ping,
+            Some("version") => SortKey::Version,
+            Some(other) => {
+                tracing::debug!(sort = other, "unknown sort key, using points");
+                SortKey::Points
+            }
+        }
+    }
+}
+
+impl SortKey {
+    /// Order hosts by this key. Stable, ties keep the ranking order of the cache.
+    ///
+    /// Featured hosts stay in front of all others for every key.
+    pub fn sort(self, hosts: &[CacheHost]) -> Vec<&CacheHost> {
+        let mut hosts: Vec<_> = hosts.iter().collect();
+        match self {
+            SortKey::Points => (),
+            SortKey::Uptime => hosts.sort_by(|a, b| {
+                b.healthy_percentage_overall
+                    .cmp(&a.healthy_percentage_overall)
+            }),
+            SortKey::Ping => hosts.sort_by(|a, b| match (a.ping_avg, b.ping_avg) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }),
+            SortKey::Version => hosts.sort_by_key(|host| version_rank(host.version_state)),
+        }
+        hosts.sort_by_key(|host| !host.is_featured);
+        hosts
+    }
+}
+
+fn version_rank(state: VersionState) -> u8 {
+    match state {
+        VersionState::Latest => 0,
+        VersionState::Outdated => 1,
+        VersionState::Foreign => 2,
+        VersionState::Missing => 3,
+    }
+}
@@ -1,29 +1,144 @@
 // SPDX-License-Identifier: AGPL-3.0-only
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use crate::sorting::SortQuery;
 use crate::Result;
 use crate::ServerError;
+use axum::headers::Cookie;
 use axum::response::IntoResponse;
-use axum::{extract::State, response::Html};
+use axum::response::Redirect;
+use axum::TypedHeader;
+use axum::{
+    extract::{Query, State},
+    response::Html,
+    Form,
+};
 use entities::state::scanner::ScannerConfig;
 use entities::state::AppState;
-use hyper::http::HeaderValue;
+use entities::state::CacheHost;
+use hyper::http::{header, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+
+/// Cookie storing the [Theme] preference, independent of the admin session
+const THEME_COOKIE: &str = "theme";
+/// Lifetime of the theme cookie
+const THEME_COOKIE_MAX_AGE_S: u64 = 365 * 24 * 60 * 60;
+
+/// Color theme of the public pages
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    /// Follow the browser preference
+    Auto,
+}
+
+impl Theme {
+    fn as_str(self) -> &'static str {
+        match self {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+            Theme::Auto => "auto",
+        }
+    }
+
+    fn from_cookie(cookies: Option<&Cookie>) -> Self {
+        match cookies.and_then(|v| v.get(THEME_COOKIE)) {
+            Some("light") => Theme::Light,
+            Some("auto") => Theme::Auto,
+            _ => Theme::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ThemeInput {
+    theme: Theme,
+    /// Page to return to, only local paths
+    back: Option<String>,
+}
+
+/// Store the theme preference and return to the previous page
+pub async fn set_theme(Form(input): Form<ThemeInput>) -> axum::response::Response {
+    let back = input
+        .back
+        .filter(|v| is_local_path(v))
+        .unwrap_or_else(|| String::from("/"));
+    let cookie = format!(
+        "{THEME_COOKIE}={}; Path=/; Max-Age={THEME_COOKIE_MAX_AGE_S}; SameSite=Lax; HttpOnly",
+        input.theme.as_str()
+    );
+    let mut res = Redirect::to(&back).into_response();
+    *res.status_mut() = StatusCode::SEE_OTHER;
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        res.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+    res
+}
+
+/// Whether *path* is a local path without authority, safe for redirecting to
+///
+/// Browsers treat `\` like `/` and strip tabs and newlines, so `/\host` and `/\t/host` would leave the site.
+fn is_local_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.contains(|c: char| c.is_control())
+}
+
+/// Public page caching, varying by the theme cookie
+fn insert_cache_headers(res: &mut axum::response::Response, max_age: impl std::fmt::Display) {
+    res.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&format!("public, max-age={max_age}")).unwrap(),
+    );
+    res.headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Cookie"));
+}
+
+/// Host data for templating, including display-only fields
+#[derive(Serialize)]
+struct HostView<'a> {
+    #[serde(flatten)]
+    host: &'a CacheHost,
+    show_last_seen: bool,
+    /// Health is unknown, as the data is stale
+    unknown: bool,
+}
 
 pub async fn instances(
     State(ref app_state): State<AppState>,
     State(ref template): State<Arc<tera::Tera>>,
     State(ref config): State<Arc<crate::Config>>,
+    Query(sort): Query<SortQuery>,
+    cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<axum::response::Response> {
     let mut context = tera::Context::new();
+    context.insert("theme", &Theme::from_cookie(cookies.as_ref().map(|v| &v.0)));
     let mut res = {
         let guard = app_state
             .cache
             .read()
             .map_err(|_| ServerError::MutexFailure)?;
-        context.insert("instances", &guard.hosts);
+        let stale = config.is_stale(guard.last_update);
+        let instances: Vec<_> = sort
+            .key()
+            .sort(&guard.hosts)
+            .into_iter()
+            .map(|host| HostView {
+                host,
+                show_last_seen: host.__show_last_seen,
+                unknown: stale,
+            })
+            .collect();
+        context.insert("instances", &instances);
         let time = guard.last_update.format("%Y.%m.%d %H:%M").to_string();
         context.insert("last_updated", &time);
+        context.insert("stale", &stale);
         let start = Instant::now();
         let res = Html(template.render("instances.html.j2", &context)?).into_response();
         let end = Instant::now();
@@ -32,25 +147,32 @@ pub async fn instances(
         tracing::trace!(templating_time = templating_time.as_millis());
         res
     };
-    res.headers_mut().insert(
-        "cache-control",
-        HeaderValue::from_str(&format!("public, max-age={}", config.max_age)).unwrap(),
-    );
+    insert_cache_headers(&mut res, config.page_max_age);
     Ok(res)
 }
 
+/// Paths the scanner requests on every instance
+pub(crate) fn checked_paths(scanner_config: &ScannerConfig) -> Vec<&str> {
+    let mut paths = vec![
+        scanner_config.about_path.as_str(),
+        scanner_config.rss_path.as_str(),
+    ];
+    paths.extend(scanner_config.profile_paths());
+    paths.push(&scanner_config.connectivity_path);
+    paths
+}
+
 pub async fn about(
     State(ref app_state): State<AppState>,
     State(ref template): State<Arc<tera::Tera>>,
     State(ref scanner_config): State<ScannerConfig>,
+    State(ref config): State<Arc<crate::Config>>,
+    cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<axum::response::Response> {
     let mut context = tera::Context::new();
-    let mut paths = Vec::with_capacity(5);
-    paths.push(&scanner_config.about_path);
-    paths.push(&scanner_config.rss_path);
-    paths.push(&scanner_config.profile_path);
-    paths.push(&scanner_config.connectivity_path);
-    context.insert("checked_paths", &paths);
+    context.insert("theme", &Theme::from_cookie(cookies.as_ref().map(|v| &v.0)));
+    context.insert("checked_paths", &checked_paths(scanner_config));
+    context.insert("about_extra_html", &config.about_extra_html);
     context.insert(
         "uptime_interval_s",
         &scanner_config.instance_check_interval.as_secs(),
@@ -63,6 +185,10 @@ pub async fn about(
         "ping_avg_interval_h",
         &scanner_config.ping_range.num_hours(),
     );
+    context.insert(
+        "unhealthy_after_failures",
+        &scanner_config.unhealthy_after_failures,
+    );
     {
         let guard = app_state
             .cache
@@ -70,11 +196,86 @@ pub async fn about(
             .map_err(|_| ServerError::MutexFailure)?;
         context.insert("latest_commit", &guard.latest_commit);
     }
+    context.insert("scanner_ips", &config.scanner_ips);
 
     let mut res = Html(template.render("about.html.j2", &context)?).into_response();
-    res.headers_mut().insert(
-        "cache-control",
-        HeaderValue::from_static("public, max-age=900"),
-    );
+    insert_cache_headers(&mut res, config.about_max_age);
     Ok(res)
 }
+
+/// Resolve the outbound IPv4 and IPv6 address via an echo service at *url*.
+///
+/// Address families without connectivity are skipped.
+/// With a *proxy* only its single outbound address is resolved.
+pub(crate) async fn resolve_outbound_ips(url: &str, proxy: Option<&str>) -> Vec<String> {
+    let mut ips = Vec::with_capacity(2);
+    let locals = match proxy {
+        Some(_) => vec![None],
+        None => vec![
+            Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        ],
+    };
+    for local in locals {
+        let mut builder = reqwest::Client::builder().local_address(local);
+        if let Some(proxy) = proxy {
+            match reqwest::Proxy::all(proxy) {
+                Ok(proxy) => builder = builder.proxy(proxy),
+                Err(e) => {
+                    tracing::warn!(error=?e, "invalid HTTP proxy for IP echo");
+                    break;
+                }
+            }
+        }
+        let client = match builder
+            .use_rustls_tls()
+            .timeout(Duration::from_secs(10))
+            .build()
+        {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!(error=?e, "failed to build IP echo client");
+                continue;
+            }
+        };
+        let body: reqwest::Result<String> = async {
+            client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await;
+        match body.map(|v| v.trim().parse::<IpAddr>()) {
+            Ok(Ok(ip)) => ips.push(ip.to_string()),
+            Ok(Err(e)) => tracing::warn!(url, error=?e, "IP echo didn't return an IP"),
+            Err(e) => tracing::debug!(url, ?local, error=?e, "failed to resolve outbound IP"),
+        }
+    }
+    tracing::info!(ips=?ips, "resolved outbound IPs");
+    ips
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_paths() {
+        assert!(is_local_path("/"));
+        assert!(is_local_path("/about?page=2"));
+        for path in [
+            "",
+            "https://example.com",
+            "//example.com",
+            "/\\example.com",
+            "\\/example.com",
+            "/\t/example.com",
+            "/\n/example.com",
+        ] {
+            assert!(!is_local_path(path), "{path:?}");
+        }
+    }
+}
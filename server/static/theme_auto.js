@@ -0,0 +1,3 @@
+// Follow the browser color scheme, loaded in the head to avoid flashing the wrong theme
+document.documentElement.setAttribute('data-bs-theme',
+    window.matchMedia('(prefers-color-scheme: light)').matches ? 'light' : 'dark');
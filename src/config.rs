@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+//! Config loading from the environment and an optional TOML file
+use std::{
+    env::{var, VarError},
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+};
+
+use miette::{Context, IntoDiagnostic};
+
+/// Load a flat TOML config file, keys are named like the environment variables.
+///
+/// Values are exported into the environment, unless the variable is already set.
+/// Thus the environment and `.env` always take precedence over the file.
+/// Arrays are joined with `,` or the separator of the key, same as the list format of the environment.
+pub fn load_file(path: &Path) -> miette::Result<()> {
+    let content = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+    let table: toml::value::Table = toml::from_str(&content)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))?;
+
+    let mut errors = Vec::new();
+    let mut values = Vec::with_capacity(table.len());
+    for (key, value) in table {
+        match value_to_env(&value, list_separator(&key)) {
+            Some(v) => values.push((key, v)),
+            None => errors.push(format!(
+                "`{key}`: unsupported value type {}, expected a string, number, boolean or list",
+                value.type_str()
+            )),
+        }
+    }
+    if !errors.is_empty() {
+        miette::bail!(
+            "Invalid config file {}:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        );
+    }
+    for (key, value) in values {
+        if var(&key).is_err() {
+            std::env::set_var(key, value);
+        }
+    }
+    Ok(())
+}
+
+/// Lists with a separator other than `,`, as their entries can contain commas
+const LIST_SEPARATORS: [(&str, char); 1] = [("PROBE_USER_AGENTS", '|')];
+
+/// Separator for list values of *key*
+fn list_separator(key: &str) -> char {
+    LIST_SEPARATORS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map_or(',', |(_, separator)| *separator)
+}
+
+fn value_to_env(value: &toml::Value, separator: char) -> Option<String> {
+    match value {
+        toml::Value::String(v) => Some(v.clone()),
+        toml::Value::Integer(v) => Some(v.to_string()),
+        toml::Value::Float(v) => Some(v.to_string()),
+        toml::Value::Boolean(v) => Some(v.to_string()),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|v| match v {
+                toml::Value::Array(_) | toml::Value::Table(_) => None,
+                v => value_to_env(v, separator),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|v| v.join(&separator.to_string())),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// Config value reader, collecting all errors instead of failing on the first one.
+///
+/// Readers return a default value on error, call [Env::finish] to get the report.
+#[derive(Default)]
+pub struct Env {
+    errors: Vec<String>,
+}
+
+impl Env {
+    /// Record a validation error
+    pub fn error(&mut self, error: impl Display) {
+        let error = error.to_string();
+        // values can be read by multiple configs
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    /// Required value
+    pub fn require(&mut self, name: &str) -> String {
+        match var(name) {
+            Ok(v) => v,
+            Err(e) => {
+                self.missing(name, e);
+                String::new()
+            }
+        }
+    }
+
+    fn missing(&mut self, name: &str, e: VarError) {
+        match e {
+            VarError::NotPresent => self.error(format_args!(
+                "missing `{name}` in environment or config file"
+            )),
+            VarError::NotUnicode(_) => self.error(format_args!("`{name}` is not valid unicode")),
+        }
+    }
+
+    /// Optional value, empty values are treated as unset
+    pub fn optional(&self, name: &str) -> Option<String> {
+        var(name).ok().filter(|v| !v.trim().is_empty())
+    }
+
+    /// Optional flag, only set for `true`
+    pub fn flag(&self, name: &str) -> bool {
+        self.optional(name).is_some_and(|v| v == "true")
+    }
+
+    /// Required number
+    pub fn require_number<T: FromStr + Default>(&mut self, name: &str) -> T {
+        match var(name) {
+            Ok(v) => self.parse_number(name, &v).unwrap_or_default(),
+            Err(e) => {
+                self.missing(name, e);
+                T::default()
+            }
+        }
+    }
+
+    /// Optional number, empty values are treated as unset
+    pub fn optional_number<T: FromStr>(&mut self, name: &str) -> Option<T> {
+        let value = self.optional(name)?;
+        self.parse_number(name, &value)
+    }
+
+    fn parse_number<T: FromStr>(&mut self, name: &str, value: &str) -> Option<T> {
+        match value.trim().parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                self.error(format_args!("`{name}` must be a positive number"));
+                None
+            }
+        }
+    }
+
+    /// Optional comma separated list, empty entries are skipped
+    ///
+    /// Keys in [LIST_SEPARATORS] use their own separator.
+    pub fn list(&self, name: &str) -> Vec<String> {
+        self.optional(name)
+            .map(|v| split_list(&v, list_separator(name)))
+            .unwrap_or_default()
+    }
+
+    /// Required comma separated list, empty entries are skipped
+    pub fn require_list(&mut self, name: &str) -> Vec<String> {
+        split_list(&self.require(name), list_separator(name))
+    }
+
+    /// Parse every entry of a list, recording invalid ones
+    pub fn parse_list<T>(&mut self, name: &str, entries: Vec<String>) -> Vec<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        entries
+            .into_iter()
+            .filter_map(|v| match v.parse() {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    self.error(format_args!("invalid `{name}`: {e}"));
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Fail with all recorded errors, if any
+    pub fn finish(self) -> miette::Result<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        miette::bail!("Invalid configuration:\n  {}", self.errors.join("\n  "))
+    }
+}
+
+fn split_list(value: &str, separator: char) -> Vec<String> {
+    value
+        .split(separator)
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Write *content* to a config file unique to *name*
+    fn config_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("nitter-status-{name}-{}.toml", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn env_value(content: &str, key: &str) -> Option<String> {
+        let table: toml::value::Table = toml::from_str(content).unwrap();
+        value_to_env(&table[key], list_separator(key))
+    }
+
+    #[test]
+    fn value_to_env_scalars() {
+        assert_eq!(env_value(r#"A = "text""#, "A").as_deref(), Some("text"));
+        assert_eq!(env_value("A = 42", "A").as_deref(), Some("42"));
+        assert_eq!(env_value("A = 1.5", "A").as_deref(), Some("1.5"));
+        assert_eq!(env_value("A = true", "A").as_deref(), Some("true"));
+        assert_eq!(env_value("A = false", "A").as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn value_to_env_arrays() {
+        assert_eq!(
+            env_value(r#"A = ["https://a", "https://b", 3]"#, "A").as_deref(),
+            Some("https://a,https://b,3")
+        );
+        assert_eq!(env_value("A = []", "A").as_deref(), Some(""));
+        // user agents contain commas
+        assert_eq!(
+            env_value(
+                r#"PROBE_USER_AGENTS = ["Mozilla/5.0 (X11; Linux x86_64)", "agent, two"]"#,
+                "PROBE_USER_AGENTS"
+            )
+            .as_deref(),
+            Some("Mozilla/5.0 (X11; Linux x86_64)|agent, two")
+        );
+    }
+
+    #[test]
+    fn value_to_env_unsupported() {
+        assert_eq!(env_value("[A]\nB = 1", "A"), None);
+        assert_eq!(env_value("A = 2023-11-14T22:13:20Z", "A"), None);
+        assert_eq!(env_value("A = [[1, 2]]", "A"), None);
+        assert_eq!(env_value("A = [{ B = 1 }]", "A"), None);
+    }
+
+    #[test]
+    fn load_file_collects_errors() {
+        let path = config_file(
+            "invalid",
+            "NS_TEST_INVALID_OK = 1\nNS_TEST_INVALID_DATE = 2023-11-14\n[NS_TEST_INVALID_TABLE]\nB = 1\n",
+        );
+        let error = load_file(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("`NS_TEST_INVALID_DATE`"), "{error}");
+        assert!(error.contains("`NS_TEST_INVALID_TABLE`"), "{error}");
+        assert!(!error.contains("NS_TEST_INVALID_OK"), "{error}");
+        // nothing is exported from an invalid file
+        assert!(var("NS_TEST_INVALID_OK").is_err());
+    }
+
+    #[test]
+    fn load_file_environment_precedence() {
+        std::env::set_var("NS_TEST_PRECEDENCE_SET", "env");
+        let path = config_file(
+            "precedence",
+            "NS_TEST_PRECEDENCE_SET = \"file\"\nNS_TEST_PRECEDENCE_UNSET = [\"a\", \"b\"]\n",
+        );
+        load_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(var("NS_TEST_PRECEDENCE_SET").unwrap(), "env");
+        assert_eq!(var("NS_TEST_PRECEDENCE_UNSET").unwrap(), "a,b");
+    }
+
+    #[test]
+    fn env_finish_reports_all() {
+        std::env::set_var("NS_TEST_FINISH_NUMBER", "-1");
+        std::env::set_var("NS_TEST_FINISH_LIST", "1,x");
+        let mut env = Env::default();
+        assert_eq!(env.require("NS_TEST_FINISH_MISSING"), "");
+        assert_eq!(env.require_number::<u32>("NS_TEST_FINISH_NUMBER"), 0);
+        let entries = env.list("NS_TEST_FINISH_LIST");
+        assert_eq!(env.parse_list::<u32>("NS_TEST_FINISH_LIST", entries), [1]);
+        // reported once
+        env.require("NS_TEST_FINISH_MISSING");
+        let error = env.finish().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Invalid configuration:\n  \
+             missing `NS_TEST_FINISH_MISSING` in environment or config file\n  \
+             `NS_TEST_FINISH_NUMBER` must be a positive number\n  \
+             invalid `NS_TEST_FINISH_LIST`: invalid digit found in string"
+        );
+        assert!(Env::default().finish().is_ok());
+    }
+}
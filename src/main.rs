@@ -1,15 +1,21 @@
 // SPDX-License-Identifier: AGPL-3.0-only
-use std::{env::var, time::Duration};
+use std::{env::var, path::PathBuf, time::Duration};
 
-use entities::state::scanner::ScannerConfig;
+use clap::{Arg, Command};
+use config::Env;
+use entities::state::scanner::{HealthSignal, ScannerConfig};
 use miette::{Context, IntoDiagnostic};
 use migration::MigratorTrait;
-use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseBackend, DatabaseConnection};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, SqlxSqliteConnector};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
 use std::sync::Arc;
 
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod config;
+
 fn main() -> miette::Result<()> {
+    let args = cli().get_matches();
     #[cfg(debug_assertions)]
     let build_mode = "debug mode";
     #[cfg(not(debug_assertions))]
@@ -20,9 +26,25 @@ fn main() -> miette::Result<()> {
         env!("CARGO_PKG_VERSION"),
         env!("CARGO_PKG_LICENSE")
     );
-    dotenvy::dotenv()
-        .into_diagnostic()
-        .wrap_err_with(|| "Failed to load .env file!")?;
+    let config_file = args.get_one::<PathBuf>("config").cloned();
+    match dotenvy::dotenv() {
+        Ok(_) => (),
+        // a config file replaces the .env file
+        Err(e) if e.not_found() && (config_file.is_some() || var("CONFIG_FILE").is_ok()) => (),
+        Err(e) => Err(e)
+            .into_diagnostic()
+            .wrap_err_with(|| "Failed to load .env file!")?,
+    }
+    // CONFIG_FILE may also be set inside .env
+    let config_file = config_file.or_else(|| {
+        var("CONFIG_FILE")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .map(PathBuf::from)
+    });
+    if let Some(path) = config_file {
+        config::load_file(&path)?;
+    }
 
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -31,46 +53,74 @@ fn main() -> miette::Result<()> {
         .into_diagnostic()
         .wrap_err_with(|| "Failed to initialize async runtime!")?;
 
-    rt.block_on(_main())
+    match args.subcommand() {
+        Some(("check", check_args)) => {
+            let url = check_args
+                .get_one::<String>("url")
+                .expect("url is a required argument");
+            rt.block_on(check_main(url))
+        }
+        _ => rt.block_on(_main()),
+    }
 }
 
-async fn _main() -> miette::Result<()> {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            var("RUST_LOG").unwrap_or_else(|_| {
-                #[cfg(debug_assertions)]
-                return format!(
-                    "warn,tower_http=debug,migration=debug,scanner=trace,server=debug,{}=debug",
-                    env!("CARGO_PKG_NAME")
-                )
-                .into();
-                #[cfg(not(debug_assertions))]
-                return format!(
-                    "warn,tower_http=debug,migration=debug,scanner=info,server=info,{}=debug",
-                    env!("CARGO_PKG_NAME")
-                )
-                .into();
-            }),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+fn cli() -> Command {
+    Command::new(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("TOML config file, overridden by environment variables. Same as CONFIG_FILE"),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check a single instance without starting the server or using the database")
+                .arg(
+                    Arg::new("url")
+                        .required(true)
+                        .help("Instance URL, e.g. https://nitter.net"),
+                ),
+        )
+}
 
-    tracing::debug!("connecting to database");
-    let dburl = require_env_str("DATABASE_URL")?;
-    let mut db_opts = ConnectOptions::new(dburl);
-    db_opts.connect_timeout(Duration::from_secs(2));
-    let pool = Database::connect(db_opts)
+/// Check a single instance URL and print the results
+async fn check_main(url: &str) -> miette::Result<()> {
+    init_tracing();
+    let mut env = Env::default();
+    let scanner_config = read_scanner_cfg(&mut env);
+    env.finish()?;
+    let report = scanner::check_instance(scanner_config, url)
         .await
-        .into_diagnostic()
-        .wrap_err("Failed connecting to database")?;
+        .wrap_err("Failed to setup instance check")?;
+    println!("{report}");
+    Ok(())
+}
+
+async fn _main() -> miette::Result<()> {
+    init_tracing();
+
+    let mut env = Env::default();
+    let dburl = env.require("DATABASE_URL");
+    let sqlite_tuning = read_sqlite_tuning(&mut env);
+    let port: u16 = env.require_number("PORT");
+    let disable_health_checks = env.require("DISABLE_HEALTH_CHECKS") == "true";
+    let read_url = env.optional("DATABASE_READ_URL");
 
-    let port: u16 = require_env_str("PORT")?
-        .parse()
-        .expect("PORT must be a number");
+    let scanner_config = read_scanner_cfg(&mut env);
 
-    let scanner_config = read_scanner_cfg()?;
+    let server_config = read_server_config(
+        &mut env,
+        scanner_config.instance_check_interval.as_secs() as _,
+        &sqlite_tuning,
+    );
+    env.finish()?;
 
-    let server_config = read_server_config(scanner_config.instance_check_interval.as_secs() as _)?;
+    tracing::debug!("connecting to database");
+    let pool = connect_sqlite(&dburl, &sqlite_tuning, true)
+        .await
+        .wrap_err("Failed connecting to database")?;
 
     test_init(&pool).await?;
 
@@ -80,9 +130,18 @@ async fn _main() -> miette::Result<()> {
         .into_diagnostic()
         .wrap_err_with(|| "Failed to perform database migration!")?;
 
-    let cache = entities::state::new();
+    let read_pool = match read_url {
+        None => pool.clone(),
+        Some(read_url) => {
+            tracing::debug!("connecting to read-only database");
+            // journal mode is persisted by the writing connection, can't be changed read-only
+            connect_sqlite(&read_url, &sqlite_tuning, false)
+                .await
+                .wrap_err("Failed connecting to read-only database")?
+        }
+    };
 
-    let disable_health_checks = require_env_str("DISABLE_HEALTH_CHECKS")? == "true";
+    let cache = entities::state::new();
 
     scanner::run_scanner(
         pool.clone(),
@@ -94,7 +153,7 @@ async fn _main() -> miette::Result<()> {
     .wrap_err("Crash starting background scanner")?;
 
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
-    server::start(&addr, pool, server_config, scanner_config, cache)
+    server::start(&addr, pool, read_pool, server_config, scanner_config, cache)
         .await
         .into_diagnostic()?;
 
@@ -103,61 +162,206 @@ async fn _main() -> miette::Result<()> {
     Ok(())
 }
 
-fn read_scanner_cfg() -> miette::Result<ScannerConfig> {
-    let nitter_instancelist: String = require_env_str("NITTER_INSTANCELIST")?;
-    let instance_ping_interval: u64 = require_env_str("INSTANCE_PING_INTERVAL_S")?
-        .parse()
-        .expect("INSTANCE_PING_INTERVAL_S must be a number");
-    let instance_list_interval: u64 = require_env_str("INSTANCE_LIST_INTERVAL_S")?
-        .parse()
-        .expect("INSTANCE_LIST_INTERVAL_S must be a number");
-    let ping_range: u32 = require_env_str("PING_RANGE_H")?
-        .parse()
-        .expect("PING_RANGE_H must be a number");
-
-    let profile_path = require_env_str("PROFILE_PATH")?;
-    let rss_path = require_env_str("RSS_PATH")?;
-    let about_path = require_env_str("ABOUT_PATH")?;
-    let profile_name = require_env_str("PROFILE_NAME")?;
-    let profile_posts_min = require_env_str("PROFILE_POSTS_MIN")?
-        .parse()
-        .expect("PROFILE_POSTS_MIN must be a positive number");
-    let additional_hosts: Vec<String> = require_env_vec_str("ADDITIONAL_HOSTS")?;
-    let additional_host_country = require_env_str("ADDITIONAL_HOSTS_COUNTRY")?;
-    let rss_content = require_env_str("RSS_CONTENT")?;
-    let bad_hosts: Vec<String> = require_env_vec_str("BAD_HOSTS")?;
-    let auto_mute = require_env_str("AUTO_MUTE")? == "true";
-    let source_git_branch = require_env_str("ORIGIN_SOURCE_GIT_BRANCH")?;
-    let source_git_url = require_env_str("ORIGIN_SOURCE_GIT_URL")?;
-    let cleanup_interval: u64 = require_env_str("CLEANUP_INTERVAL_S")?
-        .parse()
-        .expect("CLEANUP_INTERVAL_S must be a number");
-    let error_retention_per_host: usize = require_env_str("ERROR_RETENTION_PER_HOST")?
-        .parse()
-        .expect("CLEANUP_INTERVAL_S must be a number");
-
-    Ok(Arc::new(entities::state::scanner::Config {
+fn init_tracing() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(
+            var("RUST_LOG").unwrap_or_else(|_| {
+                #[cfg(debug_assertions)]
+                return format!(
+                    "warn,tower_http=debug,migration=debug,scanner=trace,server=debug,{}=debug",
+                    env!("CARGO_PKG_NAME")
+                )
+                .into();
+                #[cfg(not(debug_assertions))]
+                return format!(
+                    "warn,tower_http=debug,migration=debug,scanner=info,server=info,{}=debug",
+                    env!("CARGO_PKG_NAME")
+                )
+                .into();
+            }),
+        ))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+}
+
+fn read_scanner_cfg(env: &mut Env) -> ScannerConfig {
+    let nitter_instancelist: String = env.require("NITTER_INSTANCELIST");
+    let instance_ping_interval: u64 = env.require_number("INSTANCE_PING_INTERVAL_S");
+    let instance_list_interval: u64 = env.require_number("INSTANCE_LIST_INTERVAL_S");
+    let ping_range: u32 = env.require_number("PING_RANGE_H");
+    let ping_avg_trim_percent: u8 = env.optional_number("PING_AVG_TRIM_PERCENT").unwrap_or(0);
+    if ping_avg_trim_percent >= 100 {
+        env.error("`PING_AVG_TRIM_PERCENT` must be below 100");
+    }
+    let ping_avg_max_stddev: Option<f64> = env.optional_number("PING_AVG_MAX_STDDEV");
+    if ping_avg_max_stddev.is_some_and(|v| v.is_nan() || v <= 0.0) {
+        env.error("`PING_AVG_MAX_STDDEV` must be a positive number");
+    }
+
+    let profile_path = env.require("PROFILE_PATH");
+    let rss_path = env.require("RSS_PATH");
+    let about_path = env.require("ABOUT_PATH");
+    let profile_name = env.require("PROFILE_NAME");
+    let profile_posts_min = env.require_number("PROFILE_POSTS_MIN");
+    let profile_posts_tolerance = env.optional_number("PROFILE_POSTS_TOLERANCE").unwrap_or(0);
+    let profile_rotation = env.list("PROFILE_ROTATION");
+    let profile_rotation = env.parse_list("PROFILE_ROTATION", profile_rotation);
+    // user agents contain commas, separated by `|` instead
+    let probe_user_agents = env.list("PROBE_USER_AGENTS");
+    let content_unavailable_markers = env.list("CONTENT_UNAVAILABLE_MARKERS");
+    let additional_hosts: Vec<String> = env.require_list("ADDITIONAL_HOSTS");
+    let additional_host_country = env.require("ADDITIONAL_HOSTS_COUNTRY");
+    let rss_content = env.require("RSS_CONTENT");
+    let rss_strict = env.flag("RSS_STRICT");
+    let bad_hosts = env.require_list("BAD_HOSTS");
+    let bad_hosts = env.parse_list("BAD_HOSTS", bad_hosts);
+    let auto_mute = env.require("AUTO_MUTE") == "true";
+    let source_git_branch = env.require("ORIGIN_SOURCE_GIT_BRANCH");
+    let source_git_url = env.require("ORIGIN_SOURCE_GIT_URL");
+    let cleanup_interval: u64 = env.require_number("CLEANUP_INTERVAL_S");
+    let error_retention_per_host: usize = env.require_number("ERROR_RETENTION_PER_HOST");
+    let points_retention_days: u64 = env.optional_number("POINTS_RETENTION_DAYS").unwrap_or(120);
+    let health_precheck = env.flag("HEALTH_PRECHECK");
+    let check_content_type = env.flag("CHECK_CONTENT_TYPE");
+    let dry_run = env.flag("SCANNER_DRY_RUN");
+    let require_https = env.flag("REQUIRE_HTTPS");
+    let exclude_hosts = env.list("EXCLUDE_HOSTS");
+    let exclude_hosts_regex = env.optional("EXCLUDE_HOSTS_REGEX");
+    let healthy_requires = match env.optional("HEALTHY_REQUIRES") {
+        None => vec![HealthSignal::Profile],
+        Some(_) => {
+            let signals = env.list("HEALTHY_REQUIRES");
+            env.parse_list("HEALTHY_REQUIRES", signals)
+        }
+    };
+    let version_pattern = env.optional("REQUIRED_VERSION_REGEX");
+    let cache_file = env.optional("CACHE_FILE").map(PathBuf::from);
+    let rate_limit_threshold = env.optional_number("RATE_LIMIT_THRESHOLD").unwrap_or(3);
+    let ranking_min_checks = env.optional_number("RANKING_MIN_CHECKS").unwrap_or(0);
+    let unhealthy_after_failures = env.optional_number("UNHEALTHY_AFTER_FAILURES").unwrap_or(1);
+    if unhealthy_after_failures == 0 {
+        env.error("`UNHEALTHY_AFTER_FAILURES` must be at least 1");
+    }
+    let http_proxy = env.optional("HTTP_PROXY_URL");
+    let http_proxy_instance_list = env.flag("HTTP_PROXY_INSTANCE_LIST");
+    let instance_list_challenge_solver = env.optional("INSTANCE_LIST_CHALLENGE_SOLVER_URL");
+    let state_webhook_url = env.optional("STATE_WEBHOOK_URL");
+    let instance_table_headers = match env.list("INSTANCE_TABLE_HEADERS") {
+        headers if headers.is_empty() => vec![String::from("Online")],
+        headers => headers,
+    };
+    let instance_table_index = env.optional_number("INSTANCE_TABLE_INDEX");
+    let strip_www = env.flag("STRIP_WWW");
+    let pool_max_idle_per_host = env.optional_number("HTTP_POOL_MAX_IDLE_PER_HOST");
+    let pool_idle_timeout = env
+        .optional_number("HTTP_POOL_IDLE_TIMEOUT_S")
+        .map(Duration::from_secs);
+    let version_check_interval = env
+        .optional_number("VERSION_CHECK_INTERVAL_S")
+        .map(Duration::from_secs);
+    let list_conditional_max_age = env
+        .optional_number("INSTANCE_LIST_CONDITIONAL_MAX_AGE_S")
+        .map(Duration::from_secs);
+
+    let config = Arc::new(entities::state::scanner::Config {
         list_fetch_interval: Duration::from_secs(instance_list_interval),
         instance_check_interval: Duration::from_secs(instance_ping_interval),
         instance_list_url: nitter_instancelist,
+        instance_list_auth: env.optional("INSTANCE_LIST_AUTH"),
         profile_path,
         rss_path,
         about_path,
         profile_name,
         profile_posts_min,
+        profile_posts_tolerance,
+        profile_rotation,
+        probe_user_agents,
+        content_unavailable_markers,
         rss_content,
+        rss_strict,
         additional_hosts,
         additional_host_country,
-        website_url: require_env_str("SITE_URL")?,
+        website_url: env.require("SITE_URL"),
         ping_range: chrono::Duration::hours(ping_range as _),
+        ping_avg_trim_percent,
+        ping_avg_max_stddev,
         auto_mute,
         source_git_branch,
         source_git_url,
         bad_hosts,
         cleanup_interval: Duration::from_secs(cleanup_interval),
         error_retention_per_host,
+        points_retention: Duration::from_secs(points_retention_days * 24 * 60 * 60),
         connectivity_path: String::from("/"),
-    }))
+        health_precheck,
+        check_content_type,
+        dry_run,
+        require_https,
+        list_conditional_max_age,
+        version_check_interval,
+        rate_limit_threshold,
+        ranking_min_checks,
+        unhealthy_after_failures,
+        exclude_hosts,
+        exclude_hosts_regex,
+        cache_file,
+        healthy_requires,
+        version_pattern,
+        http_proxy,
+        http_proxy_instance_list,
+        instance_list_challenge_solver,
+        state_webhook_url,
+        instance_table_headers,
+        instance_table_index,
+        strip_www,
+        pool_max_idle_per_host,
+        pool_idle_timeout,
+    });
+    for error in scanner::validate_config(&config) {
+        env.error(error);
+    }
+    config
+}
+
+/// SQLite connection settings, applied to every pooled connection
+struct SqliteTuning {
+    /// Use WAL journal mode with synchronous=NORMAL
+    wal: bool,
+    busy_timeout: Duration,
+}
+
+fn read_sqlite_tuning(env: &mut Env) -> SqliteTuning {
+    let busy_timeout_ms = env
+        .optional_number("SQLITE_BUSY_TIMEOUT_MS")
+        .unwrap_or(5000);
+    SqliteTuning {
+        wal: env.flag("SQLITE_WAL"),
+        busy_timeout: Duration::from_millis(busy_timeout_ms),
+    }
+}
+
+/// Connect to the sqlite DB at *url*, setting the journal mode only if *set_journal_mode*
+async fn connect_sqlite(
+    url: &str,
+    tuning: &SqliteTuning,
+    set_journal_mode: bool,
+) -> miette::Result<DatabaseConnection> {
+    let mut opts: SqliteConnectOptions = url.parse().into_diagnostic()?;
+    opts = opts.busy_timeout(tuning.busy_timeout);
+    if tuning.wal {
+        if set_journal_mode {
+            opts = opts.journal_mode(SqliteJournalMode::Wal);
+        }
+        opts = opts.synchronous(SqliteSynchronous::Normal);
+    }
+    // single connection, same as the sea-orm default for sqlite
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(Duration::from_secs(2))
+        .connect_with(opts)
+        .await
+        .into_diagnostic()?;
+    Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool))
 }
 
 async fn test_init(db: &DatabaseConnection) -> miette::Result<()> {
@@ -175,36 +379,134 @@ async fn test_init(db: &DatabaseConnection) -> miette::Result<()> {
     Ok(())
 }
 
-fn read_server_config(instance_ping_interval: usize) -> miette::Result<server::Config> {
-    let site_url = require_env_str("SITE_URL")?;
-    let session_ttl_seconds = require_env_str("SESSION_TTL_SECONDS")?
-        .parse()
-        .expect("SESSION_TTL_SECONDS must be a positive number");
-    let login_token_name = require_env_str("LOGIN_TOKEN_NAME")?;
-    let admin_domains = require_env_str("ADMIN_DOMAINS")?
-        .split(",")
-        .map(|v| v.trim().to_string())
+fn read_server_config(
+    env: &mut Env,
+    instance_ping_interval: usize,
+    sqlite_tuning: &SqliteTuning,
+) -> server::Config {
+    let site_url = env.require("SITE_URL");
+    let session_ttl_seconds = env.require_number("SESSION_TTL_SECONDS");
+    let login_token_name = env.require("LOGIN_TOKEN_NAME");
+    let login_token_path = validate_login_token_path(
+        env.optional("LOGIN_TOKEN_PATH")
+            .unwrap_or_else(|| format!(".well-known/{login_token_name}")),
+    )
+    .unwrap_or_else(|e| {
+        env.error(e);
+        String::new()
+    });
+    let login_token_dns_name = validate_login_token_dns_name(
+        env.optional("LOGIN_TOKEN_DNS_NAME")
+            .unwrap_or_else(|| format!("{login_token_name}.{{domain}}")),
+    )
+    .unwrap_or_else(|e| {
+        env.error(e);
+        String::new()
+    });
+    let admin_domains = env.require_list("ADMIN_DOMAINS");
+    let session_db_uri = env.require("SESSION_DB_URI");
+    let session_cleanup_interval = env
+        .optional_number("SESSION_CLEANUP_INTERVAL_S")
+        .unwrap_or(60);
+    let session_db_vacuum_interval = env.optional_number("SESSION_DB_VACUUM_INTERVAL_S");
+    let api_key = env.optional("API_KEY");
+    let probe_peers = env
+        .list("PROBE_PEERS")
+        .into_iter()
+        .filter_map(|v| match v.split_once('=') {
+            Some((name, secret)) => Some((name.trim().to_owned(), secret.trim().to_owned())),
+            None => {
+                env.error("`PROBE_PEERS` entries must be in the format name=secret");
+                None
+            }
+        })
         .collect();
-    let session_db_uri = require_env_str("SESSION_DB_URI")?;
+    let probe_peers = server::ProbePeers(probe_peers);
+    let cors_origins = env.list("CORS_ORIGINS");
+    let scanner_ips = env.list("SCANNER_IPS");
+    let scanner_ip_echo_url = env.optional("SCANNER_IP_ECHO_URL");
+    let login_proxy = env.flag("HTTP_PROXY_LOGIN");
+    let about_extra_html = match env.optional("ABOUT_EXTRA_HTML_FILE") {
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                env.error(format_args!(
+                    "can't read `ABOUT_EXTRA_HTML_FILE` at {path}: {e}"
+                ));
+                None
+            }
+        },
+        None => env.optional("ABOUT_EXTRA_HTML"),
+    };
+    let api_rate_limit_per_minute: u32 = env
+        .optional_number("API_RATE_LIMIT_PER_MINUTE")
+        .unwrap_or(30);
+    let api_rate_limit_burst: u32 = env.optional_number("API_RATE_LIMIT_BURST").unwrap_or(10);
+    if api_rate_limit_per_minute > 0 && api_rate_limit_burst == 0 {
+        env.error("`API_RATE_LIMIT_BURST` must be at least 1");
+    }
+    let api_rate_limit =
+        (api_rate_limit_per_minute > 0 && api_rate_limit_burst > 0).then(|| server::RateLimit {
+            period: Duration::from_secs(60) / api_rate_limit_per_minute,
+            burst: api_rate_limit_burst,
+        });
+    let trusted_proxies = env.list("TRUSTED_PROXIES");
+    let trusted_proxies = env.parse_list("TRUSTED_PROXIES", trusted_proxies);
+    let api_max_age = env.optional_number("API_CACHE_MAX_AGE");
+    let stale_after_s = env.optional_number("STALE_CACHE_AFTER_S");
+    let page_max_age = env.optional_number("PAGE_CACHE_MAX_AGE");
 
-    Ok(server::Config {
+    server::Config {
         site_url,
-        max_age: instance_ping_interval,
+        api_max_age: api_max_age.unwrap_or(instance_ping_interval),
+        page_max_age: page_max_age.unwrap_or(instance_ping_interval),
+        about_max_age: page_max_age.unwrap_or(900),
         session_ttl_seconds,
-        login_token_name,
+        login_token_path,
+        login_token_dns_name,
         admin_domains,
         session_db_uri,
-    })
+        session_db_wal: sqlite_tuning.wal,
+        session_db_busy_timeout: sqlite_tuning.busy_timeout,
+        session_cleanup_interval: Duration::from_secs(session_cleanup_interval),
+        session_db_vacuum_interval: session_db_vacuum_interval.map(Duration::from_secs),
+        api_key,
+        stale_after_s: stale_after_s.unwrap_or(3 * instance_ping_interval as u64),
+        cors_origins,
+        probe_peers,
+        scanner_ips,
+        scanner_ip_echo_url,
+        login_proxy,
+        about_extra_html,
+        api_rate_limit,
+        trusted_proxies,
+    }
 }
 
-fn require_env_vec_str(name: &str) -> miette::Result<Vec<String>> {
-    Ok(require_env_str(name)?
-        .trim()
-        .split(",")
-        .map(|v| v.trim().to_owned())
-        .collect())
+/// Validate the login token path, stripping any leading slash
+fn validate_login_token_path(path: String) -> miette::Result<String> {
+    let path = path.trim().trim_start_matches('/');
+    if path.is_empty()
+        || path.contains(['?', '#', ' '])
+        || path.split('/').any(|segment| segment == "..")
+    {
+        miette::bail!("invalid `LOGIN_TOKEN_PATH`: `{path}` is not a valid URL path");
+    }
+    Ok(path.to_owned())
 }
 
-fn require_env_str(name: &str) -> miette::Result<String> {
-    var(name).map_err(|v| miette::miette!("missing `{}` in environment: {:?}", name, v))
+/// Validate the login token DNS name template, which has to contain `{domain}` exactly once
+fn validate_login_token_dns_name(name: String) -> miette::Result<String> {
+    let name = name.trim().trim_end_matches('.');
+    let Some((prefix, suffix)) = name.split_once("{domain}") else {
+        miette::bail!("invalid `LOGIN_TOKEN_DNS_NAME`: `{name}` doesn't contain `{{domain}}`");
+    };
+    let valid_label = |v: &str| {
+        v.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    };
+    if !valid_label(prefix) || !valid_label(suffix) {
+        miette::bail!("invalid `LOGIN_TOKEN_DNS_NAME`: `{name}` is not a valid DNS name");
+    }
+    Ok(name.to_owned())
 }